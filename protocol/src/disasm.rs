@@ -1,3 +1,6 @@
+//! CHIP-8 instruction decoding, shared by the debugger and the VM's trace recorder.
+
+#[allow(non_camel_case_types)]
 pub enum Opcode {
     OP_00E0,
     OP_00EE,
@@ -31,6 +34,7 @@ pub enum Opcode {
     OP_FX18(u8),
     OP_FX1E(u8),
     OP_FX29(u8),
+    OP_FX30(u8),
     OP_FX33(u8),
     OP_FX55(u8),
     OP_FX65(u8),
@@ -72,6 +76,7 @@ impl Opcode {
             Opcode::OP_FX18(x) => format!("LD ST, V{}", x),
             Opcode::OP_FX1E(x) => format!("ADD I, V{}", x),
             Opcode::OP_FX29(x) => format!("LD F, V{}", x),
+            Opcode::OP_FX30(x) => format!("LD HF, V{}", x),
             Opcode::OP_FX33(x) => format!("LD B, V{}", x),
             Opcode::OP_FX55(x) => format!("LD [I], V{}", x),
             Opcode::OP_FX65(x) => format!("LD V{}, [I]", x),
@@ -97,7 +102,7 @@ impl Disasm {
         opcodes
     }
 
-    fn opcode(opcode: u16) -> Opcode {
+    pub fn opcode(opcode: u16) -> Opcode {
         let op_1 = (opcode & 0xF000) >> 12;
         let op_2 = (opcode & 0x0F00) >> 8;
         let op_3 = (opcode & 0x00F0) >> 4;
@@ -139,7 +144,9 @@ impl Disasm {
             (0xF, _, 0x0, 0xA) => Opcode::OP_FX0A(x),
             (0xF, _, 0x1, 0x5) => Opcode::OP_FX15(x),
             (0xF, _, 0x1, 0x8) => Opcode::OP_FX18(x),
+            (0xF, _, 0x1, 0xE) => Opcode::OP_FX1E(x),
             (0xF, _, 0x2, 0x9) => Opcode::OP_FX29(x),
+            (0xF, _, 0x3, 0x0) => Opcode::OP_FX30(x),
             (0xF, _, 0x3, 0x3) => Opcode::OP_FX33(x),
             (0xf, _, 0x5, 0x5) => Opcode::OP_FX55(x),
             (0xF, _, 0x6, 0x5) => Opcode::OP_FX65(x),
@@ -148,7 +155,7 @@ impl Disasm {
     }
 
     fn read_word(code: &Vec<u8>, location: usize) -> u16 {
-        (code[location] as u16) << 8 | (code[(location + 1)] as u16)
+        (code[location] as u16) << 8 | (code[location + 1] as u16)
     }
 
 }
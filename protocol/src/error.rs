@@ -0,0 +1,46 @@
+use std::fmt;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum DbgError {
+    /// VM and debugger were built with different protocol versions
+    VersionMismatch { server: u32, client: u32 },
+    /// The handshake did not present the token the VM requires
+    Unauthorized,
+    /// A command was sent before the `Hello` handshake succeeded
+    HandshakeRequired,
+    /// The request could not be decoded
+    Malformed(String),
+    /// A command argument is out of range
+    InvalidArgument(String),
+    /// The command is not allowed in the VM's current state, e.g. stepping a running VM
+    InvalidState(String),
+    /// The VM stopped accepting commands
+    VmUnavailable,
+    /// There is no breakpoint or watchpoint with the given id
+    NoSuchBreakpoint(u32),
+    /// No reply arrived in time
+    Timeout,
+    /// The reply does not match the request
+    UnexpectedReply(String),
+    /// Connection failure on the debugger side
+    Io(String)
+}
+
+impl fmt::Display for DbgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DbgError::VersionMismatch { server, client } =>
+                write!(f, "Protocol version mismatch: VM speaks {}, debugger speaks {}", server, client),
+            DbgError::Unauthorized => write!(f, "The VM requires a different debugger token"),
+            DbgError::HandshakeRequired => write!(f, "Handshake required before sending commands"),
+            DbgError::Malformed(ref reason) => write!(f, "Malformed request: {}", reason),
+            DbgError::InvalidArgument(ref reason) => write!(f, "Invalid argument: {}", reason),
+            DbgError::InvalidState(ref reason) => write!(f, "Invalid VM state: {}", reason),
+            DbgError::VmUnavailable => write!(f, "VM is not accepting commands"),
+            DbgError::NoSuchBreakpoint(id) => write!(f, "No breakpoint or watchpoint with id {}", id),
+            DbgError::Timeout => write!(f, "Timed out waiting for a reply"),
+            DbgError::UnexpectedReply(ref reason) => write!(f, "Unexpected reply: {}", reason),
+            DbgError::Io(ref reason) => write!(f, "Connection error: {}", reason)
+        }
+    }
+}
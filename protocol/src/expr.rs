@@ -0,0 +1,311 @@
+//! Expressions over the VM state, used by breakpoint conditions and tracepoint messages.
+//!
+//! The debugger parses them, the VM evaluates them. Values are `u16` with wrapping arithmetic; comparisons and
+//! logical operators yield 1 or 0, and any non-zero value counts as true.
+
+use std::fmt;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Register {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    /// Delay timer
+    Dt,
+    /// Sound timer
+    St
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Not,
+    Neg,
+    BitNot
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Mul,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    BitAnd,
+    BitXor,
+    BitOr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Expr {
+    Const(u16),
+    Reg(Register),
+    /// Byte at the address, `mem[...]`
+    Mem(Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>)
+}
+
+/// Piece of a tracepoint message
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum FormatPart {
+    Text(String),
+    /// Printed as `0x` prefixed hex, or in decimal
+    Value { expr: Expr, decimal: bool }
+}
+
+/// VM state expressions are evaluated against
+pub trait ExprContext {
+    fn register(&self, register: Register) -> u16;
+    fn memory(&self, addr: u16) -> u8;
+}
+
+impl Expr {
+    pub fn eval<C: ExprContext>(&self, context: &C) -> u16 {
+        match *self {
+            Expr::Const(value) => value,
+            Expr::Reg(register) => context.register(register),
+            Expr::Mem(ref addr) => context.memory(addr.eval(context)) as u16,
+            Expr::Unary(op, ref operand) => {
+                let value = operand.eval(context);
+
+                match op {
+                    UnaryOp::Not => (value == 0) as u16,
+                    UnaryOp::Neg => value.wrapping_neg(),
+                    UnaryOp::BitNot => !value
+                }
+            },
+            Expr::Binary(BinaryOp::And, ref left, ref right) => (left.eval(context) != 0 && right.eval(context) != 0) as u16,
+            Expr::Binary(BinaryOp::Or, ref left, ref right) => (left.eval(context) != 0 || right.eval(context) != 0) as u16,
+            Expr::Binary(op, ref left, ref right) => {
+                let (left, right) = (left.eval(context), right.eval(context));
+
+                match op {
+                    BinaryOp::Mul => left.wrapping_mul(right),
+                    BinaryOp::Add => left.wrapping_add(right),
+                    BinaryOp::Sub => left.wrapping_sub(right),
+                    BinaryOp::Shl => left.checked_shl(right as u32).unwrap_or(0),
+                    BinaryOp::Shr => left.checked_shr(right as u32).unwrap_or(0),
+                    BinaryOp::BitAnd => left & right,
+                    BinaryOp::BitXor => left ^ right,
+                    BinaryOp::BitOr => left | right,
+                    BinaryOp::Eq => (left == right) as u16,
+                    BinaryOp::Ne => (left != right) as u16,
+                    BinaryOp::Lt => (left < right) as u16,
+                    BinaryOp::Le => (left <= right) as u16,
+                    BinaryOp::Gt => (left > right) as u16,
+                    BinaryOp::Ge => (left >= right) as u16,
+                    BinaryOp::And | BinaryOp::Or => unreachable!()
+                }
+            }
+        }
+    }
+}
+
+pub fn format_message<C: ExprContext>(parts: &[FormatPart], context: &C) -> String {
+    parts.iter().map(|part| match *part {
+        FormatPart::Text(ref text) => text.clone(),
+        FormatPart::Value { ref expr, decimal: true } => format!("{}", expr.eval(context)),
+        FormatPart::Value { ref expr, decimal: false } => format!("{:#x}", expr.eval(context))
+    }).collect()
+}
+
+impl BinaryOp {
+    pub fn symbol(&self) -> &'static str {
+        match *self {
+            BinaryOp::Mul => "*",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitXor => "^",
+            BinaryOp::BitOr => "|",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||"
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Register::V(x) => write!(f, "v{:x}", x),
+            Register::I => write!(f, "i"),
+            Register::Pc => write!(f, "pc"),
+            Register::Sp => write!(f, "sp"),
+            Register::Dt => write!(f, "dt"),
+            Register::St => write!(f, "st")
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expr::Const(value) => write!(f, "{:#x}", value),
+            Expr::Reg(register) => write!(f, "{}", register),
+            Expr::Mem(ref addr) => write!(f, "mem[{}]", addr),
+            Expr::Unary(op, ref operand) => {
+                let symbol = match op {
+                    UnaryOp::Not => "!",
+                    UnaryOp::Neg => "-",
+                    UnaryOp::BitNot => "~"
+                };
+
+                write!(f, "{}", symbol)?;
+                write_operand(f, operand)
+            },
+            Expr::Binary(op, ref left, ref right) => {
+                write_operand(f, left)?;
+                write!(f, " {} ", op.symbol())?;
+                write_operand(f, right)
+            }
+        }
+    }
+}
+
+/// Nested binary expressions are parenthesized instead of tracking precedence
+fn write_operand(f: &mut fmt::Formatter, operand: &Expr) -> fmt::Result {
+    match *operand {
+        Expr::Binary(..) => write!(f, "({})", operand),
+        _ => write!(f, "{}", operand)
+    }
+}
+
+/// Formats the part the way it is written in a tracepoint message
+impl fmt::Display for FormatPart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FormatPart::Text(ref text) => write!(f, "{}", text.replace('{', "{{").replace('}', "}}")),
+            FormatPart::Value { ref expr, decimal: true } => write!(f, "{{{}:d}}", expr),
+            FormatPart::Value { ref expr, decimal: false } => write!(f, "{{{}}}", expr)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::{format_message, BinaryOp, Expr, ExprContext, FormatPart, Register, UnaryOp};
+
+    /// V registers hold their index, I is 0x300 and memory bytes are the low byte of their address
+    struct Context {
+        memory_reads: Cell<usize>
+    }
+
+    impl ExprContext for Context {
+        fn register(&self, register: Register) -> u16 {
+            match register {
+                Register::V(x) => x as u16,
+                Register::I => 0x300,
+                Register::Pc => 0x2a4,
+                Register::Sp => 2,
+                Register::Dt => 60,
+                Register::St => 0
+            }
+        }
+
+        fn memory(&self, addr: u16) -> u8 {
+            self.memory_reads.set(self.memory_reads.get() + 1);
+            addr as u8
+        }
+    }
+
+    fn context() -> Context {
+        Context { memory_reads: Cell::new(0) }
+    }
+
+    fn constant(value: u16) -> Box<Expr> {
+        Box::new(Expr::Const(value))
+    }
+
+    fn binary(op: BinaryOp, left: u16, right: u16) -> u16 {
+        Expr::Binary(op, constant(left), constant(right)).eval(&context())
+    }
+
+    #[test]
+    fn arithmetic_wraps_around() {
+        assert_eq!(binary(BinaryOp::Add, 0xFFFF, 2), 1);
+        assert_eq!(binary(BinaryOp::Sub, 1, 2), 0xFFFF);
+        assert_eq!(binary(BinaryOp::Mul, 0x100, 0x100), 0);
+        assert_eq!(Expr::Unary(UnaryOp::Neg, constant(1)).eval(&context()), 0xFFFF);
+        assert_eq!(Expr::Unary(UnaryOp::BitNot, constant(0xFF)).eval(&context()), 0xFF00);
+    }
+
+    #[test]
+    fn shifts_by_16_or_more_yield_zero() {
+        assert_eq!(binary(BinaryOp::Shl, 1, 15), 0x8000);
+        assert_eq!(binary(BinaryOp::Shl, 1, 16), 0);
+        assert_eq!(binary(BinaryOp::Shr, 0x8000, 100), 0);
+    }
+
+    #[test]
+    fn comparisons_and_logical_operators_yield_one_or_zero() {
+        assert_eq!(binary(BinaryOp::Lt, 1, 2), 1);
+        assert_eq!(binary(BinaryOp::Ge, 1, 2), 0);
+        assert_eq!(binary(BinaryOp::And, 5, 7), 1);
+        assert_eq!(binary(BinaryOp::Or, 0, 0), 0);
+        assert_eq!(Expr::Unary(UnaryOp::Not, constant(5)).eval(&context()), 0);
+        assert_eq!(Expr::Unary(UnaryOp::Not, constant(0)).eval(&context()), 1);
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        let context = context();
+        let read = || Box::new(Expr::Mem(constant(0x300)));
+
+        assert_eq!(Expr::Binary(BinaryOp::And, constant(0), read()).eval(&context), 0);
+        assert_eq!(Expr::Binary(BinaryOp::Or, constant(1), read()).eval(&context), 1);
+        assert_eq!(context.memory_reads.get(), 0);
+
+        assert_eq!(Expr::Binary(BinaryOp::Or, constant(0), read()).eval(&context), 0);
+        assert_eq!(context.memory_reads.get(), 1);
+    }
+
+    #[test]
+    fn reads_registers_and_memory() {
+        let expr = Expr::Mem(Box::new(Expr::Binary(BinaryOp::Add, Box::new(Expr::Reg(Register::I)),
+                                                   Box::new(Expr::Reg(Register::V(0xA))))));
+
+        assert_eq!(expr.eval(&context()), 0x0A);
+        assert_eq!(Expr::Reg(Register::Pc).eval(&context()), 0x2a4);
+    }
+
+    #[test]
+    fn displays_nested_expressions_in_parentheses() {
+        let expr = Expr::Binary(BinaryOp::And,
+                                Box::new(Expr::Binary(BinaryOp::Eq, Box::new(Expr::Reg(Register::V(3))), constant(16))),
+                                Box::new(Expr::Unary(UnaryOp::Not, Box::new(Expr::Mem(constant(0x400))))));
+
+        assert_eq!(expr.to_string(), "(v3 == 0x10) && !mem[0x400]");
+    }
+
+    #[test]
+    fn formats_values_in_hex_or_decimal() {
+        let parts = vec![
+            FormatPart::Text("dt=".to_owned()),
+            FormatPart::Value { expr: Expr::Reg(Register::Dt), decimal: true },
+            FormatPart::Text(" {i}=".to_owned()),
+            FormatPart::Value { expr: Expr::Reg(Register::I), decimal: false }
+        ];
+
+        assert_eq!(format_message(&parts, &context()), "dt=60 {i}=0x300");
+        assert_eq!(parts.iter().map(|part| part.to_string()).collect::<String>(), "dt={dt:d} {{i}}={i}");
+    }
+}
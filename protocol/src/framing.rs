@@ -0,0 +1,182 @@
+//! Every message travels as a frame: its length as a big-endian `u32` followed by the bincode-encoded message.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use bincode;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// Upper bound of a frame's payload; memory dumps of the whole 64 KiB address space fit comfortably
+pub const MAX_FRAME_SIZE: usize = 1 << 20;
+
+#[derive(Debug)]
+pub enum FrameError {
+    /// The peer closed the connection between frames
+    Closed,
+    Io(io::Error),
+    /// The announced payload exceeds `MAX_FRAME_SIZE`, the stream cannot be trusted anymore
+    TooLarge(usize),
+    /// The payload was read completely but does not decode, the stream is still in sync
+    Decode(String)
+}
+
+impl FrameError {
+    pub fn is_timeout(&self) -> bool {
+        match *self {
+            FrameError::Io(ref e) => e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut,
+            _ => false
+        }
+    }
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FrameError::Closed => write!(f, "connection closed"),
+            FrameError::Io(ref e) => write!(f, "{}", e),
+            FrameError::TooLarge(size) => write!(f, "frame of {} bytes exceeds the limit of {} bytes", size, MAX_FRAME_SIZE),
+            FrameError::Decode(ref reason) => write!(f, "malformed frame: {}", reason)
+        }
+    }
+}
+
+impl From<io::Error> for FrameError {
+    fn from(e: io::Error) -> Self {
+        FrameError::Io(e)
+    }
+}
+
+pub fn write_frame<W: Write, T: Serialize>(writer: &mut W, message: &T) -> Result<(), FrameError> {
+    let payload = bincode::serialize(message).map_err(|e| FrameError::Decode(e.to_string()))?;
+
+    if payload.len() > MAX_FRAME_SIZE {
+        return Err(FrameError::TooLarge(payload.len()));
+    }
+
+    let size = payload.len() as u32;
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&[(size >> 24) as u8, (size >> 16) as u8, (size >> 8) as u8, size as u8]);
+    frame.extend_from_slice(&payload);
+
+    writer.write_all(&frame)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn read_frame<R: Read, T: DeserializeOwned>(reader: &mut R) -> Result<T, FrameError> {
+    let mut header = [0u8; 4];
+
+    match reader.read(&mut header[..1])? {
+        0 => return Err(FrameError::Closed),
+        _ => reader.read_exact(&mut header[1..])?
+    }
+
+    let size = (header[0] as usize) << 24 | (header[1] as usize) << 16 | (header[2] as usize) << 8 | header[3] as usize;
+
+    if size > MAX_FRAME_SIZE {
+        return Err(FrameError::TooLarge(size));
+    }
+
+    let mut payload = vec![0; size];
+    reader.read_exact(&mut payload)?;
+
+    bincode::deserialize(&payload).map_err(|e| FrameError::Decode(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use messages::Command;
+
+    use super::{read_frame, write_frame, FrameError, MAX_FRAME_SIZE};
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let size = payload.len() as u32;
+        let mut frame = vec![(size >> 24) as u8, (size >> 16) as u8, (size >> 8) as u8, size as u8];
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn payloads_with_newlines_round_trip() {
+        let poke = Command::Poke { addr: 0x0A0A, bytes: vec![0x0A, 0x0D, 0x0A, 0x00, 0x0A] };
+        let stop = Command::Stop;
+
+        let mut stream = vec![];
+        write_frame(&mut stream, &poke).unwrap();
+        write_frame(&mut stream, &stop).unwrap();
+
+        let mut reader = Cursor::new(stream);
+        assert_eq!(read_frame::<_, Command>(&mut reader).unwrap(), poke);
+        assert_eq!(read_frame::<_, Command>(&mut reader).unwrap(), stop);
+        match read_frame::<_, Command>(&mut reader) {
+            Err(FrameError::Closed) => {},
+            other => panic!("expected the stream to be closed, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn oversized_frames_are_rejected_before_reading_them() {
+        let mut reader = Cursor::new(vec![0xFF, 0xFF, 0xFF, 0xFF, 0x00]);
+
+        match read_frame::<_, Command>(&mut reader) {
+            Err(FrameError::TooLarge(size)) => assert_eq!(size, 0xFFFF_FFFF),
+            other => panic!("expected TooLarge, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn oversized_messages_are_not_written() {
+        let poke = Command::Poke { addr: 0, bytes: vec![0; MAX_FRAME_SIZE] };
+        let mut stream = vec![];
+
+        match write_frame(&mut stream, &poke) {
+            Err(FrameError::TooLarge(_)) => assert!(stream.is_empty()),
+            other => panic!("expected TooLarge, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn garbage_payloads_fail_to_decode_and_keep_the_stream_in_sync() {
+        let mut stream = frame(&[0xDE, 0xAD, 0xBE, 0xEF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        write_frame(&mut stream, &Command::Cpu).unwrap();
+
+        let mut reader = Cursor::new(stream);
+        match read_frame::<_, Command>(&mut reader) {
+            Err(FrameError::Decode(_)) => {},
+            other => panic!("expected a decoding error, got {:?}", other)
+        }
+        assert_eq!(read_frame::<_, Command>(&mut reader).unwrap(), Command::Cpu);
+    }
+
+    #[test]
+    fn garbage_lengths_inside_payloads_fail_without_allocating_them() {
+        // `Poke` with a byte vector claiming u64::MAX elements
+        let mut payload = vec![];
+        payload.extend_from_slice(&12u32.to_le_bytes());
+        payload.extend_from_slice(&[0x00, 0x02]);
+        payload.extend_from_slice(&[0xFF; 8]);
+
+        match read_frame::<_, Command>(&mut Cursor::new(frame(&payload))) {
+            Err(FrameError::Decode(_)) => {},
+            other => panic!("expected a decoding error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn truncated_frames_fail() {
+        let mut truncated = frame(&[0x01, 0x02, 0x03, 0x04]);
+        truncated.truncate(6);
+
+        match read_frame::<_, Command>(&mut Cursor::new(truncated)) {
+            Err(FrameError::Io(_)) => {},
+            other => panic!("expected an I/O error, got {:?}", other)
+        }
+        match read_frame::<_, Command>(&mut Cursor::new(vec![0x00, 0x00])) {
+            Err(FrameError::Io(_)) => {},
+            other => panic!("expected an I/O error, got {:?}", other)
+        }
+    }
+}
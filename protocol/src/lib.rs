@@ -0,0 +1,25 @@
+//! Messages exchanged between the CHIP-8 VM and the remote debugger.
+//!
+//! Messages are sent as length-prefixed frames (see `framing`). Every `Request` is answered with exactly one
+//! `Reply` carrying the same id. A connection starts with a `Command::Hello` handshake; the VM refuses any
+//! other command until the protocol versions match and the handshake carried the VM's token, if it requires one.
+//! Once attached, the VM also pushes `Event`s, such as hitting a breakpoint; both travel as `ServerMessage`s.
+
+#[macro_use] extern crate serde_derive;
+extern crate serde;
+extern crate bincode;
+
+pub mod disasm;
+mod error;
+pub mod expr;
+pub mod framing;
+mod messages;
+
+pub use error::DbgError;
+pub use messages::{Command, Request, Response, Reply, CpuSnapshot, FontSnapshot, MemoryMapSnapshot, HistoryInfo, VmStatus, StopReason, StepMode};
+pub use messages::{ResetMode, RomSource};
+pub use messages::{Event, ServerMessage};
+pub use messages::{BreakpointInfo, WatchAccess, WatchpointInfo, WatchpointHit};
+
+/// Has to be bumped on every incompatible change of the messages
+pub const PROTOCOL_VERSION: u32 = 12;
@@ -0,0 +1,234 @@
+use error::DbgError;
+use expr::{Expr, FormatPart, Register};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Command {
+    /// `token` has to match the secret the VM was started with, if any
+    Hello { version: u32, token: Option<String> },
+    Cpu,
+    Start,
+    /// Runs until the step completes, then stops with `StopReason::Step`
+    Step(StepMode),
+    Stop,
+    /// Restarts the program without changing whether the VM runs
+    Reset(ResetMode),
+    /// Replaces the ROM, then hard resets
+    LoadRom(RomSource),
+    /// Reads the ROM file again, e.g. after reassembling it, then hard resets
+    ReloadRom,
+    Reseed(u64),
+    /// Reads `size` bytes starting at `addr`, wrapping around; at most the size of RAM
+    Mem { addr: u16, size: usize },
+    /// V registers, SP and the timers only take values that fit them
+    SetRegister { register: Register, value: u16 },
+    SetStack { slot: u8, value: u16 },
+    /// Writes the bytes starting at `addr`, bypassing watchpoints
+    Poke { addr: u16, bytes: Vec<u8> },
+    /// Sets `start..=end` to `value`
+    Fill { start: u16, end: u16, value: u8 },
+    Font,
+    /// RAM size and where the ROM got loaded
+    MemoryMap,
+    /// Keys currently held down
+    Keypad,
+    Status,
+    /// Stops at `addr` when the condition is true, or always without one
+    AddBreakpoint { addr: u16, condition: Option<Expr> },
+    /// Prints the formatted message on the VM side instead of stopping
+    AddTracepoint { addr: u16, condition: Option<Expr>, message: Vec<FormatPart> },
+    /// Lets the next `count` hits of a breakpoint or tracepoint pass
+    IgnoreBreakpoint { id: u32, count: u32 },
+    DeleteBreakpoint(u32),
+    EnableBreakpoint { id: u32, enabled: bool },
+    ListBreakpoints,
+    /// Watches `start..=end`, deleted and toggled like breakpoints since both share ids
+    AddWatchpoint { start: u16, end: u16, access: WatchAccess },
+    ListWatchpoints,
+    /// Undoes the given number of instructions from the execution history, then stops with `StopReason::Step`
+    ReverseStep(u32),
+    /// Undoes instructions until reaching a breakpoint or an instruction that hit a watchpoint
+    ReverseContinue,
+    /// How much execution history the VM keeps
+    History
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum StepMode {
+    /// Executes the given number of instructions
+    Instructions(u32),
+    /// Executes one instruction, running `2NNN` calls until they return
+    Over,
+    /// Runs until the current subroutine returns
+    Out,
+    /// Runs until PC reaches the address
+    Until(u16),
+    /// Runs until the next vblank
+    Frame
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ResetMode {
+    /// Resets the CPU, timers, screen and keypad, keeping memory as it is
+    Soft,
+    /// Also clears memory and loads the fonts and the ROM again
+    Hard
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum RomSource {
+    /// File read by the VM, relative to its working directory
+    Path(String),
+    /// Contents sent by the debugger, e.g. of a file the VM cannot access
+    Bytes { name: String, data: Vec<u8> }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Request {
+    pub id: u32,
+    pub command: Command
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Response {
+    Hello { version: u32 },
+    /// Acknowledges commands that do not return anything
+    Done,
+    Cpu(CpuSnapshot),
+    Mem(Vec<u8>),
+    Font(FontSnapshot),
+    MemoryMap(MemoryMapSnapshot),
+    Keypad([bool; 16]),
+    Status(VmStatus),
+    Breakpoint(BreakpointInfo),
+    Breakpoints(Vec<BreakpointInfo>),
+    Watchpoint(WatchpointInfo),
+    Watchpoints(Vec<WatchpointInfo>),
+    History(HistoryInfo)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Reply {
+    /// Id of the request being answered, 0 when the request could not be decoded
+    pub id: u32,
+    pub result: Result<Response, DbgError>
+}
+
+/// Pushed by the VM to attached debuggers
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Event {
+    Stopped { reason: StopReason, cpu: CpuSnapshot },
+    /// The VM shut down, e.g. because its window got closed or a replayed movie ended
+    Exited
+}
+
+/// Frame sent by the VM, events are kept apart from replies
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ServerMessage {
+    Reply(Reply),
+    Event(Event)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CpuSnapshot {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub sp: u8,
+    pub stack: [u16; 16],
+    pub dt: u8,
+    pub st: u8,
+    pub seed: Option<u64>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FontSnapshot {
+    pub name: String,
+    pub location: u16,
+    pub size: usize,
+    pub big_location: Option<u16>,
+    pub big_size: usize
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MemoryMapSnapshot {
+    pub ram_size: usize,
+    pub rom_location: u16,
+    pub rom_size: usize
+}
+
+/// Why the VM is not running
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum StopReason {
+    /// Started paused or stopped by a debugger
+    Requested,
+    /// Finished a `Command::Step`
+    Step,
+    Breakpoint { id: u32, addr: u16 },
+    /// Stops after the instruction accessing the watched memory completed
+    Watchpoint(WatchpointHit),
+    /// The instruction at PC could not be executed
+    Fault(String),
+    /// Reverse execution ran out of history, PC is at the oldest instruction still recorded
+    HistoryStart
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HistoryInfo {
+    /// Instructions that can be stepped back
+    pub instructions: usize,
+    /// Estimated memory used by the history, in bytes
+    pub size: usize,
+    /// Size the history is kept under, 0 when it is disabled
+    pub budget: usize
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VmStatus {
+    pub running: bool,
+    pub stop_reason: Option<StopReason>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BreakpointInfo {
+    pub id: u32,
+    pub addr: u16,
+    pub enabled: bool,
+    /// Times the condition held, including ignored hits
+    pub hits: u32,
+    pub condition: Option<Expr>,
+    /// Set for tracepoints
+    pub message: Option<Vec<FormatPart>>,
+    /// Remaining hits to let pass
+    pub ignore_count: u32
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum WatchAccess {
+    Read,
+    Write,
+    /// Reads and writes
+    Access
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WatchpointInfo {
+    pub id: u32,
+    pub start: u16,
+    pub end: u16,
+    pub access: WatchAccess,
+    pub enabled: bool,
+    pub hits: u32
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WatchpointHit {
+    pub id: u32,
+    /// `Read` or `Write`, never `Access`
+    pub access: WatchAccess,
+    pub addr: u16,
+    pub old: u8,
+    pub new: u8,
+    /// Address and opcode of the instruction that accessed the memory
+    pub pc: u16,
+    pub opcode: u16
+}
@@ -1,3 +1,9 @@
 #!/bin/bash
+# Records an execution trace of a ROM and summarises it: trace.sh [ROM] [TRACE] [chip8 options...]
 
-RUST_LOG=info cargo run --bin chip8 -- games/tetris.c8
+ROM=${1:-games/tetris.c8}
+TRACE=${2:-trace.bin}
+shift $(( $# < 2 ? $# : 2 ))
+
+cargo run --bin chip8 -- --trace "$TRACE" "$@" "$ROM"
+cargo run --bin chip8trace -- summary "$TRACE"
@@ -0,0 +1,287 @@
+extern crate chip8_protocol;
+extern crate chip8_trace;
+extern crate clap;
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+use chip8_protocol::expr::Register;
+use chip8_trace::{diff_files, parse_number, AddressRange, TraceFormat, TraceReader, TraceRecord};
+
+fn main() {
+    let trace = || Arg::with_name("TRACE").help("Trace file recorded with chip8 --trace").required(true).index(1);
+    let count = || Arg::with_name("count").long("count").value_name("N").help("Prints at most N records");
+
+    let matches = App::new("chip8trace")
+        .about("Inspects execution traces recorded by the CHIP-8 VM")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(SubCommand::with_name("print")
+            .about("Pretty-prints the records")
+            .arg(trace())
+            .arg(count())
+            .arg(Arg::with_name("from")
+                .long("from")
+                .value_name("CYCLE")
+                .help("Skips the records before the cycle"))
+            .arg(Arg::with_name("range")
+                .long("range")
+                .value_name("START-END")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Only prints instructions within the address range, can be repeated")))
+        .subcommand(SubCommand::with_name("search")
+            .about("Prints the records matching all of the given criteria")
+            .arg(trace())
+            .arg(count())
+            .arg(Arg::with_name("pc")
+                .long("pc")
+                .value_name("START[-END]")
+                .help("Instructions at the address or within the range"))
+            .arg(Arg::with_name("opcode")
+                .long("opcode")
+                .value_name("OPCODE")
+                .help("Instructions with the opcode, hex digits with X as wildcard, e.g. DXYX"))
+            .arg(Arg::with_name("mnemonic")
+                .long("mnemonic")
+                .value_name("TEXT")
+                .help("Instructions whose mnemonic contains the text, ignoring case"))
+            .arg(Arg::with_name("writes")
+                .long("writes")
+                .value_name("START[-END]")
+                .help("Instructions writing to the address or range"))
+            .arg(Arg::with_name("register")
+                .long("register")
+                .value_name("NAME[=VALUE]")
+                .help("Instructions changing the register (v0-vf, i, sp, dt, st), optionally to the value")))
+        .subcommand(SubCommand::with_name("summary")
+            .about("Summarises where time went and what got written")
+            .arg(trace())
+            .arg(Arg::with_name("top")
+                .long("top")
+                .value_name("N")
+                .help("Entries listed per table (10 by default)")))
+        .subcommand(SubCommand::with_name("diff")
+            .about("Reports where two traces diverge, exits with 1 when they do")
+            .arg(Arg::with_name("LEFT").help("Trace of the reference run").required(true).index(1))
+            .arg(Arg::with_name("RIGHT").help("Trace to compare with it").required(true).index(2))
+            .arg(Arg::with_name("context")
+                .long("context")
+                .value_name("N")
+                .help("Records shown before and after the divergence (5 by default)")))
+        .get_matches();
+
+    let result = match matches.subcommand() {
+        ("print", Some(matches)) => print(matches),
+        ("search", Some(matches)) => search(matches),
+        ("summary", Some(matches)) => summary(matches),
+        ("diff", Some(matches)) => diff(matches),
+        _ => unreachable!()
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn open(matches: &ArgMatches) -> Result<TraceReader, String> {
+    let path = Path::new(matches.value_of("TRACE").unwrap());
+    TraceReader::open(path).map_err(|e| format!("Could not read trace {:?}: {}", path, e))
+}
+
+fn value<T, F>(matches: &ArgMatches, name: &str, parse: F) -> Result<Option<T>, String>
+    where F: Fn(&str) -> Result<T, String> {
+    match matches.value_of(name) {
+        Some(value) => parse(value).map(Some).map_err(|e| format!("Invalid value '{}' for --{}: {}", value, name, e)),
+        None => Ok(None)
+    }
+}
+
+fn print(matches: &ArgMatches) -> Result<(), String> {
+    let count = value(matches, "count", parse_number)?;
+    let from = value(matches, "from", parse_number)?.unwrap_or(0);
+    let ranges = match matches.values_of("range") {
+        Some(values) => values.map(AddressRange::from_str).collect::<Result<Vec<_>, _>>()?,
+        None => vec![]
+    };
+
+    let records = open(matches)?
+        .filter(|record| record.as_ref().map_or(true, |record| {
+            record.cycle >= from && (ranges.is_empty() || ranges.iter().any(|range| range.contains(record.pc)))
+        }));
+
+    print_records(records, count)
+}
+
+/// Opcode pattern of `search --opcode`, as a mask of the fixed digits and their values
+fn parse_opcode(pattern: &str) -> Result<(u16, u16), String> {
+    if pattern.len() != 4 {
+        return Err("expected 4 hex digits or X".to_owned());
+    }
+
+    let mut mask = 0;
+    let mut value = 0;
+    for c in pattern.chars() {
+        mask <<= 4;
+        value <<= 4;
+        match c {
+            'x' | 'X' => {},
+            _ => {
+                let digit = c.to_digit(16).ok_or_else(|| format!("'{}' is not a hex digit", c))?;
+                mask |= 0xF;
+                value |= digit as u16;
+            }
+        }
+    }
+
+    Ok((mask, value))
+}
+
+fn parse_register(name: &str) -> Result<Register, String> {
+    let name = name.to_lowercase();
+
+    match name.as_str() {
+        "i" => Ok(Register::I),
+        "sp" => Ok(Register::Sp),
+        "dt" => Ok(Register::Dt),
+        "st" => Ok(Register::St),
+        _ if name.len() == 2 && name.starts_with('v') => u8::from_str_radix(&name[1..], 16)
+            .map(Register::V)
+            .map_err(|_| format!("there is no register {}", name)),
+        _ => Err(format!("there is no register {}", name))
+    }
+}
+
+/// `NAME` or `NAME=VALUE`
+fn parse_register_change(value: &str) -> Result<(Register, Option<u16>), String> {
+    let mut parts = value.splitn(2, '=');
+    let register = parse_register(parts.next().unwrap_or(""))?;
+    let value = match parts.next() {
+        Some(value) => Some(parse_number(value).map(|value| value as u16)?),
+        None => None
+    };
+
+    Ok((register, value))
+}
+
+fn search(matches: &ArgMatches) -> Result<(), String> {
+    let count = value(matches, "count", parse_number)?;
+    let pc = value(matches, "pc", AddressRange::from_str)?;
+    let opcode = value(matches, "opcode", parse_opcode)?;
+    let mnemonic = matches.value_of("mnemonic").map(|text| text.to_lowercase());
+    let writes = value(matches, "writes", AddressRange::from_str)?;
+    let register = value(matches, "register", parse_register_change)?;
+
+    let matches_record = |record: &TraceRecord| {
+        pc.map_or(true, |range| range.contains(record.pc)) &&
+            opcode.map_or(true, |(mask, value)| record.opcode & mask == value) &&
+            mnemonic.as_ref().map_or(true, |text| record.mnemonic.to_lowercase().contains(text.as_str())) &&
+            writes.map_or(true, |range| record.writes(range.start, range.end)) &&
+            register.map_or(true, |(register, value)| match record.register(register) {
+                Some(new) => value.map_or(true, |value| new == value),
+                None => false
+            })
+    };
+
+    let records = open(matches)?
+        .filter(|record| record.as_ref().map_or(true, |record| matches_record(record)));
+
+    print_records(records, count)
+}
+
+fn print_records<I: Iterator<Item = Result<TraceRecord, String>>>(records: I, count: Option<u64>) -> Result<(), String> {
+    let limit = count.unwrap_or(u64::max_value()) as usize;
+
+    for record in records.take(limit) {
+        println!("{}", record?);
+    }
+    Ok(())
+}
+
+/// Counts sorted by how often they occur, most frequent first
+fn top<K: Clone + Ord>(counts: &HashMap<K, u64>, n: usize) -> Vec<(K, u64)> {
+    let mut sorted: Vec<(K, u64)> = counts.iter().map(|(key, &count)| (key.clone(), count)).collect();
+    sorted.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    sorted.truncate(n);
+    sorted
+}
+
+fn summary(matches: &ArgMatches) -> Result<(), String> {
+    let top_n = value(matches, "top", parse_number)?.unwrap_or(10) as usize;
+    let reader = open(matches)?;
+    let format = reader.format();
+
+    let mut total = 0u64;
+    let mut cycles = None;
+    let mut by_pc: HashMap<u16, u64> = HashMap::new();
+    let mut mnemonics: HashMap<u16, String> = HashMap::new();
+    let mut by_operation: HashMap<String, u64> = HashMap::new();
+    let mut writes: HashMap<u16, u64> = HashMap::new();
+
+    for record in reader {
+        let record = record?;
+
+        total += 1;
+        cycles = Some(match cycles {
+            Some((first, _)) => (first, record.cycle),
+            None => (record.cycle, record.cycle)
+        });
+        *by_pc.entry(record.pc).or_insert(0) += 1;
+        *by_operation.entry(record.mnemonic.split_whitespace().next().unwrap_or("").to_owned()).or_insert(0) += 1;
+        for write in &record.memory {
+            *writes.entry(write.addr).or_insert(0) += 1;
+        }
+        mnemonics.entry(record.pc).or_insert(record.mnemonic);
+    }
+
+    let (first, last) = match cycles {
+        Some(cycles) => cycles,
+        None => {
+            println!("Trace is empty");
+            return Ok(());
+        }
+    };
+
+    println!("{} instructions recorded between cycles {} and {} ({})", total, first, last,
+             if format == TraceFormat::Binary { "binary" } else { "JSON lines" });
+    println!("{} distinct addresses executed, {} bytes written to {} addresses",
+             by_pc.len(), writes.values().sum::<u64>(), writes.len());
+
+    println!("\nHottest instructions:");
+    for (pc, count) in top(&by_pc, top_n) {
+        println!("{:>10}  {:03x}  {}", count, pc, mnemonics[&pc]);
+    }
+
+    println!("\nOperations:");
+    for (operation, count) in top(&by_operation, top_n) {
+        println!("{:>10}  {}", count, operation);
+    }
+
+    if !writes.is_empty() {
+        println!("\nMost written addresses:");
+        for (addr, count) in top(&writes, top_n) {
+            println!("{:>10}  {:03x}", count, addr);
+        }
+    }
+
+    Ok(())
+}
+
+fn diff(matches: &ArgMatches) -> Result<(), String> {
+    let context = value(matches, "context", parse_number)?.unwrap_or(5) as usize;
+    let left = Path::new(matches.value_of("LEFT").unwrap());
+    let right = Path::new(matches.value_of("RIGHT").unwrap());
+
+    match diff_files(left, right, context)? {
+        Some(divergence) => {
+            print!("{}", divergence);
+            process::exit(1);
+        },
+        None => println!("Traces are identical")
+    }
+    Ok(())
+}
@@ -1,8 +1,30 @@
 use super::super::interconnect;
-use super::super::remote_dbg;
+use super::super::display::{CHIP8_WIDTH, CHIP8_HEIGHT};
+use super::quirks::Quirks;
+use super::rng::{RandomSource, RngState, XorShiftSource};
 use std::fmt;
-use rand;
-use rand::Rng;
+
+use chip8_protocol::CpuSnapshot;
+use chip8_protocol::expr::Register;
+
+/// Registers, stack and timers, cheap enough to copy before every instruction for the execution history
+#[derive(Debug, Clone, Copy)]
+pub struct Registers {
+    v: [u8; 16],
+    i: u16,
+    pc: u16,
+    stack: [u16; 16],
+    sp: u8,
+    delay_timer: u8,
+    sound_timer: u8,
+    await_key_press: bool
+}
+
+impl Registers {
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+}
 
 pub struct Cpu {
     v: [u8; 16],
@@ -13,7 +35,14 @@ pub struct Cpu {
 
     // TODO: extract ?
     delay_timer: u8,
-    await_key_press: bool
+    /// Only counted down, nothing is played yet
+    sound_timer: u8,
+    await_key_press: bool,
+
+    quirks: Quirks,
+    font_location: u16,
+    big_font_location: u16,
+    rng: Box<dyn RandomSource>
 }
 
 impl fmt::Debug for Cpu {
@@ -24,6 +53,14 @@ impl fmt::Debug for Cpu {
 
 impl Cpu {
     pub fn new() -> Cpu {
+        Cpu::with_rng(Box::new(XorShiftSource::from_entropy()))
+    }
+
+    pub fn with_seed(seed: u64) -> Cpu {
+        Cpu::with_rng(Box::new(XorShiftSource::new(seed)))
+    }
+
+    pub fn with_rng(rng: Box<dyn RandomSource>) -> Cpu {
         Cpu {
             v: [0; 16],
             i: 0,
@@ -32,33 +69,146 @@ impl Cpu {
             sp: 0,
 
             delay_timer: 0,
-            await_key_press: false
+            sound_timer: 0,
+            await_key_press: false,
+
+            quirks: Quirks::default(),
+            font_location: 0,
+            big_font_location: 0,
+            rng
+        }
+    }
+
+    /// Clears registers, stack and timers, keeping the configuration and the random source
+    pub fn reset(&mut self) {
+        self.v = [0; 16];
+        self.i = 0;
+        self.pc = 0x200;
+        self.stack = [0; 16];
+        self.sp = 0;
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.await_key_press = false;
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn set_font_location(&mut self, font_location: u16) {
+        self.font_location = font_location;
+    }
+
+    pub fn set_big_font_location(&mut self, big_font_location: u16) {
+        self.big_font_location = big_font_location;
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// Value of a register as seen by debugger expressions
+    pub fn register(&self, register: Register) -> u16 {
+        match register {
+            Register::V(x) => self.v[(x & 0xF) as usize] as u16,
+            Register::I => self.i,
+            Register::Pc => self.pc,
+            Register::Sp => self.sp as u16,
+            Register::Dt => self.delay_timer as u16,
+            Register::St => self.sound_timer as u16
+        }
+    }
+
+    /// Overwrites a register on behalf of the debugger, which checks that the value fits
+    pub fn set_register(&mut self, register: Register, value: u16) {
+        match register {
+            Register::V(x) => self.v[(x & 0xF) as usize] = value as u8,
+            Register::I => self.i = value,
+            Register::Pc => self.pc = value,
+            Register::Sp => self.sp = value as u8,
+            Register::Dt => self.delay_timer = value as u8,
+            Register::St => self.sound_timer = value as u8
         }
     }
 
+    pub fn set_stack(&mut self, slot: usize, value: u16) {
+        self.stack[slot] = value;
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            v: self.v,
+            i: self.i,
+            pc: self.pc,
+            stack: self.stack,
+            sp: self.sp,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            await_key_press: self.await_key_press
+        }
+    }
+
+    pub fn restore_registers(&mut self, registers: &Registers) {
+        self.v = registers.v;
+        self.i = registers.i;
+        self.pc = registers.pc;
+        self.stack = registers.stack;
+        self.sp = registers.sp;
+        self.delay_timer = registers.delay_timer;
+        self.sound_timer = registers.sound_timer;
+        self.await_key_press = registers.await_key_press;
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.rng.seed()
+    }
+
+    pub fn rng_state(&self) -> RngState {
+        self.rng.state()
+    }
+
+    pub fn set_rng(&mut self, rng: Box<dyn RandomSource>) {
+        self.rng = rng;
+    }
+
     // TODO: weird...
-    pub fn snapshot(&self) -> remote_dbg::CpuSnapshot {
-        remote_dbg::CpuSnapshot {
+    pub fn snapshot(&self) -> CpuSnapshot {
+        CpuSnapshot {
             v: self.v,
             i: self.i,
             pc: self.pc,
             sp: self.sp,
-            stack: self.stack
+            stack: self.stack,
+            dt: self.delay_timer,
+            st: self.sound_timer,
+            seed: self.rng.seed()
         }
     }
     
-    pub fn execute_cycle(&mut self, interconnect: &mut interconnect::Interconnect) {
+    /// Executes the instruction at PC, failing with a description of the fault if it cannot be executed
+    pub fn execute_cycle(&mut self, interconnect: &mut interconnect::Interconnect) -> Result<(), String> {
         trace!("{:?}", self);
 
+        let opcode = interconnect.read_word(self.pc);
+        self.execute_opcode(opcode, interconnect)
+    }
+
+    /// Has to be called at 60Hz
+    pub fn tick_timers(&mut self) {
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
 
-        let opcode = interconnect.read_word(self.pc);
-        self.execute_opcode(opcode, interconnect);
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+        }
     }
 
-    fn execute_opcode(&mut self, opcode: u16, interconnect: &mut interconnect::Interconnect) {
+    fn execute_opcode(&mut self, opcode: u16, interconnect: &mut interconnect::Interconnect) -> Result<(), String> {
 
         let op_1 = (opcode & 0xF000) >> 12;
         let op_2 = (opcode & 0x0F00) >> 8;
@@ -80,13 +230,16 @@ impl Cpu {
             },
             (0x0, 0x0, 0xE, 0xE) => {
                 trace!("[FLOW] Return from subroutine");
+                if self.sp == 0 {
+                    return Err(format!("Stack underflow: return at {:#x} outside of a subroutine", self.pc));
+                }
                 self.sp -= 1;
                 self.pc = self.stack[self.sp as usize];
                 self.pc += 2;
             },
             (0x0, _, _, _) => {
                 trace!("[CALL] Call RCA 1802 program at {:#x}", nnn);
-                panic!("[CALL] Call RCA 1802 program at {:#x}", nnn);
+                return Err(format!("Unsupported call of RCA 1802 program at {:#x}", nnn));
             }
             (0x1, _, _, _) => {
                 trace!("[FLOW] Jump to: {:#x}", nnn);
@@ -94,6 +247,9 @@ impl Cpu {
             },
             (0x2, _, _, _) => {
                 trace!("[FLOW] Call subroutine at {:#x}", nnn);
+                if self.sp as usize == self.stack.len() {
+                    return Err(format!("Stack overflow: call of {:#x} at {:#x}", nnn, self.pc));
+                }
                 self.stack[self.sp as usize] = self.pc;
                 self.sp += 1;
                 self.pc = nnn;
@@ -141,16 +297,25 @@ impl Cpu {
             (0x8, _, _, 0x1) => {
                 trace!("[BITOP] Set V{:x} to V{:x} OR V{:x}", x, x, y);
                 self.v[x] = self.v[x] | self.v[y];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
                 self.pc += 2;
             },
             (0x8, _, _, 0x2) => {
                 trace!("[BITOP] Set V{:x} to V{:x} AND V{:x}", x, x, y);
                 self.v[x] = self.v[x] & self.v[y];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
                 self.pc += 2;
             },
             (0x8, _, _, 0x3) => {
                 trace!("[BITOP] Set V{:x} to V{:x} XOR V{:x}", x, x, y);
                 self.v[x] = self.v[x] ^ self.v[y];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
                 self.pc += 2;
             },
             (0x8, _, _, 0x4) => {
@@ -168,8 +333,9 @@ impl Cpu {
             },
             (0x8, _, _, 0x6) => {
                 trace!("[BITOP] Shift V{:x} right by 1 and store result to V{:x}", y, x);
-                self.v[0xF] = vy & 1;
-                self.v[x] = vy >> 1;
+                let source = if self.quirks.shift_vx { vx } else { vy };
+                self.v[x] = source >> 1;
+                self.v[0xF] = source & 1;
                 self.pc += 2;
             },
             (0x8, _, _, 0x7) => {
@@ -180,8 +346,9 @@ impl Cpu {
             },
             (0x8, _, _, 0xE) => {
                 trace!("[BITOP] Shift V{:x} left by 1 and copy the result to V{:x}", y, x);
-                self.v[0xF] = vy & 0x80;
-                self.v[x] = vy << 1;
+                let source = if self.quirks.shift_vx { vx } else { vy };
+                self.v[x] = source << 1;
+                self.v[0xF] = (source & 0x80) >> 7;
                 self.pc += 2;
             },
             (0x9, _, _, 0x0) => {
@@ -199,11 +366,12 @@ impl Cpu {
             },
             (0xB, _, _, _) => {
                 trace!("[FLOW] Jump to address {:#x} + V0", nnn);
-                self.pc = (self.v[0] as u16) + nnn;
+                let offset = if self.quirks.jump_vx { vx } else { self.v[0] };
+                self.pc = (offset as u16) + nnn;
             },
             (0xC, _, _, _) => {
                 trace!("[RAND] Set V{:x} to the result: rand() AND {:#x}", x, nn);
-                self.v[x] = rand::thread_rng().gen::<u8>() & nn;
+                self.v[x] = self.rng.next_byte() & nn;
                 self.pc += 2;
             },
             (0xD, _, _, _) => {
@@ -211,20 +379,33 @@ impl Cpu {
 
                 self.v[0xF] = 0;
 
-                for j in 0..n {
-                    let pixel = interconnect.ram()[(self.i + j as u16) as usize];
+                let x0 = vx as usize % CHIP8_WIDTH;
+                let y0 = vy as usize % CHIP8_HEIGHT;
+
+                for j in 0..n as usize {
+                    let row = y0 + j;
+                    if row >= CHIP8_HEIGHT && self.quirks.clip_sprites {
+                        break;
+                    }
+
+                    let sprite = interconnect.read_byte(self.i.wrapping_add(j as u16));
                     for i in 0..8 {
-                        if (pixel & (0x80 >> i)) != 0 {
-                            // FIXME: index out of bounds: the len is 64 but the index is 255 (UFO)
-                            if interconnect.display().vram()[(vy + j) as usize][(vx + i) as usize] == 1 {
+                        let column = x0 + i;
+                        if column >= CHIP8_WIDTH && self.quirks.clip_sprites {
+                            break;
+                        }
+
+                        if (sprite & (0x80 >> i)) != 0 {
+                            let vram = interconnect.display().vram();
+                            let pixel = &mut vram[row % CHIP8_HEIGHT][column % CHIP8_WIDTH];
+                            if *pixel == 1 {
                                 self.v[0xF] = 1;
                             }
-                            interconnect.display().vram()[(vy + j) as usize][(vx + i) as usize] ^= 1;
+                            *pixel ^= 1;
                         }
                     }
                 }
 
-                interconnect.display().draw();
                 self.pc += 2;
             },
             (0xE, _, 0x9, 0xE) => {
@@ -250,10 +431,8 @@ impl Cpu {
             },
             (0xF, _, 0x0, 0xA) => {
                 trace!("[KEYOP] Await (blocking) key press and store result in V{:x}", x);
-                panic!("[KEYOP] Await (blocking) key press and store result in V{:x}", x);
-                self.await_key_press = true;
-                // TODO: FINISH IMPLEMENTATION
-                self.pc += 2;
+                // TODO: FINISH IMPLEMENTATION, set `await_key_press`
+                return Err(format!("Unsupported blocking key press wait for V{:x}", x));
             },
             (0xF, _, 0x1, 0x5) => {
                 trace!("[TIMER] Set the delay timer to V{:x}", x);
@@ -262,17 +441,22 @@ impl Cpu {
             },
             (0xF, _, 0x1, 0x8) => {
                 trace!("[SOUND] Set the sound timer to V{:x}", x);
-                warn!("UNIMPLEMENTED - [SOUND] Set the sound timer to V{:x}", x);
+                self.sound_timer = vx;
                 self.pc += 2;
             },
             (0xF, _, 0x1, 0xE) => {
                 trace!("[MEM] Add V{:x} to I", x);
-                self.i += vx as u16;
+                self.i = self.i.wrapping_add(vx as u16);
                 self.pc += 2;
             },
             (0xF, _, 0x2, 0x9) => {
                 trace!("[MEM] Set I to the location of the sprite for the character in V{:x}", x);
-                self.i = (vx * 5) as u16;
+                self.i = self.font_location + (vx & 0xF) as u16 * 5;
+                self.pc += 2;
+            },
+            (0xF, _, 0x3, 0x0) => {
+                trace!("[MEM] Set I to the location of the big sprite for the character in V{:x}", x);
+                self.i = self.big_font_location + (vx & 0xF) as u16 * 10;
                 self.pc += 2;
             },
             (0xF, _, 0x3, 0x3) => {
@@ -283,19 +467,102 @@ impl Cpu {
             },
             (0xf, _, 0x5, 0x5) => {
                 trace!("[MEM] Store V0 to V{:x} in memory starting at address I", x);
-                interconnect.write_memory(self.i, &(self.v[0..x].to_vec()));
+                interconnect.write_memory(self.i, &(self.v[0..=x].to_vec()));
+                if self.quirks.load_store_increment_i {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
+                }
                 self.pc += 2;
             },
             (0xF, _, 0x6, 0x5) => {
                 trace!("[MEM] Fill V0 to V{:x} with values from memory starting at address I", x);
-                for i in 0..x {
-                    self.v[i] = interconnect.read_byte(self.i + i as u16);
+                for i in 0..=x {
+                    self.v[i] = interconnect.read_byte(self.i.wrapping_add(i as u16));
+                }
+                if self.quirks.load_store_increment_i {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
                 }
                 self.pc += 2;
             }
             _ => {
-                panic!("Unrecognized instruction: {:#x}", opcode);
+                return Err(format!("Unrecognized instruction: {:#x}", opcode));
             }
         }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cpu;
+    use super::super::rng::ScriptedSource;
+    use chip8::{Display, Keypad, Interconnect};
+    use chip8_protocol::expr::Register;
+
+    fn interconnect(program: &[u8]) -> Interconnect {
+        let mut interconnect = Interconnect::new(Display::headless(), Keypad::headless(), 0x1000);
+        interconnect.load(0x200, program);
+        interconnect
+    }
+
+    fn run(cpu: &mut Cpu, interconnect: &mut Interconnect, instructions: usize) {
+        cpu.set_pc(0x200);
+        for _ in 0..instructions {
+            cpu.execute_cycle(interconnect).unwrap();
+        }
+    }
+
+    #[test]
+    fn fx1e_wraps_i_around() {
+        let mut cpu = Cpu::with_seed(1);
+        cpu.set_register(Register::I, 0xFFFF);
+        cpu.set_register(Register::V(0), 2);
+
+        run(&mut cpu, &mut interconnect(&[0xF0, 0x1E]), 1);
+
+        assert_eq!(cpu.register(Register::I), 0x0001);
+    }
+
+    #[test]
+    fn fx65_and_dxyn_read_past_the_highest_i() {
+        let mut cpu = Cpu::with_seed(1);
+        cpu.set_register(Register::I, 0xFFFF);
+
+        let mut interconnect = interconnect(&[0xD0, 0x02, 0xF1, 0x65]);
+        interconnect.load(0xFFF, &[0x12]);
+        interconnect.load(0x000, &[0x34]);
+        run(&mut cpu, &mut interconnect, 2);
+
+        let rows = interconnect.display().vram();
+        assert_eq!(rows[0][..8], [0, 0, 0, 1, 0, 0, 1, 0]);
+        assert_eq!(rows[1][..8], [0, 0, 1, 1, 0, 1, 0, 0]);
+        assert_eq!(cpu.register(Register::V(0)), 0x12);
+        assert_eq!(cpu.register(Register::V(1)), 0x34);
+        assert_eq!(cpu.register(Register::I), 0x0001);
+    }
+
+    #[test]
+    fn cxnn_masks_the_scripted_values() {
+        let mut cpu = Cpu::with_rng(Box::new(ScriptedSource::new(vec![0xAB, 0x5C])));
+
+        run(&mut cpu, &mut interconnect(&[0xC0, 0xF0, 0xC1, 0xFF, 0xC2, 0x0F]), 3);
+
+        assert_eq!(cpu.register(Register::V(0)), 0xA0);
+        assert_eq!(cpu.register(Register::V(1)), 0x5C);
+        assert_eq!(cpu.register(Register::V(2)), 0x0B);
+    }
+
+    #[test]
+    fn seeded_cpus_draw_the_same_numbers() {
+        let program = [0xC0, 0xFF, 0xC1, 0xFF, 0xC2, 0xFF];
+        let (mut left, mut right) = (Cpu::with_seed(42), Cpu::with_seed(42));
+
+        run(&mut left, &mut interconnect(&program), 3);
+        run(&mut right, &mut interconnect(&program), 3);
+
+        assert_eq!(left.seed(), Some(42));
+        for x in 0..3 {
+            assert_eq!(left.register(Register::V(x)), right.register(Register::V(x)));
+        }
     }
 }
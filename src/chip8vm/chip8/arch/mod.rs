@@ -1,3 +1,5 @@
 mod cpu;
+pub mod quirks;
+pub mod rng;
 
-pub use self::cpu::Cpu;
+pub use self::cpu::{Cpu, Registers};
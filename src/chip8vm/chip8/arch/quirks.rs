@@ -0,0 +1,72 @@
+use std::str::FromStr;
+
+/// Behaviour differences between CHIP-8 interpreters that ROMs may depend on
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Quirks {
+    /// `8XY6`/`8XYE` shift VX in place instead of shifting VY into VX
+    pub shift_vx: bool,
+    /// `FX55`/`FX65` leave I pointing past the last register stored/loaded
+    pub load_store_increment_i: bool,
+    /// `BNNN` jumps to XNN + VX instead of NNN + V0
+    pub jump_vx: bool,
+    /// `8XY1`, `8XY2` and `8XY3` reset VF to 0
+    pub vf_reset: bool,
+    /// Sprites are clipped at the screen edges instead of wrapping around
+    pub clip_sprites: bool
+}
+
+/// The original COSMAC VIP interpreter's behaviour, what CHIP-8 ROMs were written for
+impl Default for Quirks {
+    fn default() -> Self {
+        QuirkProfile::Chip8.quirks()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum QuirkProfile {
+    Chip8,
+    Schip,
+    XoChip
+}
+
+impl QuirkProfile {
+    pub fn quirks(&self) -> Quirks {
+        match *self {
+            QuirkProfile::Chip8 => Quirks {
+                shift_vx: false,
+                load_store_increment_i: true,
+                jump_vx: false,
+                vf_reset: true,
+                clip_sprites: true
+            },
+            QuirkProfile::Schip => Quirks {
+                shift_vx: true,
+                load_store_increment_i: false,
+                jump_vx: true,
+                vf_reset: false,
+                clip_sprites: true
+            },
+            QuirkProfile::XoChip => Quirks {
+                shift_vx: false,
+                load_store_increment_i: true,
+                jump_vx: false,
+                vf_reset: false,
+                clip_sprites: false
+            }
+        }
+    }
+}
+
+impl FromStr for QuirkProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "chip8" | "chip-8" | "vip" => Ok(QuirkProfile::Chip8),
+            "schip" | "superchip" => Ok(QuirkProfile::Schip),
+            "xochip" | "xo-chip" => Ok(QuirkProfile::XoChip),
+            other => Err(format!("Unknown quirk profile: {} (expected chip8, schip or xochip)", other))
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use rand;
+
+/// Source of random bytes for `CXNN`
+pub trait RandomSource {
+    fn next_byte(&mut self) -> u8;
+
+    /// Seed the source was created with, if it has one
+    fn seed(&self) -> Option<u64>;
+
+    fn state(&self) -> RngState;
+}
+
+/// Serializable state of a random source, so it can be stored (e.g. in save states) and restored later
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum RngState {
+    XorShift { seed: u64, state: u64 },
+    Scripted { values: Vec<u8>, position: usize }
+}
+
+impl RngState {
+    pub fn restore(self) -> Box<dyn RandomSource> {
+        match self {
+            RngState::XorShift { seed, state } => Box::new(XorShiftSource { seed, state }),
+            RngState::Scripted { values, position } => Box::new(ScriptedSource { values, position })
+        }
+    }
+}
+
+/// Seedable xorshift64* generator
+pub struct XorShiftSource {
+    seed: u64,
+    state: u64
+}
+
+impl XorShiftSource {
+    pub fn new(seed: u64) -> Self {
+        // xorshift must never be in the all zero state
+        let state = match seed ^ 0x9E37_79B9_7F4A_7C15 {
+            0 => 0x9E37_79B9_7F4A_7C15,
+            state => state
+        };
+
+        XorShiftSource { seed, state }
+    }
+
+    pub fn from_entropy() -> Self {
+        XorShiftSource::new(rand::random())
+    }
+}
+
+impl RandomSource for XorShiftSource {
+    fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    fn seed(&self) -> Option<u64> {
+        Some(self.seed)
+    }
+
+    fn state(&self) -> RngState {
+        RngState::XorShift { seed: self.seed, state: self.state }
+    }
+}
+
+/// Returns the given values in order, starting over once they run out
+pub struct ScriptedSource {
+    values: Vec<u8>,
+    position: usize
+}
+
+impl ScriptedSource {
+    pub fn new(values: Vec<u8>) -> Self {
+        assert!(!values.is_empty(), "Scripted random source needs at least one value");
+
+        ScriptedSource { values, position: 0 }
+    }
+}
+
+impl RandomSource for ScriptedSource {
+    fn next_byte(&mut self) -> u8 {
+        let value = self.values[self.position];
+        self.position = (self.position + 1) % self.values.len();
+        value
+    }
+
+    fn seed(&self) -> Option<u64> {
+        None
+    }
+
+    fn state(&self) -> RngState {
+        RngState::Scripted { values: self.values.clone(), position: self.position }
+    }
+}
@@ -0,0 +1,101 @@
+use chip8_protocol::{BreakpointInfo, DbgError};
+use chip8_protocol::expr::{format_message, Expr, ExprContext, FormatPart};
+
+/// PC breakpoints and tracepoints managed by the debugger
+#[derive(Default)]
+pub struct Breakpoints {
+    next_id: u32,
+    breakpoints: Vec<BreakpointInfo>,
+    /// Address execution resumes from, so that continuing from a breakpoint does not hit it again
+    resume_pc: Option<u16>
+}
+
+impl Breakpoints {
+    /// Ids are shared with watchpoints, so that the debugger can delete or toggle either by id
+    pub fn allocate_id(&mut self) -> u32 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// Adds a breakpoint, or a tracepoint when there is a message to print
+    pub fn add(&mut self, addr: u16, condition: Option<Expr>, message: Option<Vec<FormatPart>>) -> BreakpointInfo {
+        let breakpoint = BreakpointInfo {
+            id: self.allocate_id(),
+            addr,
+            enabled: true,
+            hits: 0,
+            condition,
+            message,
+            ignore_count: 0
+        };
+        self.breakpoints.push(breakpoint.clone());
+        breakpoint
+    }
+
+    pub fn delete(&mut self, id: u32) -> Result<(), DbgError> {
+        let index = self.breakpoints.iter().position(|b| b.id == id).ok_or(DbgError::NoSuchBreakpoint(id))?;
+        self.breakpoints.remove(index);
+        Ok(())
+    }
+
+    pub fn set_enabled(&mut self, id: u32, enabled: bool) -> Result<BreakpointInfo, DbgError> {
+        let breakpoint = self.breakpoints.iter_mut().find(|b| b.id == id).ok_or(DbgError::NoSuchBreakpoint(id))?;
+        breakpoint.enabled = enabled;
+        Ok(breakpoint.clone())
+    }
+
+    pub fn ignore(&mut self, id: u32, count: u32) -> Result<BreakpointInfo, DbgError> {
+        let breakpoint = self.breakpoints.iter_mut().find(|b| b.id == id).ok_or(DbgError::NoSuchBreakpoint(id))?;
+        breakpoint.ignore_count = count;
+        Ok(breakpoint.clone())
+    }
+
+    pub fn list(&self) -> Vec<BreakpointInfo> {
+        self.breakpoints.clone()
+    }
+
+    pub fn resume_from(&mut self, pc: u16) {
+        self.resume_pc = Some(pc);
+    }
+
+    /// Has to be called before executing the instruction at `pc`, returns the breakpoint that got hit.
+    /// Tracepoints print their message and let execution go on.
+    pub fn check<C: ExprContext>(&mut self, pc: u16, context: &C) -> Option<BreakpointInfo> {
+        if self.resume_pc.take() == Some(pc) {
+            return None;
+        }
+
+        let mut hit = None;
+
+        for breakpoint in self.breakpoints.iter_mut().filter(|b| b.enabled && b.addr == pc) {
+            if breakpoint.condition.as_ref().map_or(false, |condition| condition.eval(context) == 0) {
+                continue;
+            }
+
+            breakpoint.hits += 1;
+            if breakpoint.ignore_count > 0 {
+                breakpoint.ignore_count -= 1;
+                continue;
+            }
+
+            match breakpoint.message {
+                Some(ref message) => println!("[trace {}] {}", breakpoint.id, format_message(message, context)),
+                None if hit.is_none() => hit = Some(breakpoint.clone()),
+                None => {}
+            }
+        }
+
+        hit
+    }
+
+    /// Breakpoint that stops execution at `pc`, without counting a hit or printing tracepoints.
+    /// Used when executing backwards, where ignore counts do not apply either.
+    pub fn find<C: ExprContext>(&self, pc: u16, context: &C) -> Option<BreakpointInfo> {
+        self.breakpoints.iter()
+            .find(|b| {
+                b.enabled && b.addr == pc && b.message.is_none() &&
+                    b.condition.as_ref().map_or(true, |condition| condition.eval(context) != 0)
+            })
+            .cloned()
+    }
+}
@@ -1,13 +1,64 @@
 use super::{Cpu, Interconnect, Display, Keypad, RemoteDbg, DbgMessage};
-use super::remote_dbg::{Chip8Snapshots};
+use super::breakpoints::Breakpoints;
+use super::display::CHIP8_WIDTH;
+use super::history::{self, History, HistoryEntry};
+use super::stepping::StepTarget;
+use super::arch::rng::{RandomSource, XorShiftSource};
+use super::movie::{Movie, MovieSession, MovieSettings, MovieRecorder, MoviePlayer};
+use super::settings::Settings;
 use super::mem_map;
 
-use std::process;
+use std::fmt;
+use std::fs;
+
 use std::thread;
 use std::net::{TcpListener, TcpStream, Shutdown};
 use std::io::prelude::*;
 use std::io::{BufReader, BufWriter};
 use std::sync::mpsc;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use chip8_protocol::{Command, CpuSnapshot, DbgError, Event, FontSnapshot, MemoryMapSnapshot, ResetMode, Response, RomSource, StepMode,
+                     StopReason, VmStatus, PROTOCOL_VERSION};
+use chip8_protocol::expr::{ExprContext, Register};
+use chip8_trace::{MemoryWrite, TraceRecord, TraceRecorder};
+
+
+const FRAME_DURATION: Duration = Duration::from_micros(16_667);
+
+#[derive(Debug)]
+pub enum RomError {
+    Empty,
+    TooLarge { size: usize, max: usize },
+    OverlapsFonts { fonts_location: u16 }
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RomError::Empty => write!(f, "ROM is empty"),
+            RomError::TooLarge { size, max } => write!(f, "ROM is too large: {} bytes (at most {} bytes fit in memory)", size, max),
+            RomError::OverlapsFonts { fonts_location } => write!(f, "ROM would overwrite the fonts at {:#x}", fonts_location)
+        }
+    }
+}
+
+/// VM state as seen by breakpoint conditions and tracepoint messages
+struct VmContext<'a> {
+    cpu: &'a Cpu,
+    interconnect: &'a Interconnect
+}
+
+impl<'a> ExprContext for VmContext<'a> {
+    fn register(&self, register: Register) -> u16 {
+        self.cpu.register(register)
+    }
+
+    fn memory(&self, addr: u16) -> u8 {
+        self.interconnect.peek(addr)
+    }
+}
 
 #[derive(PartialEq)]
 enum VmState {
@@ -19,104 +70,745 @@ enum VmState {
 pub struct Chip8 {
     cpu: Cpu,
     interconnect: Interconnect,
+    movie: Option<MovieSession>,
+    tracer: Option<TraceRecorder>,
+    settings: Settings,
+    /// Loaded ROM, kept for hard resets
+    rom: Vec<u8>,
+    /// File the ROM got read from, `None` when a debugger uploaded it
+    rom_path: Option<PathBuf>,
+    debugger: Option<RemoteDbg>,
+    breakpoints: Breakpoints,
+    step_target: Option<StepTarget>,
+    /// Executed instructions the debugger can step back
+    history: History,
+    /// Instructions executed in the current frame, which may have been interrupted by the debugger
+    frame_cycle: u32,
+    /// Instructions executed since the VM started
+    cycle: u64,
 
-    state: VmState
+    state: VmState,
+    stop_reason: Option<StopReason>
 }
 
 impl Chip8 {
-    pub fn new(display: Display, keypad: Keypad) -> Self {
-        let mut interconnect = Interconnect::new(display, keypad);
-        Chip8::load_fonts(&mut interconnect);
+    pub fn new(display: Display, keypad: Keypad, settings: Settings) -> Self {
+        let mut interconnect = Interconnect::new(display, keypad, settings.memory_map.ram_size);
+        Chip8::load_fonts(&mut interconnect, &settings);
+        let history_budget = settings.debugger.as_ref().map_or(0, |debugger| debugger.history_budget);
 
         Chip8 {
-            cpu: Cpu::new(),
+            cpu: Chip8::create_cpu(&settings),
             interconnect,
-            state: VmState::CREATED
+            movie: None,
+            tracer: None,
+            settings,
+            rom: vec![],
+            rom_path: None,
+            debugger: None,
+            breakpoints: Breakpoints::default(),
+            step_target: None,
+            history: History::new(history_budget),
+            frame_cycle: 0,
+            cycle: 0,
+            state: VmState::CREATED,
+            stop_reason: None
+        }
+    }
+
+    fn create_cpu(settings: &Settings) -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.set_quirks(settings.quirks);
+        cpu.set_pc(settings.memory_map.rom_location);
+        cpu.set_font_location(settings.memory_map.fonts_location);
+        cpu.set_big_font_location(settings.memory_map.big_fonts_location);
+        cpu
+    }
+
+    fn load_fonts(interconnect: &mut Interconnect, settings: &Settings) {
+        let memory_map = settings.memory_map;
+
+        interconnect.load(memory_map.fonts_location, &settings.font.small);
+        if let Some(ref big) = settings.font.big {
+            interconnect.load(memory_map.big_fonts_location, big);
+        }
+    }
+
+    /// Memory ranges occupied by the fonts
+    fn font_ranges(&self) -> Vec<(u16, usize)> {
+        let memory_map = self.settings.memory_map;
+        let mut ranges = vec![(memory_map.fonts_location, mem_map::FONTS_SIZE)];
+
+        if self.settings.font.big.is_some() {
+            ranges.push((memory_map.big_fonts_location, mem_map::BIG_FONTS_SIZE));
+        }
+
+        ranges
+    }
+
+    /// Checks that the ROM fits into memory next to the fonts
+    fn check_rom(&self, rom: &[u8]) -> Result<(), RomError> {
+        let memory_map = self.settings.memory_map;
+        let max = memory_map.rom_capacity();
+
+        if rom.is_empty() {
+            return Err(RomError::Empty);
+        }
+
+        if rom.len() > max {
+            return Err(RomError::TooLarge { size: rom.len(), max });
+        }
+
+        let rom_start = memory_map.rom_location as usize;
+        for (fonts_location, fonts_size) in self.font_ranges() {
+            let fonts_start = fonts_location as usize;
+            if fonts_start < rom_start + rom.len() && rom_start < fonts_start + fonts_size {
+                return Err(RomError::OverlapsFonts { fonts_location });
+            }
+        }
+
+        if rom.len() % 2 != 0 {
+            warn!("ROM has an odd size of {} bytes", rom.len());
         }
+
+        Ok(())
+    }
+
+    pub fn load_rom(&mut self, rom: &Vec<u8>) -> Result<(), RomError> {
+        self.check_rom(rom)?;
+
+        self.interconnect.load(self.settings.memory_map.rom_location, rom);
+        self.rom = rom.clone();
+        Ok(())
+    }
+
+    /// Remembers the file the ROM came from, so that a debugger can reload it
+    pub fn set_rom_path(&mut self, path: &Path) {
+        self.rom_path = Some(path.to_path_buf());
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.cpu.seed()
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        self.cpu.set_rng(Box::new(XorShiftSource::new(seed)));
     }
 
-    fn load_fonts(interconnect: &mut Interconnect) {
-        // TODO: extract somewhere else
-        let font_set: Vec<u8> = vec![
-            0xF0, 0x90, 0x90, 0x90, 0xF0, /* 0 */
-            0x20, 0x60, 0x20, 0x20, 0x70, /* 1 */
-            0xF0, 0x10, 0xF0, 0x80, 0xF0, /* 2 */
-            0xF0, 0x10, 0xF0, 0x10, 0xF0, /* 3 */
-            0x90, 0x90, 0xF0, 0x10, 0x10, /* 4 */
-            0xF0, 0x80, 0xF0, 0x10, 0xF0, /* 5 */
-            0xF0, 0x80, 0xF0, 0x90, 0xF0, /* 6 */
-            0xF0, 0x10, 0x20, 0x40, 0x40, /* 7 */
-            0xF0, 0x90, 0xF0, 0x90, 0xF0, /* 8 */
-            0xF0, 0x90, 0xF0, 0x10, 0xF0, /* 9 */
-            0xF0, 0x90, 0xF0, 0x90, 0x90, /* A */
-            0xE0, 0x90, 0xE0, 0x90, 0xE0, /* B */
-            0xF0, 0x80, 0x80, 0x80, 0xF0, /* C */
-            0xE0, 0x90, 0x90, 0x90, 0xE0, /* D */
-            0xF0, 0x80, 0xF0, 0x80, 0xF0, /* E */
-            0xF0, 0x80, 0xF0, 0x80, 0x80, /* F */
-        ];
+    /// Replaces the random source used by `CXNN`, e.g. with a scripted one
+    pub fn set_random_source(&mut self, rng: Box<dyn RandomSource>) {
+        self.cpu.set_rng(rng);
+    }
 
-        interconnect.write_memory(mem_map::FONTS_LOCATION, &font_set);
+    /// Records keypad input of the whole run; has to be called after the VM got seeded
+    pub fn record_movie(&mut self, path: &Path, rom: &[u8]) {
+        let seed = self.cpu.seed().expect("Movies can only be recorded with a seeded random source");
+        let recorder = MovieRecorder::new(path, rom, seed, MovieSettings::new(&self.settings));
+        self.movie = Some(MovieSession::Recording(recorder));
     }
 
-    pub fn load_rom(&mut self, rom: &Vec<u8>) {
-        self.interconnect.write_memory(mem_map::ROM_LOCATION, rom);
+    /// Feeds keypad input from the movie instead of the keypad, stopping the VM when it runs out.
+    /// The VM has to be created with the settings the movie was recorded with, see `MovieSettings::apply`.
+    pub fn replay_movie(&mut self, movie: Movie) -> Result<(), String> {
+        let differences = movie.settings.differences(&self.settings);
+        if !differences.is_empty() {
+            return Err(format!("Movie was recorded with different settings: {}", differences.join(", ")));
+        }
+
+        self.reseed(movie.seed);
+        self.movie = Some(MovieSession::Replaying(MoviePlayer::new(movie)));
+        Ok(())
     }
 
-    pub fn run(&mut self) {
+    /// Records the executed instructions its filter selects
+    pub fn record_trace(&mut self, recorder: TraceRecorder) {
+        self.tracer = Some(recorder);
+    }
+
+    pub fn run(&mut self) -> Result<(), String> {
         assert!(self.state == VmState::CREATED);
 
         let (sender, receiver) = mpsc::channel();
-        let (sender2, receiver2) = mpsc::channel();
 
-        RemoteDbg::init(sender, receiver2);
+        // Debuggers attaching right away already find the VM in its initial state
+        if self.settings.paused {
+            self.stop(StopReason::Requested);
+        } else {
+            self.resume();
+        }
 
-        self.state = VmState::RUNNING;
+        if let Some(ref settings) = self.settings.debugger {
+            self.debugger = Some(RemoteDbg::init(settings, sender)?);
+        }
 
         loop {
-            match receiver.try_recv() {
-                Ok(message) => self.handle_dbg_message(message, &sender2),
-                Err(_) => {}
+            let frame_start = Instant::now();
+
+            while let Ok(message) = receiver.try_recv() {
+                self.handle_dbg_message(message);
             }
 
             if self.state == VmState::RUNNING {
-                self.step();
+                if self.frame().is_err() {
+                    break;
+                }
+            }
+
+            // Without a debugger nothing could resume the VM, e.g. after a CPU fault
+            if self.state == VmState::STOPPED && self.debugger.is_none() {
+                break;
+            }
+
+            let elapsed = frame_start.elapsed();
+            if elapsed < FRAME_DURATION {
+                thread::sleep(FRAME_DURATION - elapsed);
+            }
+        }
+
+        if let Some(movie) = self.movie.take() {
+            movie.finish(&mut self.interconnect);
+        }
+
+        if let Some(tracer) = self.tracer.take() {
+            if let Err(e) = tracer.finish() {
+                error!("Could not write the trace: {}", e);
+            }
+        }
+
+        if let Some(ref debugger) = self.debugger {
+            debugger.publish(Event::Exited);
+        }
+
+        match self.stop_reason {
+            Some(StopReason::Fault(ref fault)) if self.debugger.is_none() => Err(format!("Stopped on a CPU fault: {}", fault)),
+            _ => Ok(())
+        }
+    }
+
+    fn frame(&mut self) -> Result<(), ()> {
+        // A frame the debugger stopped continues where it left off
+        if self.frame_cycle == 0 {
+            self.begin_frame()?;
+        }
+
+        while self.state == VmState::RUNNING && self.frame_cycle < self.settings.instructions_per_frame {
+            let context = VmContext { cpu: &self.cpu, interconnect: &self.interconnect };
+            if let Some(breakpoint) = self.breakpoints.check(self.cpu.pc(), &context) {
+                info!("Breakpoint {} hit at {:#x}", breakpoint.id, breakpoint.addr);
+                self.stop(StopReason::Breakpoint { id: breakpoint.id, addr: breakpoint.addr });
+                break;
+            }
+
+            if let Some(reason) = self.execute_instruction() {
+                self.stop(reason);
+            }
+        }
+
+        if self.frame_cycle < self.settings.instructions_per_frame {
+            self.interconnect.display().draw();
+        } else {
+            self.end_frame();
+        }
+
+        Ok(())
+    }
+
+    fn begin_frame(&mut self) -> Result<(), ()> {
+        let mut keypad_state = self.interconnect.keypad().poll()?;
+
+        match self.movie {
+            Some(MovieSession::Replaying(ref mut player)) => keypad_state = player.next_frame().ok_or(())?,
+            Some(MovieSession::Recording(ref mut recorder)) => recorder.record_frame(&keypad_state),
+            None => {}
+        };
+
+        self.interconnect.keypad().update_state(keypad_state);
+        Ok(())
+    }
+
+    /// Ticks the timers and presents the frame, like a vblank
+    fn end_frame(&mut self) {
+        self.frame_cycle = 0;
+        self.cpu.tick_timers();
+        self.interconnect.display().draw();
+
+        if let Some(ref mut movie) = self.movie {
+            movie.end_frame(&mut self.interconnect);
+        }
+
+        // Keeps the trace readable when the VM gets killed
+        let flushed = match self.tracer {
+            Some(ref mut tracer) => tracer.flush(),
+            None => Ok(())
+        };
+        if let Err(e) = flushed {
+            error!("Could not write the trace, stopped recording: {}", e);
+            self.tracer = None;
+        }
+
+        if self.step_target == Some(StepTarget::Frame) {
+            self.stop(StopReason::Step);
+        }
+    }
+
+    /// Starts a debugger step, which completes while the VM runs
+    fn step(&mut self, mode: StepMode) -> Result<(), DbgError> {
+        if self.state != VmState::STOPPED {
+            return Err(DbgError::InvalidState("VM has to be stopped to step".to_owned()));
+        }
+
+        let pc = self.cpu.pc();
+        let target = StepTarget::new(mode, pc, self.cpu.register(Register::Sp) as u8, self.interconnect.read_word(pc))?;
+
+        self.resume();
+        self.step_target = Some(target);
+        Ok(())
+    }
+
+    /// Executes one instruction, returning why execution has to stop after it
+    fn execute_instruction(&mut self) -> Option<StopReason> {
+        let pc = self.cpu.pc();
+        let opcode = self.interconnect.read_word(pc);
+
+        let recording = self.history.is_enabled();
+        let registers = self.cpu.registers();
+        let frame_cycle = self.frame_cycle;
+        // Only clearing the screen and drawing change pixels, and only `CXNN` draws random numbers
+        let vram = if recording && (opcode == 0x00E0 || opcode & 0xF000 == 0xD000) {
+            Some(*self.interconnect.display().vram())
+        } else {
+            None
+        };
+        let rng = if recording && opcode & 0xF000 == 0xC000 { Some(self.cpu.rng_state()) } else { None };
+
+        let traced = match self.tracer {
+            Some(ref mut tracer) => tracer.wants(self.cycle, pc),
+            None => false
+        };
+        let cpu_before = if traced { Some(self.cpu.snapshot()) } else { None };
+
+        if recording || traced {
+            self.interconnect.start_journal();
+        }
+
+        let result = self.cpu.execute_cycle(&mut self.interconnect);
+        let memory = self.interconnect.take_journal();
+
+        if let Err(fault) = result {
+            error!("CPU fault: {}", fault);
+            return Some(StopReason::Fault(fault));
+        }
+        self.frame_cycle += 1;
+
+        if let Some(ref before) = cpu_before {
+            self.trace_instruction(pc, opcode, before, &memory);
+        }
+        self.cycle += 1;
+
+        let watch_hit = self.interconnect.watchpoints().take_hit(pc, opcode);
+
+        if recording {
+            let vram = vram.map_or(vec![], |before| history::changed_pixels(&before, self.interconnect.display().vram()));
+            self.history.push(HistoryEntry { registers, frame_cycle, rng, memory, vram, watch_hit: watch_hit.clone() });
+        }
+
+        if let Some(hit) = watch_hit {
+            info!("Watchpoint {} hit by {:04x} at {:#x}: {:?} of {:#x}, {:#x} -> {:#x}",
+                  hit.id, hit.opcode, hit.pc, hit.access, hit.addr, hit.old, hit.new);
+            return Some(StopReason::Watchpoint(hit));
+        }
+
+        let (pc, sp) = (self.cpu.pc(), self.cpu.register(Register::Sp) as u8);
+        let step_complete = match self.step_target {
+            Some(ref mut target) => target.after_instruction(pc, sp),
+            None => false
+        };
+
+        if step_complete { Some(StopReason::Step) } else { None }
+    }
+
+    fn trace_instruction(&mut self, pc: u16, opcode: u16, before: &CpuSnapshot, memory: &[(u16, u8)]) {
+        let writes = memory.iter()
+            .map(|&(addr, old)| MemoryWrite { addr, old, new: self.interconnect.peek(addr) })
+            .collect();
+        let record = TraceRecord::new(self.cycle, pc, opcode, before, &self.cpu.snapshot(), writes);
+
+        let result = match self.tracer {
+            Some(ref mut tracer) => tracer.record(&record),
+            None => Ok(())
+        };
+
+        if let Err(e) = result {
+            error!("Could not write the trace, stopped recording: {}", e);
+            self.tracer = None;
+        }
+    }
+
+    /// Checks that the VM can execute backwards right now
+    fn check_reversible(&self) -> Result<(), DbgError> {
+        if self.state != VmState::STOPPED {
+            return Err(DbgError::InvalidState("VM has to be stopped to step back".to_owned()));
+        }
+
+        if !self.history.is_enabled() {
+            return Err(DbgError::InvalidState("execution history is disabled".to_owned()));
+        }
+
+        // Movies would get out of sync with frames being executed again
+        if self.movie.is_some() {
+            return Err(DbgError::InvalidState("cannot step back while a movie is recorded or replayed".to_owned()));
+        }
+
+        Ok(())
+    }
+
+    /// Restores the state from before the most recently executed instruction, returns false without any history left
+    fn undo_instruction(&mut self) -> bool {
+        let entry = match self.history.pop() {
+            Some(entry) => entry,
+            None => return false
+        };
+
+        for &(addr, old) in entry.memory.iter().rev() {
+            self.interconnect.load(addr, &[old]);
+        }
+
+        {
+            let vram = self.interconnect.display().vram();
+            for &(pixel, old) in &entry.vram {
+                vram[pixel as usize / CHIP8_WIDTH][pixel as usize % CHIP8_WIDTH] = old;
+            }
+        }
+
+        self.cpu.restore_registers(&entry.registers);
+        if let Some(rng) = entry.rng {
+            self.cpu.set_rng(rng.restore());
+        }
+        self.frame_cycle = entry.frame_cycle;
+        self.cycle -= 1;
+        true
+    }
+
+    /// Undoes `count` instructions, stopping early when the history runs out
+    fn reverse_step(&mut self, count: u32) -> Result<(), DbgError> {
+        self.check_reversible()?;
+
+        if count == 0 {
+            return Err(DbgError::InvalidArgument("has to step back at least one instruction".to_owned()));
+        }
+
+        let mut reason = StopReason::Step;
+        for _ in 0..count {
+            if !self.undo_instruction() {
+                reason = StopReason::HistoryStart;
+                break;
             }
         }
+
+        self.interconnect.display().draw();
+        self.stop(reason);
+        Ok(())
     }
 
-    fn step(&mut self) {
-        match self.interconnect.keypad().poll() {
-            Err(_) => process::exit(0),
-            Ok(keypad_state) => {
-                self.interconnect.keypad().update_state(keypad_state);
-                self.cpu.execute_cycle(&mut self.interconnect);
+    /// Undoes instructions until PC reaches a breakpoint, or the undone instruction accessed watched memory
+    fn reverse_continue(&mut self) -> Result<(), DbgError> {
+        self.check_reversible()?;
+
+        let watchpoints = self.interconnect.watchpoints().list();
+        let reason = loop {
+            let hit = match self.history.last() {
+                Some(entry) => entry.watchpoint_hit(&watchpoints, &self.interconnect),
+                None => break StopReason::HistoryStart
+            };
+
+            self.undo_instruction();
+            if let Some(hit) = hit {
+                info!("Watchpoint {} hit backwards by {:04x} at {:#x}", hit.id, hit.opcode, hit.pc);
+                break StopReason::Watchpoint(hit);
             }
+
+            let context = VmContext { cpu: &self.cpu, interconnect: &self.interconnect };
+            if let Some(breakpoint) = self.breakpoints.find(self.cpu.pc(), &context) {
+                info!("Breakpoint {} hit backwards at {:#x}", breakpoint.id, breakpoint.addr);
+                break StopReason::Breakpoint { id: breakpoint.id, addr: breakpoint.addr };
+            }
+        };
+
+        self.interconnect.display().draw();
+        self.stop(reason);
+        Ok(())
+    }
+
+    fn check_resettable(&self) -> Result<(), DbgError> {
+        // Movies would get out of sync with the program starting over or drawing other random numbers
+        if self.movie.is_some() {
+            return Err(DbgError::InvalidState("cannot reset or reseed while a movie is recorded or replayed".to_owned()));
+        }
+
+        Ok(())
+    }
+
+    /// Starts the program over without changing whether the VM runs; breakpoints and watchpoints are kept
+    fn reset(&mut self, mode: ResetMode) -> Result<(), DbgError> {
+        self.check_resettable()?;
+
+        self.cpu.reset();
+        self.cpu.set_pc(self.settings.memory_map.rom_location);
+        self.interconnect.display().clear();
+        self.interconnect.keypad().update_state([false; 16]);
+
+        if mode == ResetMode::Hard {
+            self.interconnect.clear_ram();
+            Chip8::load_fonts(&mut self.interconnect, &self.settings);
+            self.interconnect.load(self.settings.memory_map.rom_location, &self.rom);
+        }
+
+        self.history.clear();
+        self.step_target = None;
+        self.frame_cycle = 0;
+        self.interconnect.display().draw();
+        info!("{:?} reset", mode);
+
+        // Lets attached debuggers pick up the new state
+        if self.state == VmState::STOPPED {
+            self.stop(StopReason::Requested);
         }
+        Ok(())
+    }
+
+    /// Reads a ROM file the debugger asked the VM to load
+    fn read_rom(path: &Path) -> Result<Vec<u8>, DbgError> {
+        fs::read(path).map_err(|e| DbgError::InvalidArgument(format!("Could not read ROM {:?}: {}", path, e)))
+    }
+
+    /// Hard resets with another ROM, keeping the current one if the new one does not fit
+    fn replace_rom(&mut self, rom: Vec<u8>, name: String, path: Option<PathBuf>) -> Result<(), DbgError> {
+        self.check_resettable()?;
+        self.check_rom(&rom).map_err(|e| DbgError::InvalidArgument(e.to_string()))?;
+
+        info!("Loaded ROM {} ({} bytes)", name, rom.len());
+        self.rom = rom;
+        self.rom_path = path;
+        self.reset(ResetMode::Hard)
+    }
+
+    fn stop(&mut self, reason: StopReason) {
+        self.state = VmState::STOPPED;
+        self.step_target = None;
+
+        if let Some(ref debugger) = self.debugger {
+            debugger.publish(Event::Stopped { reason: reason.clone(), cpu: self.cpu.snapshot() });
+        }
+        self.stop_reason = Some(reason);
+    }
+
+    fn resume(&mut self) {
+        self.breakpoints.resume_from(self.cpu.pc());
+        self.state = VmState::RUNNING;
+        self.step_target = None;
+        self.stop_reason = None;
     }
 
     // TODO: Extract debugging stuff
-    fn handle_dbg_message(&mut self, message: DbgMessage, sender: &mpsc::Sender<Chip8Snapshots>) {
-        debug!("Handling debugger message: {:?}", message);
+    fn handle_dbg_message(&mut self, message: DbgMessage) {
+        debug!("Handling debugger command: {:?}", message.command);
+
+        let result = self.execute_dbg_command(message.command);
+        if message.reply.send(result).is_err() {
+            warn!("Debugger went away before receiving the reply");
+        }
+    }
+
+    fn execute_dbg_command(&mut self, command: Command) -> Result<Response, DbgError> {
+        match command {
+            Command::Hello { .. } => return Ok(Response::Hello { version: PROTOCOL_VERSION }),
+            Command::Start => self.resume(),
+            Command::Stop => self.stop(StopReason::Requested),
+            Command::Reset(mode) => self.reset(mode)?,
+            Command::LoadRom(RomSource::Path(path)) => {
+                let path = PathBuf::from(path);
+                let rom = Chip8::read_rom(&path)?;
+                self.replace_rom(rom, format!("{:?}", path), Some(path))?;
+            },
+            Command::LoadRom(RomSource::Bytes { name, data }) => self.replace_rom(data, format!("{} (uploaded)", name), None)?,
+            Command::ReloadRom => {
+                let path = match self.rom_path.clone() {
+                    Some(path) => path,
+                    None => return Err(DbgError::InvalidState("ROM was uploaded by a debugger, there is no file to reload".to_owned()))
+                };
+                let rom = Chip8::read_rom(&path)?;
+                self.replace_rom(rom, format!("{:?}", path), Some(path))?;
+            },
+            Command::Reseed(seed) => {
+                self.check_resettable()?;
+                self.reseed(seed);
+            },
+            Command::Cpu => return Ok(Response::Cpu(self.cpu.snapshot())),
+            Command::Status => return Ok(Response::Status(VmStatus {
+                running: self.state == VmState::RUNNING,
+                stop_reason: self.stop_reason.clone()
+            })),
+            Command::AddBreakpoint { addr, condition } => return Ok(Response::Breakpoint(self.breakpoints.add(addr, condition, None))),
+            Command::AddTracepoint { addr, condition, message } => {
+                return Ok(Response::Breakpoint(self.breakpoints.add(addr, condition, Some(message))));
+            },
+            Command::IgnoreBreakpoint { id, count } => return self.breakpoints.ignore(id, count).map(Response::Breakpoint),
+            Command::DeleteBreakpoint(id) => {
+                if !self.interconnect.watchpoints().delete(id) {
+                    self.breakpoints.delete(id)?;
+                }
+            },
+            Command::EnableBreakpoint { id, enabled } => {
+                if let Some(watchpoint) = self.interconnect.watchpoints().set_enabled(id, enabled) {
+                    return Ok(Response::Watchpoint(watchpoint));
+                }
+                return self.breakpoints.set_enabled(id, enabled).map(Response::Breakpoint);
+            },
+            Command::ListBreakpoints => return Ok(Response::Breakpoints(self.breakpoints.list())),
+            Command::AddWatchpoint { start, end, access } => {
+                if start > end {
+                    return Err(DbgError::InvalidArgument(format!("watched range {:#x}-{:#x} is empty", start, end)));
+                }
+
+                let id = self.breakpoints.allocate_id();
+                return Ok(Response::Watchpoint(self.interconnect.watchpoints().add(id, start, end, access)));
+            },
+            Command::ListWatchpoints => return Ok(Response::Watchpoints(self.interconnect.watchpoints().list())),
+            Command::Font => {
+                let memory_map = self.settings.memory_map;
+                let font = &self.settings.font;
 
-        match message {
-            DbgMessage::START => self.state = VmState::RUNNING,
-            DbgMessage::STOP => self.state = VmState::STOPPED,
-            DbgMessage::RESTART => self.cpu = Cpu::new(),
-            DbgMessage::CPU => sender.send(Chip8Snapshots::CPU(self.cpu.snapshot())).unwrap(),
-            DbgMessage::MEM(addr, size) => {
-                let mut v = vec![];
+                return Ok(Response::Font(FontSnapshot {
+                    name: font.name.clone(),
+                    location: memory_map.fonts_location,
+                    size: font.small.len(),
+                    big_location: font.big.as_ref().map(|_| memory_map.big_fonts_location),
+                    big_size: font.big.as_ref().map_or(0, |big| big.len())
+                }));
+            },
+            Command::MemoryMap => return Ok(Response::MemoryMap(MemoryMapSnapshot {
+                ram_size: self.settings.memory_map.ram_size,
+                rom_location: self.settings.memory_map.rom_location,
+                rom_size: self.rom.len()
+            })),
+            Command::Keypad => {
+                let mut keys = [false; 16];
+                for (key, pressed) in keys.iter_mut().enumerate() {
+                    *pressed = self.interconnect.keypad().is_key_pressed(key);
+                }
+                return Ok(Response::Keypad(keys));
+            },
+            Command::Mem { addr, size } => {
+                if size > self.settings.memory_map.ram_size {
+                    return Err(DbgError::InvalidArgument(format!("{} bytes exceed the {} bytes of memory", size, self.settings.memory_map.ram_size)));
+                }
+
+                let mut v = Vec::with_capacity(size);
 
                 for i in 0..size {
-                    v.push(self.interconnect.read_byte(addr + i as u16));
+                    v.push(self.interconnect.peek(addr.wrapping_add(i as u16)));
                 }
 
-                sender.send(Chip8Snapshots::MEM(v));
+                return Ok(Response::Mem(v));
             },
-            DbgMessage::STEP => {
-                assert!(self.state == VmState::STOPPED);
-                self.step();
-            }
+            Command::SetRegister { register, value } => {
+                let max = match register {
+                    Register::I | Register::Pc => 0xFFFF,
+                    Register::Sp => 16,
+                    _ => 0xFF
+                };
+
+                if value > max {
+                    return Err(DbgError::InvalidArgument(format!("{:#x} does not fit into {}", value, register)));
+                }
+                self.cpu.set_register(register, value);
+            },
+            Command::SetStack { slot, value } => {
+                if slot >= 16 {
+                    return Err(DbgError::InvalidArgument(format!("there is no stack slot {}", slot)));
+                }
+                self.cpu.set_stack(slot as usize, value);
+            },
+            Command::Poke { addr, bytes } => {
+                if bytes.len() > self.settings.memory_map.ram_size {
+                    return Err(DbgError::InvalidArgument(format!("{} bytes do not fit into memory", bytes.len())));
+                }
+                self.interconnect.load(addr, &bytes);
+            },
+            Command::Fill { start, end, value } => {
+                if start > end {
+                    return Err(DbgError::InvalidArgument(format!("range {:#x}-{:#x} is empty", start, end)));
+                }
+                self.interconnect.load(start, &vec![value; (end - start) as usize + 1]);
+            },
+            Command::Step(mode) => self.step(mode)?,
+            Command::ReverseStep(count) => self.reverse_step(count)?,
+            Command::ReverseContinue => self.reverse_continue()?,
+            Command::History => return Ok(Response::History(self.history.info()))
+        };
+
+        Ok(Response::Done)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Chip8;
+    use chip8::{Display, Keypad, Settings};
+    use chip8::arch::rng::ScriptedSource;
+    use chip8::settings::DebuggerSettings;
+    use chip8_protocol::{Command, DbgError};
+    use chip8_protocol::expr::Register;
+    use std::path::Path;
+
+    fn chip8(program: &[u8]) -> Chip8 {
+        let debugger = DebuggerSettings {
+            address: "127.0.0.1:0".to_owned(),
+            token: None,
+            gdb_address: None,
+            history_budget: 1 << 20
+        };
+        let mut chip8 = Chip8::new(Display::headless(), Keypad::headless(), Settings { debugger: Some(debugger), ..Settings::default() });
+        chip8.load_rom(&program.to_vec()).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn injected_random_source_drives_cxnn() {
+        let mut chip8 = chip8(&[0xC0, 0xFF, 0xC1, 0xFF]);
+        chip8.set_random_source(Box::new(ScriptedSource::new(vec![3, 7])));
+
+        assert!(chip8.execute_instruction().is_none());
+        assert!(chip8.execute_instruction().is_none());
+
+        assert_eq!(chip8.seed(), None);
+        assert_eq!(chip8.cpu.register(Register::V(0)), 3);
+        assert_eq!(chip8.cpu.register(Register::V(1)), 7);
+    }
+
+    #[test]
+    fn stepping_back_over_cxnn_draws_the_same_number_again() {
+        let mut chip8 = chip8(&[0xC0, 0xFF, 0xC1, 0xFF]);
+        chip8.set_random_source(Box::new(ScriptedSource::new(vec![3, 7, 11])));
+
+        chip8.execute_instruction();
+        chip8.execute_instruction();
+        assert!(chip8.undo_instruction());
+        chip8.execute_instruction();
+
+        assert_eq!(chip8.cpu.register(Register::V(1)), 7);
+    }
+
+    #[test]
+    fn movies_can_not_be_reseeded() {
+        let mut chip8 = chip8(&[0xC0, 0xFF]);
+        chip8.reseed(5);
+        chip8.record_movie(Path::new("reseed.movie"), &[0xC0, 0xFF]);
+
+        match chip8.execute_dbg_command(Command::Reseed(6)) {
+            Err(DbgError::InvalidState(_)) => {},
+            other => panic!("reseeding a recorded movie: {:?}", other)
         }
+        assert_eq!(chip8.seed(), Some(5));
     }
 }
@@ -0,0 +1,326 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde_json;
+
+use super::keypad::{Keys, KeypadState};
+
+const DEFAULT_DEAD_ZONE: i16 = 8000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PadButton {
+    A,
+    B,
+    X,
+    Y,
+    Back,
+    Guide,
+    Start,
+    LeftStick,
+    RightStick,
+    LeftShoulder,
+    RightShoulder,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PadAxis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    TriggerLeft,
+    TriggerRight
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AxisDirection {
+    Negative,
+    Positive
+}
+
+/// Anything on a game controller that can be bound to a keypad key
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PadInput {
+    Button(PadButton),
+    Axis(PadAxis, AxisDirection)
+}
+
+/// Backend independent controller event; `i32` is the controller instance id
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PadEvent {
+    Connected(i32),
+    Disconnected(i32),
+    ButtonDown(i32, PadButton),
+    ButtonUp(i32, PadButton),
+    AxisMotion(i32, PadAxis, i16)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct PadBinding {
+    pub input: PadInput,
+    pub key: Keys
+}
+
+/// Bindings for every player; controllers are assigned to players in the order they get connected.
+/// Controllers beyond the configured players reuse the bindings of the last one.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ControllerMapping {
+    #[serde(default = "default_dead_zone")]
+    pub dead_zone: i16,
+    pub players: Vec<Vec<PadBinding>>
+}
+
+fn default_dead_zone() -> i16 {
+    DEFAULT_DEAD_ZONE
+}
+
+impl Default for ControllerMapping {
+    fn default() -> Self {
+        let bind = |input, key| PadBinding { input, key };
+
+        ControllerMapping {
+            dead_zone: DEFAULT_DEAD_ZONE,
+            players: vec![vec![
+                bind(PadInput::Button(PadButton::DPadUp), Keys::K2),
+                bind(PadInput::Button(PadButton::DPadDown), Keys::K8),
+                bind(PadInput::Button(PadButton::DPadLeft), Keys::K4),
+                bind(PadInput::Button(PadButton::DPadRight), Keys::K6),
+                bind(PadInput::Axis(PadAxis::LeftY, AxisDirection::Negative), Keys::K2),
+                bind(PadInput::Axis(PadAxis::LeftY, AxisDirection::Positive), Keys::K8),
+                bind(PadInput::Axis(PadAxis::LeftX, AxisDirection::Negative), Keys::K4),
+                bind(PadInput::Axis(PadAxis::LeftX, AxisDirection::Positive), Keys::K6),
+                bind(PadInput::Button(PadButton::A), Keys::K5),
+                bind(PadInput::Button(PadButton::B), Keys::K0),
+                bind(PadInput::Button(PadButton::X), Keys::K1),
+                bind(PadInput::Button(PadButton::Y), Keys::K3),
+                bind(PadInput::Button(PadButton::Back), Keys::KE),
+                bind(PadInput::Button(PadButton::Start), Keys::KF),
+            ]]
+        }
+    }
+}
+
+impl ControllerMapping {
+    pub fn load(path: &Path) -> io::Result<ControllerMapping> {
+        let content = fs::read_to_string(path)?;
+        serde_json::from_str(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Looks for `<rom>.pad.json` next to the ROM and falls back to the default bindings
+    pub fn for_rom(rom_path: &Path) -> ControllerMapping {
+        let mut mapping_path = rom_path.as_os_str().to_owned();
+        mapping_path.push(".pad.json");
+        let mapping_path = Path::new(&mapping_path);
+
+        if !mapping_path.exists() {
+            return ControllerMapping::default();
+        }
+
+        match ControllerMapping::load(mapping_path) {
+            Ok(mapping) => {
+                info!("Using controller mapping from: {:?}", mapping_path);
+                mapping
+            },
+            Err(err) => {
+                warn!("Could not load controller mapping from {:?}: {}", mapping_path, err);
+                ControllerMapping::default()
+            }
+        }
+    }
+
+    fn bindings(&self, player: usize) -> &[PadBinding] {
+        match self.players.get(player).or(self.players.last()) {
+            Some(bindings) => bindings,
+            None => &[]
+        }
+    }
+}
+
+/// Translates controller events into keypad state
+pub struct Controllers {
+    mapping: ControllerMapping,
+    players: Vec<Option<i32>>,
+    active_inputs: HashMap<i32, HashSet<PadInput>>
+}
+
+impl Controllers {
+    pub fn new(mapping: ControllerMapping) -> Self {
+        Controllers {
+            mapping,
+            players: vec![],
+            active_inputs: HashMap::new()
+        }
+    }
+
+    pub fn player(&self, instance_id: i32) -> Option<usize> {
+        self.players.iter().position(|p| *p == Some(instance_id))
+    }
+
+    pub fn handle_event(&mut self, event: PadEvent) {
+        match event {
+            PadEvent::Connected(id) => {
+                if self.player(id).is_some() {
+                    return;
+                }
+
+                let player = match self.players.iter().position(|p| p.is_none()) {
+                    Some(free) => { self.players[free] = Some(id); free },
+                    None => { self.players.push(Some(id)); self.players.len() - 1 }
+                };
+
+                info!("Controller {} connected as player {}", id, player + 1);
+                self.active_inputs.insert(id, HashSet::new());
+            },
+            PadEvent::Disconnected(id) => {
+                if let Some(player) = self.player(id) {
+                    info!("Controller {} (player {}) disconnected", id, player + 1);
+                    self.players[player] = None;
+                }
+                self.active_inputs.remove(&id);
+            },
+            PadEvent::ButtonDown(id, button) => {
+                if let Some(inputs) = self.active_inputs.get_mut(&id) {
+                    inputs.insert(PadInput::Button(button));
+                }
+            },
+            PadEvent::ButtonUp(id, button) => {
+                if let Some(inputs) = self.active_inputs.get_mut(&id) {
+                    inputs.remove(&PadInput::Button(button));
+                }
+            },
+            PadEvent::AxisMotion(id, axis, value) => {
+                let dead_zone = self.mapping.dead_zone;
+
+                if let Some(inputs) = self.active_inputs.get_mut(&id) {
+                    inputs.remove(&PadInput::Axis(axis, AxisDirection::Negative));
+                    inputs.remove(&PadInput::Axis(axis, AxisDirection::Positive));
+
+                    if value > dead_zone {
+                        inputs.insert(PadInput::Axis(axis, AxisDirection::Positive));
+                    } else if value < -dead_zone {
+                        inputs.insert(PadInput::Axis(axis, AxisDirection::Negative));
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn state(&self) -> KeypadState {
+        let mut state = [false; 16];
+
+        for (player, slot) in self.players.iter().enumerate() {
+            let inputs = match *slot {
+                Some(id) => &self.active_inputs[&id],
+                None => continue
+            };
+
+            for binding in self.mapping.bindings(player) {
+                if inputs.contains(&binding.input) {
+                    state[binding.key as usize] = true;
+                }
+            }
+        }
+
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ControllerMapping, Controllers, PadAxis, PadButton, PadEvent};
+    use chip8::keypad::{KeypadState, Keys};
+    use serde_json;
+
+    fn pressed(state: KeypadState) -> Vec<usize> {
+        (0..16).filter(|&key| state[key]).collect()
+    }
+
+    fn keys(keys: &[Keys]) -> Vec<usize> {
+        let mut keys: Vec<usize> = keys.iter().map(|&key| key as usize).collect();
+        keys.sort();
+        keys
+    }
+
+    fn pong() -> ControllerMapping {
+        serde_json::from_str(r#"{
+            "players": [
+                [{"input": {"Button": "DPadUp"}, "key": "K1"}, {"input": {"Axis": ["LeftY", "Positive"]}, "key": "K4"}],
+                [{"input": {"Button": "DPadUp"}, "key": "KC"}, {"input": {"Axis": ["LeftY", "Positive"]}, "key": "KD"}]
+            ]
+        }"#).unwrap()
+    }
+
+    #[test]
+    fn buttons_and_axes_map_to_keys() {
+        let mut controllers = Controllers::new(ControllerMapping::default());
+        controllers.handle_event(PadEvent::Connected(3));
+
+        controllers.handle_event(PadEvent::ButtonDown(3, PadButton::A));
+        controllers.handle_event(PadEvent::AxisMotion(3, PadAxis::LeftX, -20000));
+        assert_eq!(pressed(controllers.state()), keys(&[Keys::K5, Keys::K4]));
+
+        controllers.handle_event(PadEvent::ButtonUp(3, PadButton::A));
+        controllers.handle_event(PadEvent::AxisMotion(3, PadAxis::LeftX, 20000));
+        assert_eq!(pressed(controllers.state()), keys(&[Keys::K6]));
+    }
+
+    #[test]
+    fn axes_inside_the_dead_zone_release_their_keys() {
+        let mut controllers = Controllers::new(ControllerMapping::default());
+        controllers.handle_event(PadEvent::Connected(0));
+
+        controllers.handle_event(PadEvent::AxisMotion(0, PadAxis::LeftY, 8001));
+        assert_eq!(pressed(controllers.state()), keys(&[Keys::K8]));
+
+        controllers.handle_event(PadEvent::AxisMotion(0, PadAxis::LeftY, 8000));
+        assert_eq!(pressed(controllers.state()), keys(&[]));
+
+        controllers.handle_event(PadEvent::AxisMotion(0, PadAxis::LeftY, -8001));
+        assert_eq!(pressed(controllers.state()), keys(&[Keys::K2]));
+
+        controllers.handle_event(PadEvent::AxisMotion(0, PadAxis::LeftY, -100));
+        assert_eq!(pressed(controllers.state()), keys(&[]));
+    }
+
+    #[test]
+    fn hot_plugged_controllers_take_free_players() {
+        let mut controllers = Controllers::new(pong());
+        controllers.handle_event(PadEvent::Connected(10));
+        controllers.handle_event(PadEvent::Connected(11));
+        controllers.handle_event(PadEvent::ButtonDown(10, PadButton::DPadUp));
+        assert_eq!(controllers.player(11), Some(1));
+
+        controllers.handle_event(PadEvent::Disconnected(10));
+        assert_eq!(controllers.player(10), None);
+        assert_eq!(pressed(controllers.state()), keys(&[]));
+
+        controllers.handle_event(PadEvent::ButtonDown(10, PadButton::DPadUp));
+        assert_eq!(pressed(controllers.state()), keys(&[]));
+
+        controllers.handle_event(PadEvent::Connected(12));
+        controllers.handle_event(PadEvent::ButtonDown(12, PadButton::DPadUp));
+        assert_eq!(controllers.player(12), Some(0));
+        assert_eq!(pressed(controllers.state()), keys(&[Keys::K1]));
+    }
+
+    #[test]
+    fn two_controllers_drive_separate_key_sets() {
+        let mut controllers = Controllers::new(pong());
+        controllers.handle_event(PadEvent::Connected(1));
+        controllers.handle_event(PadEvent::Connected(2));
+
+        controllers.handle_event(PadEvent::ButtonDown(1, PadButton::DPadUp));
+        controllers.handle_event(PadEvent::AxisMotion(2, PadAxis::LeftY, 30000));
+        assert_eq!(pressed(controllers.state()), keys(&[Keys::K1, Keys::KD]));
+
+        controllers.handle_event(PadEvent::ButtonDown(2, PadButton::DPadUp));
+        controllers.handle_event(PadEvent::ButtonUp(1, PadButton::DPadUp));
+        assert_eq!(pressed(controllers.state()), keys(&[Keys::KC, Keys::KD]));
+    }
+}
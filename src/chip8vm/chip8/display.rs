@@ -4,27 +4,41 @@ use sdl2::rect::Rect;
 use sdl2::render::Canvas;
 use sdl2::video::Window;
 
-// TODO: extract to config
-const CHIP8_WIDTH: usize = 64;
-const CHIP8_HEIGHT: usize = 32;
-const SCALE: usize = 12;
-const WINDOW_WIDTH: usize = CHIP8_WIDTH * SCALE;
-const WINDOW_HEIGHT: usize = CHIP8_HEIGHT * SCALE;
+pub const CHIP8_WIDTH: usize = 64;
+pub const CHIP8_HEIGHT: usize = 32;
+pub const DEFAULT_SCALE: usize = 12;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Palette {
+    pub foreground: [u8; 3],
+    pub background: [u8; 3]
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            foreground: [255, 255, 255],
+            background: [0, 0, 0]
+        }
+    }
+}
 
 // TODO: make it more generic
 pub struct Display {
     // TODO: move vram to Interconnect?
     vram: [[u8; CHIP8_WIDTH]; CHIP8_HEIGHT],
-    canvas: Canvas<Window>,
+    canvas: Option<Canvas<Window>>,
+    scale: usize,
+    palette: Palette
 }
 
 impl Display {
-    pub fn new(sdl_context: &sdl2::Sdl) -> Display {
+    pub fn new(sdl_context: &sdl2::Sdl, scale: usize, palette: Palette) -> Display {
         let v_ctx = sdl_context.video().unwrap();
 
         // TODO: do not handle this here!
         let window = match v_ctx
-            .window("chip8 VM", WINDOW_WIDTH as u32, WINDOW_HEIGHT as u32)
+            .window("chip8 VM", (CHIP8_WIDTH * scale) as u32, (CHIP8_HEIGHT * scale) as u32)
             .position_centered()
             .build() {
             Ok(window) => window,
@@ -40,12 +54,25 @@ impl Display {
             Err(err) => panic!("failed to create canvas: {}", err)
         };
 
+        canvas.set_draw_color(Display::color(palette.background));
         let _ = canvas.clear();
         let _ = canvas.present();
 
         Display {
             vram: [[0; CHIP8_WIDTH]; CHIP8_HEIGHT],
-            canvas
+            canvas: Some(canvas),
+            scale,
+            palette
+        }
+    }
+
+    /// Display that only keeps vram, without opening any window
+    pub fn headless() -> Display {
+        Display {
+            vram: [[0; CHIP8_WIDTH]; CHIP8_HEIGHT],
+            canvas: None,
+            scale: DEFAULT_SCALE,
+            palette: Palette::default()
         }
     }
 
@@ -54,34 +81,42 @@ impl Display {
     }
 
     pub fn clear(&mut self) {
-        self.canvas.clear();
-        self.canvas.present();
+        self.vram = [[0; CHIP8_WIDTH]; CHIP8_HEIGHT];
+    }
+
+    fn color(rgb: [u8; 3]) -> pixels::Color {
+        pixels::Color::RGB(rgb[0], rgb[1], rgb[2])
     }
 
     pub fn draw(&mut self) {
-        // TODO: enable custom color configuration
-        let black: sdl2::pixels::Color = sdl2::pixels::Color::RGB(0, 0, 0);
-        let white: sdl2::pixels::Color = sdl2::pixels::Color::RGB(255, 255, 255);
+        let canvas = match self.canvas {
+            Some(ref mut canvas) => canvas,
+            None => return
+        };
+
+        let foreground = Display::color(self.palette.foreground);
+        let background = Display::color(self.palette.background);
+        let scale = self.scale;
 
         let mut pixel = Rect::new(0, 0, 0, 0);
 
         for i in 0..CHIP8_WIDTH {
             for j in 0..CHIP8_HEIGHT {
-                pixel.set_x((i * SCALE) as i32);
-                pixel.set_y((j * SCALE) as i32);
-                pixel.set_width(SCALE as u32);
-                pixel.set_height(SCALE as u32);
+                pixel.set_x((i * scale) as i32);
+                pixel.set_y((j * scale) as i32);
+                pixel.set_width(scale as u32);
+                pixel.set_height(scale as u32);
 
                 if self.vram[j as usize][i as usize] == 1 {
-                    self.canvas.set_draw_color(white);
-                    self.canvas.fill_rect(pixel);
+                    canvas.set_draw_color(foreground);
+                    canvas.fill_rect(pixel);
                 } else {
-                    self.canvas.set_draw_color(black);
-                    self.canvas.fill_rect(pixel);
+                    canvas.set_draw_color(background);
+                    canvas.fill_rect(pixel);
                 };
             };
         };
 
-        self.canvas.present()
+        canvas.present()
     }
 }
@@ -0,0 +1,216 @@
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::mem_map::{FONTS_SIZE, BIG_FONTS_SIZE};
+
+const VIP_FONT: [u8; FONTS_SIZE] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, /* 0 */
+    0x60, 0x20, 0x20, 0x20, 0x70, /* 1 */
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, /* 2 */
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, /* 3 */
+    0xA0, 0xA0, 0xF0, 0x20, 0x20, /* 4 */
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, /* 5 */
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, /* 6 */
+    0xF0, 0x10, 0x10, 0x10, 0x10, /* 7 */
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, /* 8 */
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, /* 9 */
+    0xF0, 0x90, 0xF0, 0x90, 0x90, /* A */
+    0xF0, 0x50, 0x70, 0x50, 0xF0, /* B */
+    0xF0, 0x80, 0x80, 0x80, 0xF0, /* C */
+    0xF0, 0x50, 0x50, 0x50, 0xF0, /* D */
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, /* E */
+    0xF0, 0x80, 0xF0, 0x80, 0x80, /* F */
+];
+
+const DREAM6800_FONT: [u8; FONTS_SIZE] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, /* 0 */
+    0x40, 0x40, 0x40, 0x40, 0x40, /* 1 */
+    0xE0, 0x20, 0xE0, 0x80, 0xE0, /* 2 */
+    0xE0, 0x20, 0xE0, 0x20, 0xE0, /* 3 */
+    0x80, 0xA0, 0xA0, 0xE0, 0x20, /* 4 */
+    0xE0, 0x80, 0xE0, 0x20, 0xE0, /* 5 */
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0, /* 6 */
+    0xE0, 0x20, 0x20, 0x20, 0x20, /* 7 */
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0, /* 8 */
+    0xE0, 0xA0, 0xE0, 0x20, 0xE0, /* 9 */
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0, /* A */
+    0xC0, 0xA0, 0xE0, 0xA0, 0xC0, /* B */
+    0xE0, 0x80, 0x80, 0x80, 0xE0, /* C */
+    0xC0, 0xA0, 0xA0, 0xA0, 0xC0, /* D */
+    0xE0, 0x80, 0xE0, 0x80, 0xE0, /* E */
+    0xE0, 0x80, 0xC0, 0x80, 0x80, /* F */
+];
+
+const ETI660_FONT: [u8; FONTS_SIZE] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, /* 0 */
+    0x20, 0x20, 0x20, 0x20, 0x20, /* 1 */
+    0xE0, 0x20, 0xE0, 0x80, 0xE0, /* 2 */
+    0xE0, 0x20, 0xE0, 0x20, 0xE0, /* 3 */
+    0xA0, 0xA0, 0xE0, 0x20, 0x20, /* 4 */
+    0xE0, 0x80, 0xE0, 0x20, 0xE0, /* 5 */
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0, /* 6 */
+    0xE0, 0x20, 0x20, 0x20, 0x20, /* 7 */
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0, /* 8 */
+    0xE0, 0xA0, 0xE0, 0x20, 0xE0, /* 9 */
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0, /* A */
+    0x80, 0x80, 0xE0, 0xA0, 0xE0, /* B */
+    0xE0, 0x80, 0x80, 0x80, 0xE0, /* C */
+    0x20, 0x20, 0xE0, 0xA0, 0xE0, /* D */
+    0xE0, 0x80, 0xE0, 0x80, 0xE0, /* E */
+    0xE0, 0x80, 0xC0, 0x80, 0x80, /* F */
+];
+
+/// Small font of SCHIP and Octo, also the one this VM always used
+const SCHIP_FONT: [u8; FONTS_SIZE] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, /* 0 */
+    0x20, 0x60, 0x20, 0x20, 0x70, /* 1 */
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, /* 2 */
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, /* 3 */
+    0x90, 0x90, 0xF0, 0x10, 0x10, /* 4 */
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, /* 5 */
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, /* 6 */
+    0xF0, 0x10, 0x20, 0x40, 0x40, /* 7 */
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, /* 8 */
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, /* 9 */
+    0xF0, 0x90, 0xF0, 0x90, 0x90, /* A */
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, /* B */
+    0xF0, 0x80, 0x80, 0x80, 0xF0, /* C */
+    0xE0, 0x90, 0x90, 0x90, 0xE0, /* D */
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, /* E */
+    0xF0, 0x80, 0xF0, 0x80, 0x80, /* F */
+];
+
+/// SCHIP 1.1 only defines big digits, A-F are left blank
+const SCHIP_BIG_FONT: [u8; BIG_FONTS_SIZE] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, /* 0 */
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, /* 1 */
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, /* 2 */
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, /* 3 */
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, /* 4 */
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, /* 5 */
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, /* 6 */
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, /* 7 */
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, /* 8 */
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, /* 9 */
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, /* A */
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, /* B */
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, /* C */
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, /* D */
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, /* E */
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, /* F */
+];
+
+const OCTO_BIG_FONT: [u8; BIG_FONTS_SIZE] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, /* 0 */
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, /* 1 */
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, /* 2 */
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, /* 3 */
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, /* 4 */
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, /* 5 */
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, /* 6 */
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, /* 7 */
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, /* 8 */
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, /* 9 */
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, /* A */
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, /* B */
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, /* C */
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, /* D */
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, /* E */
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, /* F */
+];
+
+/// Hexadecimal fonts built into known interpreters
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FontSet {
+    #[serde(rename = "vip")]
+    CosmacVip,
+    Dream6800,
+    Eti660,
+    Schip,
+    Octo
+}
+
+impl Default for FontSet {
+    fn default() -> Self {
+        FontSet::Octo
+    }
+}
+
+impl FontSet {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            FontSet::CosmacVip => "vip",
+            FontSet::Dream6800 => "dream6800",
+            FontSet::Eti660 => "eti660",
+            FontSet::Schip => "schip",
+            FontSet::Octo => "octo"
+        }
+    }
+
+    pub fn font(&self) -> Font {
+        let (small, big): (&[u8], Option<&[u8]>) = match *self {
+            FontSet::CosmacVip => (&VIP_FONT, None),
+            FontSet::Dream6800 => (&DREAM6800_FONT, None),
+            FontSet::Eti660 => (&ETI660_FONT, None),
+            FontSet::Schip => (&SCHIP_FONT, Some(&SCHIP_BIG_FONT)),
+            FontSet::Octo => (&SCHIP_FONT, Some(&OCTO_BIG_FONT))
+        };
+
+        Font {
+            name: self.name().to_owned(),
+            small: small.to_vec(),
+            big: big.map(|big| big.to_vec())
+        }
+    }
+}
+
+impl FromStr for FontSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "vip" | "cosmac" | "chip8" => Ok(FontSet::CosmacVip),
+            "dream6800" | "dream" => Ok(FontSet::Dream6800),
+            "eti660" | "eti-660" => Ok(FontSet::Eti660),
+            "schip" | "superchip" => Ok(FontSet::Schip),
+            "octo" | "xochip" => Ok(FontSet::Octo),
+            other => Err(format!("Unknown font: {} (expected vip, dream6800, eti660, schip or octo)", other))
+        }
+    }
+}
+
+/// Glyphs of the hexadecimal digits: 4x5 ones used by `FX29` and optional 8x10 ones used by `FX30`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Font {
+    pub name: String,
+    pub small: Vec<u8>,
+    pub big: Option<Vec<u8>>
+}
+
+impl Default for Font {
+    fn default() -> Self {
+        FontSet::default().font()
+    }
+}
+
+impl Font {
+    /// Raw font file: 80 bytes of small glyphs, optionally followed by 160 bytes of big ones
+    pub fn load(path: &Path) -> Result<Font, String> {
+        let data = fs::read(path).map_err(|e| format!("Could not read font {:?}: {}", path, e))?;
+
+        let big = match data.len() {
+            FONTS_SIZE => None,
+            len if len == FONTS_SIZE + BIG_FONTS_SIZE => Some(data[FONTS_SIZE..].to_vec()),
+            len => return Err(format!("Invalid font {:?}: expected {} or {} bytes, got {}",
+                                      path, FONTS_SIZE, FONTS_SIZE + BIG_FONTS_SIZE, len))
+        };
+
+        Ok(Font {
+            name: path.display().to_string(),
+            small: data[..FONTS_SIZE].to_vec(),
+            big
+        })
+    }
+}
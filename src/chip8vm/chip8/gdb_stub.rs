@@ -0,0 +1,581 @@
+//! GDB remote serial protocol stub, so that standard tooling can attach to the VM.
+//!
+//! GDB requests are translated into the same debugger `Command`s the `RemoteDbg` server forwards to the VM loop,
+//! and the VM's stop events become stop replies. Registers are numbered V0-VF (0-15), I (16), PC (17), SP (18),
+//! DT (19) and ST (20), as described by the target XML; 16-bit registers are sent little-endian. Only one GDB is
+//! served at a time.
+
+use std::collections::HashMap;
+use std::io::{BufReader, Bytes, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+
+use chip8_protocol::{Command, CpuSnapshot, DbgError, Event, Response, ServerMessage, StepMode, StopReason, WatchAccess};
+use chip8_protocol::expr::Register;
+
+use super::remote_dbg::{execute, DbgMessage, Subscribers};
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <architecture>chip8</architecture>
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+const REGISTER_COUNT: usize = 21;
+/// Largest packet GDB may send, memory reads are capped to fit into a reply of the same size
+const PACKET_SIZE: usize = 0x1000;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+/// Everything the session waits for: GDB's packets and the VM's events
+enum Input {
+    Packet(String),
+    /// Packet whose checksum did not match
+    Corrupt,
+    /// Ctrl-C, sent by GDB to stop the running VM
+    Interrupt,
+    Event(Event),
+    Closed
+}
+
+/// Starts accepting GDB connections on a thread of its own
+pub fn listen(address: &str, sender: mpsc::Sender<DbgMessage>, subscribers: Subscribers) -> Result<(), String> {
+    let listener = TcpListener::bind(address)
+        .map_err(|e| format!("Could not start the GDB stub on {}: {}", address, e))?;
+    let local_address = listener.local_addr()
+        .map_err(|e| format!("Could not start the GDB stub on {}: {}", address, e))?;
+
+    println!("GDB stub listening on {}", local_address);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let (sender, subscribers) = (sender.clone(), subscribers.clone());
+                    // A session of its own, so that a panic while serving it leaves the listener running
+                    thread::spawn(move || {
+                        info!("GDB connected");
+                        serve(stream, &sender, &subscribers);
+                        info!("GDB disconnected");
+                    });
+                },
+                Err(e) => warn!("GDB connection failed: {}", e)
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn serve(stream: TcpStream, sender: &mpsc::Sender<DbgMessage>, subscribers: &Subscribers) {
+    let reader = match stream.try_clone() {
+        Ok(reader) => reader,
+        Err(e) => {
+            warn!("Could not set up the GDB connection: {}", e);
+            return;
+        }
+    };
+
+    let (input_sender, input) = mpsc::channel();
+    let packets = input_sender.clone();
+    thread::spawn(move || read_packets(reader, packets));
+
+    // Events are forwarded into the same channel, so that a running VM can be interrupted while waiting for them
+    let (writer, messages) = mpsc::channel();
+    let subscription = subscribers.subscribe(writer);
+    thread::spawn(move || forward_events(messages, input_sender));
+
+    let mut session = Session { stream, input, sender: sender.clone(), no_ack: false, points: HashMap::new(), closing: false };
+    session.run();
+
+    drop(subscription);
+    let _ = session.stream.shutdown(Shutdown::Both);
+}
+
+fn read_packets(stream: TcpStream, input: mpsc::Sender<Input>) {
+    let mut bytes = BufReader::new(stream).bytes();
+
+    while let Some(byte) = next_byte(&mut bytes) {
+        let item = match byte {
+            0x03 => Input::Interrupt,
+            b'$' => match read_packet(&mut bytes) {
+                Some(Some(packet)) => Input::Packet(packet),
+                Some(None) => Input::Corrupt,
+                None => break
+            },
+            // Acknowledgements, TCP already makes sure nothing gets lost
+            _ => continue
+        };
+
+        if input.send(item).is_err() {
+            return;
+        }
+    }
+
+    let _ = input.send(Input::Closed);
+}
+
+/// Reads the rest of a packet after `$`, `None` when the connection closed and `Some(None)` on a bad checksum
+fn read_packet(bytes: &mut Bytes<BufReader<TcpStream>>) -> Option<Option<String>> {
+    let mut data = vec![];
+
+    loop {
+        match next_byte(bytes)? {
+            b'#' => break,
+            byte => data.push(byte)
+        }
+    }
+
+    let checksum = [next_byte(bytes)?, next_byte(bytes)?];
+    let valid = ::std::str::from_utf8(&checksum).ok()
+        .and_then(|checksum| u8::from_str_radix(checksum, 16).ok())
+        .map_or(false, |expected| expected == checksum_of(&data));
+
+    Some(if valid { Some(String::from_utf8_lossy(&data).into_owned()) } else { None })
+}
+
+fn next_byte(bytes: &mut Bytes<BufReader<TcpStream>>) -> Option<u8> {
+    bytes.next().and_then(|byte| byte.ok())
+}
+
+fn forward_events(messages: mpsc::Receiver<ServerMessage>, input: mpsc::Sender<Input>) {
+    for message in messages {
+        if let ServerMessage::Event(event) = message {
+            if input.send(Input::Event(event)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+struct Session {
+    stream: TcpStream,
+    input: mpsc::Receiver<Input>,
+    sender: mpsc::Sender<DbgMessage>,
+    /// Set once GDB switched acknowledgements off with `QStartNoAckMode`
+    no_ack: bool,
+    /// Breakpoint and watchpoint ids by GDB's `Z` type and address, deleted when GDB leaves
+    points: HashMap<(u8, u16), u32>,
+    closing: bool
+}
+
+impl Session {
+    fn run(&mut self) {
+        // GDB expects the target to be stopped once it attached
+        if let Err(e) = self.stop_vm() {
+            warn!("Could not stop the VM for GDB: {}", e);
+        }
+
+        while !self.closing {
+            let packet = match self.input.recv() {
+                Ok(Input::Packet(packet)) => packet,
+                Ok(Input::Corrupt) => {
+                    self.write_raw(b"-");
+                    continue;
+                },
+                // Stopped already, or stopped by another debugger
+                Ok(Input::Interrupt) | Ok(Input::Event(Event::Stopped { .. })) => continue,
+                Ok(Input::Event(Event::Exited)) | Ok(Input::Closed) | Err(_) => break
+            };
+
+            if !self.no_ack {
+                self.write_raw(b"+");
+            }
+
+            let reply = match self.handle(&packet) {
+                Ok(reply) => reply,
+                Err(e) => {
+                    warn!("GDB request `{}` failed: {}", packet, e);
+                    Some("E01".to_owned())
+                }
+            };
+
+            if let Some(reply) = reply {
+                self.write_packet(&reply);
+            }
+
+            if packet == "QStartNoAckMode" {
+                self.no_ack = true;
+            }
+        }
+
+        self.delete_points();
+    }
+
+    /// Answers the packet, `None` when there is nothing to send back
+    fn handle(&mut self, packet: &str) -> Result<Option<String>, DbgError> {
+        if packet.is_empty() {
+            return Ok(Some(String::new()));
+        }
+
+        // Everything GDB sends is ASCII, anything else could not be split at byte offsets
+        if !packet.is_ascii() {
+            return Err(malformed(packet));
+        }
+
+        let (command, args) = packet.split_at(1);
+
+        let reply = match command {
+            "?" => {
+                self.stop_vm()?;
+                match self.execute(Command::Status)? {
+                    Response::Status(status) => self.stop_reply(status.stop_reason.as_ref()),
+                    other => return Err(unexpected(other))
+                }
+            },
+            "g" => {
+                let cpu = self.cpu()?;
+                (0..REGISTER_COUNT).map(|n| encode_register(n, &cpu)).collect()
+            },
+            "G" => {
+                let mut rest = args;
+                for n in 0..REGISTER_COUNT {
+                    let len = register_size(n) * 2;
+                    if rest.len() < len {
+                        return Err(malformed(packet));
+                    }
+                    let (value, tail) = rest.split_at(len);
+                    self.set_register(n, value)?;
+                    rest = tail;
+                }
+                "OK".to_owned()
+            },
+            "p" => {
+                let n = parse_hex(args).ok_or_else(|| malformed(packet))? as usize;
+                if n >= REGISTER_COUNT {
+                    return Err(DbgError::InvalidArgument(format!("no register {}", n)));
+                }
+                encode_register(n, &self.cpu()?)
+            },
+            "P" => {
+                let mut parts = args.splitn(2, '=');
+                let n = parts.next().and_then(parse_hex).ok_or_else(|| malformed(packet))? as usize;
+                let value = parts.next().ok_or_else(|| malformed(packet))?;
+                self.set_register(n, value)?;
+                "OK".to_owned()
+            },
+            "m" => {
+                let (addr, size) = parse_range(args).ok_or_else(|| malformed(packet))?;
+                match self.execute(Command::Mem { addr, size: size.min(PACKET_SIZE / 2) })? {
+                    Response::Mem(bytes) => encode_hex(&bytes),
+                    other => return Err(unexpected(other))
+                }
+            },
+            "M" => {
+                let mut parts = args.splitn(2, ':');
+                let (addr, size) = parts.next().and_then(parse_range).ok_or_else(|| malformed(packet))?;
+                let bytes = parts.next().and_then(decode_hex).ok_or_else(|| malformed(packet))?;
+                if bytes.len() != size {
+                    return Err(malformed(packet));
+                }
+                self.execute(Command::Poke { addr, bytes })?;
+                "OK".to_owned()
+            },
+            "c" | "s" => {
+                if !args.is_empty() {
+                    let pc = parse_hex(args).ok_or_else(|| malformed(packet))?;
+                    self.execute(Command::SetRegister { register: Register::Pc, value: pc })?;
+                }
+
+                let command = if command == "c" { Command::Start } else { Command::Step(StepMode::Instructions(1)) };
+                self.execute(command)?;
+                return Ok(self.wait_for_stop());
+            },
+            // `bs` and `bc` execute backwards through the VM's history
+            "b" => {
+                let command = match args {
+                    "s" => Command::ReverseStep(1),
+                    "c" => Command::ReverseContinue,
+                    _ => return Err(malformed(packet))
+                };
+                self.execute(command)?;
+                return Ok(self.wait_for_stop());
+            },
+            "Z" | "z" => {
+                self.update_point(command == "Z", args).ok_or_else(|| malformed(packet))??;
+                "OK".to_owned()
+            },
+            "D" => {
+                // A detached target keeps running
+                self.delete_points();
+                self.execute(Command::Start)?;
+                self.closing = true;
+                "OK".to_owned()
+            },
+            "k" => {
+                self.closing = true;
+                return Ok(None);
+            },
+            // There is a single thread
+            "H" | "T" => "OK".to_owned(),
+            _ => query(packet)
+        };
+
+        Ok(Some(reply))
+    }
+
+    /// Waits until the VM stops again, stopping it when GDB sends an interrupt
+    fn wait_for_stop(&mut self) -> Option<String> {
+        loop {
+            match self.input.recv() {
+                Ok(Input::Interrupt) => {
+                    if let Err(e) = self.execute(Command::Stop) {
+                        warn!("Could not interrupt the VM: {}", e);
+                    }
+                },
+                // Events from before the VM was resumed may still be queued, the VM's status tells them apart
+                Ok(Input::Event(Event::Stopped { .. })) => match self.execute(Command::Status) {
+                    Ok(Response::Status(ref status)) if status.running => {},
+                    Ok(Response::Status(status)) => return Some(self.stop_reply(status.stop_reason.as_ref())),
+                    Ok(other) => warn!("Could not check why the VM stopped: {}", unexpected(other)),
+                    Err(e) => warn!("Could not check why the VM stopped: {}", e)
+                },
+                Ok(Input::Event(Event::Exited)) => {
+                    self.closing = true;
+                    return Some("W00".to_owned());
+                },
+                // GDB does not send anything else while the target runs
+                Ok(Input::Packet(_)) | Ok(Input::Corrupt) => {},
+                Ok(Input::Closed) | Err(_) => {
+                    self.closing = true;
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn stop_reply(&self, reason: Option<&StopReason>) -> String {
+        match reason {
+            Some(&StopReason::Requested) => format!("S{:02x}", SIGINT),
+            Some(&StopReason::Breakpoint { .. }) => format!("T{:02x}swbreak:;", SIGTRAP),
+            Some(&StopReason::Watchpoint(ref hit)) => {
+                // Reported the way GDB inserted the watchpoint, falling back to the access for foreign ones
+                let kind = match self.points.iter().find(|&(_, &id)| id == hit.id).map(|(&(kind, _), _)| kind) {
+                    Some(3) => "rwatch",
+                    Some(4) => "awatch",
+                    Some(_) => "watch",
+                    None if hit.access == WatchAccess::Read => "rwatch",
+                    None => "watch"
+                };
+                format!("T{:02x}{}:{:x};", SIGTRAP, kind, hit.addr)
+            },
+            Some(&StopReason::Fault(_)) => format!("S{:02x}", SIGILL),
+            Some(&StopReason::HistoryStart) => format!("T{:02x}replaylog:begin;", SIGTRAP),
+            Some(&StopReason::Step) | None => format!("S{:02x}", SIGTRAP)
+        }
+    }
+
+    /// Handles `Z`/`z` packets, `TYPE,ADDR,KIND`; `None` when they are malformed
+    fn update_point(&mut self, insert: bool, args: &str) -> Option<Result<(), DbgError>> {
+        let mut parts = args.split(',');
+        let kind = parts.next()?.parse::<u8>().ok()?;
+        let addr = parts.next().and_then(parse_hex)?;
+        let size = parts.next().and_then(parse_hex)?;
+
+        if !insert {
+            return Some(match self.points.remove(&(kind, addr)) {
+                Some(id) => self.execute(Command::DeleteBreakpoint(id)).map(|_| ()),
+                None => Ok(())
+            });
+        }
+
+        if self.points.contains_key(&(kind, addr)) {
+            return Some(Ok(()));
+        }
+
+        let access = match kind {
+            0 | 1 => None,
+            2 => Some(WatchAccess::Write),
+            3 => Some(WatchAccess::Read),
+            4 => Some(WatchAccess::Access),
+            _ => return None
+        };
+
+        let command = match access {
+            None => Command::AddBreakpoint { addr, condition: None },
+            Some(access) => Command::AddWatchpoint { start: addr, end: addr.wrapping_add(size.max(1) - 1), access }
+        };
+
+        Some(self.execute(command).and_then(|response| {
+            let id = match response {
+                Response::Breakpoint(breakpoint) => breakpoint.id,
+                Response::Watchpoint(watchpoint) => watchpoint.id,
+                other => return Err(unexpected(other))
+            };
+            self.points.insert((kind, addr), id);
+            Ok(())
+        }))
+    }
+
+    fn delete_points(&mut self) {
+        for (_, id) in self.points.drain().collect::<Vec<_>>() {
+            if let Err(e) = execute(Command::DeleteBreakpoint(id), &self.sender) {
+                warn!("Could not delete GDB breakpoint {}: {}", id, e);
+            }
+        }
+    }
+
+    fn stop_vm(&mut self) -> Result<(), DbgError> {
+        match self.execute(Command::Status)? {
+            Response::Status(ref status) if status.running => self.execute(Command::Stop).map(|_| ()),
+            Response::Status(_) => Ok(()),
+            other => Err(unexpected(other))
+        }
+    }
+
+    fn cpu(&mut self) -> Result<CpuSnapshot, DbgError> {
+        match self.execute(Command::Cpu)? {
+            Response::Cpu(cpu) => Ok(cpu),
+            other => Err(unexpected(other))
+        }
+    }
+
+    fn set_register(&mut self, n: usize, hex: &str) -> Result<(), DbgError> {
+        let register = register(n).ok_or_else(|| DbgError::InvalidArgument(format!("no register {}", n)))?;
+        let bytes = decode_hex(hex).ok_or_else(|| malformed(hex))?;
+        let value = bytes.iter().rev().fold(0u16, |value, &byte| value << 8 | byte as u16);
+
+        self.execute(Command::SetRegister { register, value }).map(|_| ())
+    }
+
+    fn execute(&self, command: Command) -> Result<Response, DbgError> {
+        execute(command, &self.sender)
+    }
+
+    fn write_packet(&mut self, data: &str) {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.write_raw(packet.as_bytes());
+    }
+
+    fn write_raw(&mut self, data: &[u8]) {
+        if let Err(e) = self.stream.write_all(data) {
+            warn!("Could not write to GDB: {}", e);
+            self.closing = true;
+        }
+    }
+}
+
+/// Answers the general queries and `v` packets, an empty reply tells GDB that something is not supported
+fn query(packet: &str) -> String {
+    if packet.starts_with("qSupported") {
+        return format!("PacketSize={:x};qXfer:features:read+;swbreak+;QStartNoAckMode+;ReverseStep+;ReverseContinue+", PACKET_SIZE);
+    }
+
+    if packet.starts_with("qXfer:features:read:target.xml:") {
+        let range = &packet["qXfer:features:read:target.xml:".len()..];
+        return match parse_range(range) {
+            Some((offset, size)) => {
+                let offset = (offset as usize).min(TARGET_XML.len());
+                let end = (offset + size).min(TARGET_XML.len());
+                let marker = if end == TARGET_XML.len() { "l" } else { "m" };
+                format!("{}{}", marker, &TARGET_XML[offset..end])
+            },
+            None => "E01".to_owned()
+        };
+    }
+
+    match packet {
+        "QStartNoAckMode" => "OK".to_owned(),
+        "qAttached" => "1".to_owned(),
+        "qC" => "QC1".to_owned(),
+        "qfThreadInfo" => "m1".to_owned(),
+        "qsThreadInfo" => "l".to_owned(),
+        _ => String::new()
+    }
+}
+
+fn register(n: usize) -> Option<Register> {
+    match n {
+        0..=15 => Some(Register::V(n as u8)),
+        16 => Some(Register::I),
+        17 => Some(Register::Pc),
+        18 => Some(Register::Sp),
+        19 => Some(Register::Dt),
+        20 => Some(Register::St),
+        _ => None
+    }
+}
+
+fn register_size(n: usize) -> usize {
+    match register(n) {
+        Some(Register::I) | Some(Register::Pc) => 2,
+        _ => 1
+    }
+}
+
+fn encode_register(n: usize, cpu: &CpuSnapshot) -> String {
+    let value = match register(n) {
+        Some(Register::V(x)) => cpu.v[x as usize] as u16,
+        Some(Register::I) => cpu.i,
+        Some(Register::Pc) => cpu.pc,
+        Some(Register::Sp) => cpu.sp as u16,
+        Some(Register::Dt) => cpu.dt as u16,
+        Some(Register::St) => cpu.st as u16,
+        None => 0
+    };
+
+    encode_hex(&[value as u8, (value >> 8) as u8][..register_size(n)])
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+fn parse_hex(hex: &str) -> Option<u16> {
+    u16::from_str_radix(hex, 16).ok()
+}
+
+/// Parses `ADDR,LENGTH`
+fn parse_range(range: &str) -> Option<(u16, usize)> {
+    let mut parts = range.splitn(2, ',');
+    let addr = parts.next().and_then(parse_hex)?;
+    let size = parts.next().and_then(|size| usize::from_str_radix(size, 16).ok())?;
+    Some((addr, size))
+}
+
+fn malformed(packet: &str) -> DbgError {
+    DbgError::Malformed(format!("`{}`", packet))
+}
+
+fn unexpected(response: Response) -> DbgError {
+    DbgError::UnexpectedReply(format!("{:?}", response))
+}
@@ -0,0 +1,144 @@
+use std::collections::VecDeque;
+use std::mem;
+
+use super::Interconnect;
+use super::arch::Registers;
+use super::arch::rng::RngState;
+use super::display::{CHIP8_WIDTH, CHIP8_HEIGHT};
+
+use chip8_protocol::{HistoryInfo, WatchAccess, WatchpointHit, WatchpointInfo};
+
+pub type Vram = [[u8; CHIP8_WIDTH]; CHIP8_HEIGHT];
+
+/// Everything an executed instruction changed, enough to undo it
+pub struct HistoryEntry {
+    /// CPU state before the instruction, PC pointing at it
+    pub registers: Registers,
+    /// Instructions executed in the frame before this one
+    pub frame_cycle: u32,
+    /// Random source before `CXNN`, other instructions do not touch it
+    pub rng: Option<RngState>,
+    /// Locations and previous values of the RAM bytes the instruction wrote, in write order
+    pub memory: Vec<(u16, u8)>,
+    /// Pixels the instruction changed as `y * CHIP8_WIDTH + x`, with their previous values
+    pub vram: Vec<(u16, u8)>,
+    /// Watchpoint the instruction hit when it got executed
+    pub watch_hit: Option<WatchpointHit>
+}
+
+impl HistoryEntry {
+    /// Rough number of bytes the entry occupies
+    fn size(&self) -> usize {
+        let rng = match self.rng {
+            Some(RngState::Scripted { ref values, .. }) => values.len(),
+            _ => 0
+        };
+
+        mem::size_of::<HistoryEntry>() + (self.memory.capacity() + self.vram.capacity()) * mem::size_of::<(u16, u8)>() + rng
+    }
+
+    /// Watchpoint the instruction hits when looking at it backwards: the one it hit when it got executed, or one
+    /// now watching memory it wrote. Has to be called before the entry gets undone, while memory holds the new values.
+    pub fn watchpoint_hit(&self, watchpoints: &[WatchpointInfo], interconnect: &Interconnect) -> Option<WatchpointHit> {
+        if let Some(ref hit) = self.watch_hit {
+            if watchpoints.iter().any(|w| w.id == hit.id && w.enabled) {
+                return Some(hit.clone());
+            }
+        }
+
+        for &(addr, old) in &self.memory {
+            let watchpoint = watchpoints.iter().find(|w| {
+                w.enabled && w.start <= addr && addr <= w.end && w.access != WatchAccess::Read
+            });
+
+            if let Some(watchpoint) = watchpoint {
+                let pc = self.registers.pc();
+                return Some(WatchpointHit {
+                    id: watchpoint.id,
+                    access: WatchAccess::Write,
+                    addr,
+                    old,
+                    new: interconnect.peek(addr),
+                    pc,
+                    opcode: interconnect.read_word(pc)
+                });
+            }
+        }
+
+        None
+    }
+}
+
+/// Pixels that differ between two frames, with their values in `before`
+pub fn changed_pixels(before: &Vram, after: &Vram) -> Vec<(u16, u8)> {
+    let mut changed = vec![];
+
+    for y in 0..CHIP8_HEIGHT {
+        for x in 0..CHIP8_WIDTH {
+            if before[y][x] != after[y][x] {
+                changed.push(((y * CHIP8_WIDTH + x) as u16, before[y][x]));
+            }
+        }
+    }
+
+    changed
+}
+
+/// Undo log of the most recently executed instructions, used to step backwards in the debugger.
+/// The oldest entries are dropped to keep it under its memory budget.
+pub struct History {
+    entries: VecDeque<HistoryEntry>,
+    size: usize,
+    /// Maximum size in bytes, 0 disables the history
+    budget: usize
+}
+
+impl History {
+    pub fn new(budget: usize) -> History {
+        History {
+            entries: VecDeque::new(),
+            size: 0,
+            budget
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.budget > 0
+    }
+
+    pub fn push(&mut self, entry: HistoryEntry) {
+        self.size += entry.size();
+        self.entries.push_back(entry);
+
+        while self.size > self.budget {
+            match self.entries.pop_front() {
+                Some(oldest) => self.size -= oldest.size(),
+                None => break
+            }
+        }
+    }
+
+    /// Most recent entry, the one undone next
+    pub fn last(&self) -> Option<&HistoryEntry> {
+        self.entries.back()
+    }
+
+    pub fn pop(&mut self) -> Option<HistoryEntry> {
+        let entry = self.entries.pop_back()?;
+        self.size -= entry.size();
+        Some(entry)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.size = 0;
+    }
+
+    pub fn info(&self) -> HistoryInfo {
+        HistoryInfo {
+            instructions: self.entries.len(),
+            size: self.size,
+            budget: self.budget
+        }
+    }
+}
@@ -1,19 +1,24 @@
 use super::display;
 use super::keypad;
-use super::mem_map;
+use super::watchpoints::Watchpoints;
 
 pub struct Interconnect {
-    ram: [u8; mem_map::RAM_SIZE],
+    ram: Vec<u8>,
     display: display::Display,
-    keypad: keypad::Keypad
+    keypad: keypad::Keypad,
+    watchpoints: Watchpoints,
+    /// Previous values of the bytes written by the CPU, collected for the execution history
+    journal: Option<Vec<(u16, u8)>>
 }
 
 impl Interconnect {
-    pub fn new(display: display::Display, keypad: keypad::Keypad) -> Interconnect {
+    pub fn new(display: display::Display, keypad: keypad::Keypad, ram_size: usize) -> Interconnect {
         Interconnect {
-            ram: [0; mem_map::RAM_SIZE],
+            ram: vec![0; ram_size],
             display,
-            keypad
+            keypad,
+            watchpoints: Watchpoints::default(),
+            journal: None
         }
     }
 
@@ -21,7 +26,7 @@ impl Interconnect {
         &mut self.display
     }
 
-    pub fn ram(&mut self) -> &mut [u8; mem_map::RAM_SIZE] {
+    pub fn ram(&mut self) -> &mut [u8] {
         &mut self.ram
     }
 
@@ -29,18 +34,67 @@ impl Interconnect {
         &mut self.keypad
     }
 
+    pub fn watchpoints(&mut self) -> &mut Watchpoints {
+        &mut self.watchpoints
+    }
+
+    /// Starts collecting the bytes the CPU overwrites
+    pub fn start_journal(&mut self) {
+        self.journal = Some(Vec::new());
+    }
+
+    /// Locations and previous values of the bytes written since `start_journal`, in write order
+    pub fn take_journal(&mut self) -> Vec<(u16, u8)> {
+        self.journal.take().unwrap_or_default()
+    }
+
+    // Addresses past the end of RAM wrap around, like on machines with less than 64 KiB
+    fn index(&self, location: usize) -> usize {
+        location % self.ram.len()
+    }
+
+    /// Instruction fetch, not seen by watchpoints
     pub fn read_word(&self, location: u16) -> u16 {
-        (self.ram[location as usize] as u16) << 8 | (self.ram[(location + 1) as usize] as u16)
+        (self.peek(location) as u16) << 8 | (self.ram[self.index(location as usize + 1)] as u16)
     }
 
-    pub fn read_byte(&self, location: u16) -> u8 {
-        self.ram[location as usize]
+    /// Data read by the CPU
+    pub fn read_byte(&mut self, location: u16) -> u8 {
+        let index = self.index(location as usize);
+        let value = self.ram[index];
+        self.watchpoints.on_read(index as u16, value);
+        value
     }
 
-    // TODO: bounds checking
+    /// Data written by the CPU
     pub fn write_memory(&mut self, location: u16, data: &Vec<u8>) {
         for i in 0..data.len() {
-            self.ram[location as usize + i] = data[i];
+            let index = self.index(location as usize + i);
+            self.watchpoints.on_write(index as u16, self.ram[index], data[i]);
+            if let Some(ref mut journal) = self.journal {
+                journal.push((index as u16, self.ram[index]));
+            }
+            self.ram[index] = data[i];
+        }
+    }
+
+    /// Reads memory on behalf of the VM or the debugger, bypassing watchpoints
+    pub fn peek(&self, location: u16) -> u8 {
+        self.ram[self.index(location as usize)]
+    }
+
+    /// Zeroes the whole RAM, bypassing watchpoints
+    pub fn clear_ram(&mut self) {
+        for byte in self.ram.iter_mut() {
+            *byte = 0;
+        }
+    }
+
+    /// Writes memory on behalf of the VM or the debugger (fonts, ROM), bypassing watchpoints
+    pub fn load(&mut self, location: u16, data: &[u8]) {
+        for i in 0..data.len() {
+            let index = self.index(location as usize + i);
+            self.ram[index] = data[i];
         }
     }
 }
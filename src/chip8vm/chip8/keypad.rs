@@ -1,7 +1,17 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
 use sdl2;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
+use sdl2::controller::{self, GameController};
+use sdl2::GameControllerSubsystem;
+use serde_json;
+
+use super::controller::{ControllerMapping, Controllers, PadAxis, PadButton, PadEvent};
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Keys {
     K0,
     K1,
@@ -21,18 +31,93 @@ pub enum Keys {
     KF,
 }
 
-type KeypadState = [bool; 16];
+pub type KeypadState = [bool; 16];
+
+/// Keyboard bindings; files map SDL key names (e.g. "1", "Q", "Space") to keypad keys
+pub struct KeyMap {
+    keys: HashMap<Keycode, Keys>
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        let keys = vec![
+            (Keycode::Num1, Keys::K1),
+            (Keycode::Num2, Keys::K2),
+            (Keycode::Num3, Keys::K3),
+            (Keycode::Num4, Keys::KC),
+            (Keycode::Q, Keys::K4),
+            (Keycode::W, Keys::K5),
+            (Keycode::E, Keys::K6),
+            (Keycode::R, Keys::KD),
+            (Keycode::A, Keys::K7),
+            (Keycode::S, Keys::K8),
+            (Keycode::D, Keys::K9),
+            (Keycode::F, Keys::KE),
+            (Keycode::Z, Keys::KA),
+            (Keycode::X, Keys::K0),
+            (Keycode::C, Keys::KB),
+            (Keycode::V, Keys::KF),
+        ];
+
+        KeyMap { keys: keys.into_iter().collect() }
+    }
+}
+
+impl KeyMap {
+    pub fn load(path: &Path) -> Result<KeyMap, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("Could not read key map {:?}: {}", path, e))?;
+        let names: HashMap<String, Keys> = serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid key map {:?}: {}", path, e))?;
+
+        let mut keys = HashMap::new();
+        for (name, key) in names {
+            let keycode = Keycode::from_name(&name).ok_or(format!("Unknown key name in key map: {}", name))?;
+            keys.insert(keycode, key);
+        }
+
+        Ok(KeyMap { keys })
+    }
+}
 
 pub struct Keypad {
     keypad: KeypadState,
-    key_events: sdl2::EventPump
+    key_map: KeyMap,
+    key_events: Option<sdl2::EventPump>,
+    controller_subsystem: Option<GameControllerSubsystem>,
+    // opened controllers have to be kept alive to keep receiving their events
+    open_controllers: HashMap<i32, GameController>,
+    controllers: Controllers
 }
 
 impl Keypad {
-    pub fn new(sdl_context: &sdl2::Sdl) -> Self {
+    pub fn new(sdl_context: &sdl2::Sdl, key_map: KeyMap, mapping: ControllerMapping) -> Self {
+        let controller_subsystem = match sdl_context.game_controller() {
+            Ok(subsystem) => Some(subsystem),
+            Err(err) => {
+                warn!("Game controller support unavailable: {}", err);
+                None
+            }
+        };
+
+        Keypad {
+            keypad: [false; 16],
+            key_map,
+            key_events: Some(sdl_context.event_pump().unwrap()),
+            controller_subsystem,
+            open_controllers: HashMap::new(),
+            controllers: Controllers::new(mapping)
+        }
+    }
+
+    /// Keypad without any input source, for running without a window
+    pub fn headless() -> Self {
         Keypad {
             keypad: [false; 16],
-            key_events: sdl_context.event_pump().unwrap()
+            key_map: KeyMap::default(),
+            key_events: None,
+            controller_subsystem: None,
+            open_controllers: HashMap::new(),
+            controllers: Controllers::new(ControllerMapping::default())
         }
     }
 
@@ -47,48 +132,97 @@ impl Keypad {
 
     pub fn poll(&mut self) -> Result<KeypadState, ()> {
 
-        for event in self.key_events.poll_iter() {
+        let events: Vec<Event> = match self.key_events {
+            Some(ref mut key_events) => key_events.poll_iter().collect(),
+            None => return Ok([false; 16])
+        };
+
+        for event in events {
             match event {
                 Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return Err(()),
+                Event::ControllerDeviceAdded { which, .. } => self.open_controller(which),
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    self.open_controllers.remove(&which);
+                    self.controllers.handle_event(PadEvent::Disconnected(which));
+                },
+                Event::ControllerButtonDown { which, button, .. } => {
+                    self.controllers.handle_event(PadEvent::ButtonDown(which, Keypad::pad_button(button)));
+                },
+                Event::ControllerButtonUp { which, button, .. } => {
+                    self.controllers.handle_event(PadEvent::ButtonUp(which, Keypad::pad_button(button)));
+                },
+                Event::ControllerAxisMotion { which, axis, value, .. } => {
+                    self.controllers.handle_event(PadEvent::AxisMotion(which, Keypad::pad_axis(axis), value));
+                },
                 _ => {}
             };
         }
 
-        let keys: Vec<Keycode> = self.key_events
-            .keyboard_state()
-            .pressed_scancodes()
-            .filter_map(Keycode::from_scancode)
-            .collect();
+        let keys: Vec<Keycode> = match self.key_events {
+            Some(ref key_events) => key_events
+                .keyboard_state()
+                .pressed_scancodes()
+                .filter_map(Keycode::from_scancode)
+                .collect(),
+            None => vec![]
+        };
 
-        let mut new_key_states = [false; 16];
+        let mut new_key_states = self.controllers.state();
 
         for key in keys {
-            // TODO: allow to define custom key mappings
-            let index = match key {
-                Keycode::Num1 => Some(Keys::K1),
-                Keycode::Num2 => Some(Keys::K2),
-                Keycode::Num3 => Some(Keys::K3),
-                Keycode::Num4 => Some(Keys::KC),
-                Keycode::Q => Some(Keys::K4),
-                Keycode::W => Some(Keys::K5),
-                Keycode::E => Some(Keys::K6),
-                Keycode::R => Some(Keys::KD),
-                Keycode::A => Some(Keys::K7),
-                Keycode::S => Some(Keys::K8),
-                Keycode::D => Some(Keys::K9),
-                Keycode::F => Some(Keys::KE),
-                Keycode::Z => Some(Keys::KA),
-                Keycode::X => Some(Keys::K0),
-                Keycode::C => Some(Keys::KB),
-                Keycode::V => Some(Keys::KF),
-                _ => None,
-            };
-
-            if let Some(i) = index {
-                new_key_states[i as usize] = true;
+            if let Some(k) = self.key_map.keys.get(&key) {
+                new_key_states[*k as usize] = true;
             }
         }
 
         Ok(new_key_states)
     }
+
+    fn open_controller(&mut self, joystick_index: u32) {
+        let subsystem = match self.controller_subsystem {
+            Some(ref subsystem) => subsystem,
+            None => return
+        };
+
+        match subsystem.open(joystick_index) {
+            Ok(pad) => {
+                let id = pad.instance_id();
+                info!("Opened game controller: {}", pad.name());
+                self.open_controllers.insert(id, pad);
+                self.controllers.handle_event(PadEvent::Connected(id));
+            },
+            Err(err) => warn!("Could not open game controller {}: {}", joystick_index, err)
+        }
+    }
+
+    fn pad_button(button: controller::Button) -> PadButton {
+        match button {
+            controller::Button::A => PadButton::A,
+            controller::Button::B => PadButton::B,
+            controller::Button::X => PadButton::X,
+            controller::Button::Y => PadButton::Y,
+            controller::Button::Back => PadButton::Back,
+            controller::Button::Guide => PadButton::Guide,
+            controller::Button::Start => PadButton::Start,
+            controller::Button::LeftStick => PadButton::LeftStick,
+            controller::Button::RightStick => PadButton::RightStick,
+            controller::Button::LeftShoulder => PadButton::LeftShoulder,
+            controller::Button::RightShoulder => PadButton::RightShoulder,
+            controller::Button::DPadUp => PadButton::DPadUp,
+            controller::Button::DPadDown => PadButton::DPadDown,
+            controller::Button::DPadLeft => PadButton::DPadLeft,
+            controller::Button::DPadRight => PadButton::DPadRight
+        }
+    }
+
+    fn pad_axis(axis: controller::Axis) -> PadAxis {
+        match axis {
+            controller::Axis::LeftX => PadAxis::LeftX,
+            controller::Axis::LeftY => PadAxis::LeftY,
+            controller::Axis::RightX => PadAxis::RightX,
+            controller::Axis::RightY => PadAxis::RightY,
+            controller::Axis::TriggerLeft => PadAxis::TriggerLeft,
+            controller::Axis::TriggerRight => PadAxis::TriggerRight
+        }
+    }
 }
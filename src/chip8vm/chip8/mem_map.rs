@@ -1,3 +1,101 @@
+use std::str::FromStr;
+
 pub const RAM_SIZE: usize = 0x1000;
 pub const ROM_LOCATION: u16 = 0x200;
 pub const FONTS_LOCATION: u16 = 0x0;
+pub const FONTS_SIZE: usize = 16 * 5;
+pub const BIG_FONTS_LOCATION: u16 = FONTS_LOCATION + FONTS_SIZE as u16;
+pub const BIG_FONTS_SIZE: usize = 16 * 10;
+
+/// Address space of the emulated machine
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct MemoryMap {
+    pub ram_size: usize,
+    pub rom_location: u16,
+    pub fonts_location: u16,
+    /// Where the 8x10 glyphs used by `FX30` are stored
+    pub big_fonts_location: u16
+}
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        MemoryLayout::CosmacVip.memory_map()
+    }
+}
+
+impl MemoryMap {
+    /// Number of bytes available for the ROM
+    pub fn rom_capacity(&self) -> usize {
+        self.ram_size.saturating_sub(self.rom_location as usize)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.ram_size < RAM_SIZE || self.ram_size > 0x10000 {
+            return Err(format!("RAM size has to be between {:#x} and 0x10000 bytes, got {:#x}", RAM_SIZE, self.ram_size));
+        }
+
+        if self.rom_location as usize >= self.ram_size {
+            return Err(format!("Load address {:#x} is outside of {:#x} bytes of RAM", self.rom_location, self.ram_size));
+        }
+
+        if self.fonts_location as usize + FONTS_SIZE > self.ram_size {
+            return Err(format!("Fonts at {:#x} do not fit in {:#x} bytes of RAM", self.fonts_location, self.ram_size));
+        }
+
+        if self.big_fonts_location as usize + BIG_FONTS_SIZE > self.ram_size {
+            return Err(format!("Big fonts at {:#x} do not fit in {:#x} bytes of RAM", self.big_fonts_location, self.ram_size));
+        }
+
+        Ok(())
+    }
+}
+
+/// Memory maps of known machines
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemoryLayout {
+    /// 4 KiB, programs start at 0x200
+    CosmacVip,
+    /// 4 KiB, programs start at 0x600
+    Eti660,
+    /// 64 KiB address space of XO-CHIP, programs start at 0x200
+    XoChip
+}
+
+impl MemoryLayout {
+    pub fn memory_map(&self) -> MemoryMap {
+        match *self {
+            MemoryLayout::CosmacVip => MemoryMap {
+                ram_size: RAM_SIZE,
+                rom_location: ROM_LOCATION,
+                fonts_location: FONTS_LOCATION,
+                big_fonts_location: BIG_FONTS_LOCATION
+            },
+            MemoryLayout::Eti660 => MemoryMap {
+                ram_size: RAM_SIZE,
+                rom_location: 0x600,
+                fonts_location: FONTS_LOCATION,
+                big_fonts_location: BIG_FONTS_LOCATION
+            },
+            MemoryLayout::XoChip => MemoryMap {
+                ram_size: 0x10000,
+                rom_location: ROM_LOCATION,
+                fonts_location: FONTS_LOCATION,
+                big_fonts_location: BIG_FONTS_LOCATION
+            }
+        }
+    }
+}
+
+impl FromStr for MemoryLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "vip" | "chip8" | "4k" => Ok(MemoryLayout::CosmacVip),
+            "eti660" | "eti-660" => Ok(MemoryLayout::Eti660),
+            "xochip" | "xo-chip" | "64k" => Ok(MemoryLayout::XoChip),
+            other => Err(format!("Unknown memory layout: {} (expected vip, eti660 or 64k)", other))
+        }
+    }
+}
@@ -1,15 +1,25 @@
-mod arch;
+pub mod arch;
+mod breakpoints;
 mod chip8;
+mod gdb_stub;
+mod history;
 mod interconnect;
+mod stepping;
+mod watchpoints;
+pub mod controller;
 pub mod display;
+pub mod fonts;
 pub mod keypad;
 pub mod mem_map;
+pub mod movie;
 pub mod remote_dbg;
+pub mod settings;
 
-pub use self::chip8::Chip8;
+pub use self::chip8::{Chip8, RomError};
 pub use self::arch::Cpu;
 pub use self::interconnect::Interconnect;
 pub use self::display::Display;
 pub use self::keypad::Keypad;
 pub use self::remote_dbg::RemoteDbg;
 pub use self::remote_dbg::DbgMessage;
+pub use self::settings::Settings;
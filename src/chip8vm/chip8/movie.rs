@@ -0,0 +1,317 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use bincode::{deserialize_from, serialize_into};
+use sha1::{Digest, Sha1};
+
+use super::Interconnect;
+use super::arch::quirks::Quirks;
+use super::fonts::Font;
+use super::keypad::KeypadState;
+use super::mem_map::MemoryMap;
+use super::settings::Settings;
+
+/// Settings that change how the machine runs, a replay only stays in sync with the same ones
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MovieSettings {
+    pub quirks: Quirks,
+    pub instructions_per_frame: u32,
+    pub memory_map: MemoryMap,
+    pub font: Font
+}
+
+impl MovieSettings {
+    pub fn new(settings: &Settings) -> Self {
+        MovieSettings {
+            quirks: settings.quirks,
+            instructions_per_frame: settings.instructions_per_frame,
+            memory_map: settings.memory_map,
+            font: settings.font.clone()
+        }
+    }
+
+    /// Makes the VM run the way it did while recording
+    pub fn apply(&self, settings: &mut Settings) {
+        settings.quirks = self.quirks;
+        settings.instructions_per_frame = self.instructions_per_frame;
+        settings.memory_map = self.memory_map;
+        settings.font = self.font.clone();
+    }
+
+    /// Names of the settings that differ from the recorded ones
+    pub fn differences(&self, settings: &Settings) -> Vec<&'static str> {
+        let current = MovieSettings::new(settings);
+        let mut differences = vec![];
+
+        if current.quirks != self.quirks {
+            differences.push("quirks");
+        }
+        if current.instructions_per_frame != self.instructions_per_frame {
+            differences.push("instructions per frame");
+        }
+        if current.memory_map != self.memory_map {
+            differences.push("memory map");
+        }
+        if current.font != self.font {
+            differences.push("font");
+        }
+
+        differences
+    }
+}
+
+/// Keypad input of a whole run, along with everything needed to reproduce it
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Movie {
+    pub rom_hash: String,
+    pub seed: u64,
+    pub settings: MovieSettings,
+    pub frames: Vec<u16>,
+    /// Short machine state hash after every frame, so that replays find the exact frame they diverged at
+    pub frame_hashes: Vec<u64>,
+    pub final_hash: String
+}
+
+impl Movie {
+    pub fn load(path: &Path) -> io::Result<Movie> {
+        let reader = BufReader::new(File::open(path)?);
+        deserialize_from(reader).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        serialize_into(writer, self).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+}
+
+pub fn rom_hash(rom: &[u8]) -> String {
+    Sha1::from(rom).digest().to_string()
+}
+
+fn machine_digest(interconnect: &mut Interconnect) -> Digest {
+    let mut hasher = Sha1::new();
+    hasher.update(&interconnect.ram()[..]);
+
+    for row in interconnect.display().vram().iter() {
+        hasher.update(&row[..]);
+    }
+
+    hasher.digest()
+}
+
+/// Hash of RAM and framebuffer, used to verify that a replay matches its recording
+pub fn machine_hash(interconnect: &mut Interconnect) -> String {
+    machine_digest(interconnect).to_string()
+}
+
+/// First 8 bytes of the machine hash, small enough to be stored for every frame
+pub fn frame_hash(interconnect: &mut Interconnect) -> u64 {
+    machine_digest(interconnect).bytes()[..8].iter().fold(0, |hash, &byte| hash << 8 | byte as u64)
+}
+
+fn encode_keypad(state: &KeypadState) -> u16 {
+    state.iter().enumerate().fold(0, |bits, (i, &pressed)| if pressed { bits | 1 << i } else { bits })
+}
+
+fn decode_keypad(bits: u16) -> KeypadState {
+    let mut state = [false; 16];
+
+    for i in 0..16 {
+        state[i] = bits & (1 << i) != 0;
+    }
+
+    state
+}
+
+pub enum MovieSession {
+    Recording(MovieRecorder),
+    Replaying(MoviePlayer)
+}
+
+impl MovieSession {
+    /// Called once per frame, after the frame has been executed
+    pub fn end_frame(&mut self, interconnect: &mut Interconnect) {
+        match *self {
+            MovieSession::Recording(ref mut recorder) => recorder.end_frame(interconnect),
+            MovieSession::Replaying(ref mut player) => player.end_frame(interconnect)
+        }
+    }
+
+    pub fn finish(self, interconnect: &mut Interconnect) {
+        let final_hash = machine_hash(interconnect);
+
+        match self {
+            MovieSession::Recording(recorder) => recorder.finish(final_hash),
+            MovieSession::Replaying(player) => player.finish(final_hash)
+        }
+    }
+}
+
+pub struct MovieRecorder {
+    path: PathBuf,
+    movie: Movie
+}
+
+impl MovieRecorder {
+    pub fn new(path: &Path, rom: &[u8], seed: u64, settings: MovieSettings) -> Self {
+        MovieRecorder {
+            path: path.to_path_buf(),
+            movie: Movie {
+                rom_hash: rom_hash(rom),
+                seed,
+                settings,
+                frames: vec![],
+                frame_hashes: vec![],
+                final_hash: String::new()
+            }
+        }
+    }
+
+    pub fn record_frame(&mut self, state: &KeypadState) {
+        self.movie.frames.push(encode_keypad(state));
+    }
+
+    fn end_frame(&mut self, interconnect: &mut Interconnect) {
+        self.movie.frame_hashes.push(frame_hash(interconnect));
+    }
+
+    fn finish(mut self, final_hash: String) {
+        self.movie.final_hash = final_hash;
+
+        match self.movie.save(&self.path) {
+            Ok(_) => info!("Recorded {} frames to: {:?}", self.movie.frames.len(), self.path),
+            Err(err) => error!("Could not save movie to {:?}: {}", self.path, err)
+        }
+    }
+}
+
+pub struct MoviePlayer {
+    movie: Movie,
+    frame: usize,
+    /// First frame whose state differed from the recording
+    divergence: Option<usize>
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> Self {
+        MoviePlayer {
+            movie,
+            frame: 0,
+            divergence: None
+        }
+    }
+
+    pub fn next_frame(&mut self) -> Option<KeypadState> {
+        let state = self.movie.frames.get(self.frame).map(|bits| decode_keypad(*bits));
+
+        if state.is_some() {
+            self.frame += 1;
+        }
+
+        state
+    }
+
+    fn end_frame(&mut self, interconnect: &mut Interconnect) {
+        if self.divergence.is_some() {
+            return;
+        }
+
+        let expected = match self.frame.checked_sub(1).and_then(|frame| self.movie.frame_hashes.get(frame)) {
+            Some(&hash) => hash,
+            None => return
+        };
+
+        let hash = frame_hash(interconnect);
+        if hash != expected {
+            error!("Replay diverged at frame {} (state {:016x}, recorded {:016x})", self.frame, hash, expected);
+            self.divergence = Some(self.frame);
+        }
+    }
+
+    fn finish(self, final_hash: String) {
+        if self.frame < self.movie.frames.len() {
+            warn!("Replay interrupted at frame {} of {}", self.frame, self.movie.frames.len());
+            return;
+        }
+
+        match self.divergence {
+            Some(frame) => println!("Replay FAILED: state diverged at frame {}", frame),
+            None if final_hash != self.movie.final_hash => println!("Replay FAILED: final state differs \
+                                                                  (expected {}, got {})", self.movie.final_hash, final_hash),
+            None => println!("Replay OK: {} frames, final state {}", self.frame, final_hash)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MovieRecorder, MoviePlayer, MovieSettings};
+    use chip8::{Display, Keypad, Interconnect, Settings};
+    use chip8::arch::quirks::QuirkProfile;
+    use chip8::fonts::FontSet;
+    use std::path::Path;
+
+    /// Runs a frame for each byte, writing it to memory
+    fn run_frames<F>(bytes: &[u8], mut end_frame: F) where F: FnMut(&mut Interconnect) {
+        let mut interconnect = Interconnect::new(Display::headless(), Keypad::headless(), 0x1000);
+
+        for (frame, &byte) in bytes.iter().enumerate() {
+            interconnect.load(0x300 + frame as u16, &[byte]);
+            end_frame(&mut interconnect);
+        }
+    }
+
+    fn record(bytes: &[u8]) -> MovieRecorder {
+        let mut recorder = MovieRecorder::new(Path::new("test.movie"), &[0x12, 0x00], 7, MovieSettings::new(&Settings::default()));
+
+        run_frames(bytes, |interconnect| {
+            recorder.record_frame(&[false; 16]);
+            recorder.end_frame(interconnect);
+        });
+        recorder
+    }
+
+    fn replay(recorder: MovieRecorder, bytes: &[u8]) -> MoviePlayer {
+        let mut player = MoviePlayer::new(recorder.movie);
+
+        run_frames(bytes, |interconnect| {
+            player.next_frame().unwrap();
+            player.end_frame(interconnect);
+        });
+        player
+    }
+
+    #[test]
+    fn recordings_hash_every_frame() {
+        let recorder = record(&[1, 2, 3, 4]);
+
+        assert_eq!(recorder.movie.frames.len(), 4);
+        assert_eq!(recorder.movie.frame_hashes.len(), 4);
+        assert_eq!(replay(recorder, &[1, 2, 3, 4]).divergence, None);
+    }
+
+    #[test]
+    fn replays_report_the_first_diverging_frame() {
+        let player = replay(record(&[1, 2, 3, 4, 5]), &[1, 2, 9, 4, 5]);
+
+        assert_eq!(player.divergence, Some(3));
+    }
+
+    #[test]
+    fn settings_differences_name_what_changed() {
+        let recorded = MovieSettings::new(&Settings::default());
+
+        let mut settings = Settings::default();
+        assert!(recorded.differences(&settings).is_empty());
+
+        settings.quirks = QuirkProfile::Schip.quirks();
+        settings.instructions_per_frame += 1;
+        settings.font = FontSet::Schip.font();
+        assert_eq!(recorded.differences(&settings), vec!["quirks", "instructions per frame", "font"]);
+
+        recorded.apply(&mut settings);
+        assert!(recorded.differences(&settings).is_empty());
+    }
+}
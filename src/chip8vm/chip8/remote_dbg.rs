@@ -1,110 +1,224 @@
-use std::process;
 use std::thread;
-use std::net::{TcpListener, TcpStream, Shutdown};
-use std::io::prelude::*;
-use std::io::{BufReader, BufWriter};
-use std::sync::mpsc;
-use std::borrow::Borrow;
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
-use serde_json::from_str;
-use serde_json::error::Error;
-use bincode::{serialize, deserialize};
+use chip8_protocol::{Command, DbgError, Event, Reply, Request, Response, ServerMessage, PROTOCOL_VERSION};
+use chip8_protocol::framing::{read_frame, write_frame, FrameError};
 
+use super::gdb_stub;
+use super::settings::DebuggerSettings;
+
+/// How long a debugger request may wait for the VM loop to pick it up and answer
+const VM_REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+/// Time allowed for sending a reply or receiving the rest of a started frame
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Debugger command forwarded to the VM, which answers through `reply`
 #[derive(Debug)]
-pub enum DbgMessage {
-    CPU,
-    START,
-    STOP,
-    STEP,
-    RESTART,
-    MEM(u16, usize)
+pub struct DbgMessage {
+    pub command: Command,
+    pub reply: mpsc::Sender<Result<Response, DbgError>>
 }
 
-// TODO: move to shared lib
-#[derive(Serialize, Deserialize, Debug)]
-enum DbgCommand {
-    Cpu,
-    Start,
-    Step,
-    Stop,
-    Mem(u16, usize)
+/// Channels to the writers of attached debuggers, which receive the VM's events
+#[derive(Clone, Default)]
+pub struct Subscribers {
+    writers: Arc<Mutex<Vec<(usize, mpsc::Sender<ServerMessage>)>>>,
+    next_id: Arc<AtomicUsize>
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct CpuSnapshot {
-    pub v: [u8; 16],
-    pub i: u16,
-    pub pc: u16,
-    pub sp: u8,
-    pub stack: [u16; 16],
+/// Keeps events flowing to a writer until it is dropped
+pub struct Subscription {
+    id: usize,
+    subscribers: Subscribers
 }
 
-#[derive(Debug)]
-pub enum Chip8Snapshots {
-    CPU(CpuSnapshot),
-    MEM(Vec<u8>)
+impl Subscribers {
+    pub fn subscribe(&self, writer: mpsc::Sender<ServerMessage>) -> Subscription {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.writers.lock().unwrap().push((id, writer));
+        Subscription { id, subscribers: self.clone() }
+    }
+
+    fn publish(&self, event: Event) {
+        let mut writers = self.writers.lock().unwrap();
+        writers.retain(|&(_, ref writer)| writer.send(ServerMessage::Event(event.clone())).is_ok());
+    }
 }
 
-pub struct RemoteDbg;
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.subscribers.writers.lock().unwrap().retain(|&(id, _)| id != self.id);
+    }
+}
+
+pub struct RemoteDbg {
+    subscribers: Subscribers
+}
 
 impl RemoteDbg {
-    pub fn init(sender: mpsc::Sender<DbgMessage>, snapshots: mpsc::Receiver<Chip8Snapshots>) {
-        let dbg_thread = thread::spawn(  move|| {
-            let listener = TcpListener::bind("0.0.0.0:9876").unwrap();
-            for stream in listener.incoming() {
-                RemoteDbg::handle_dbg_client(stream.unwrap(), sender.clone(), snapshots.borrow());
+    /// Starts listening for debuggers, the actual address is reported since the port may have been picked by the OS
+    pub fn init(settings: &DebuggerSettings, sender: mpsc::Sender<DbgMessage>) -> Result<RemoteDbg, String> {
+        let listener = TcpListener::bind(&settings.address)
+            .map_err(|e| format!("Could not start the debugger server on {}: {}", settings.address, e))?;
+        let address = listener.local_addr()
+            .map_err(|e| format!("Could not start the debugger server on {}: {}", settings.address, e))?;
+
+        println!("Debugger listening on {}", address);
+        if settings.token.is_none() && !address.ip().is_loopback() {
+            warn!("Debugger server on {} is reachable from other machines without a token", address);
+        }
+
+        let subscribers = Subscribers::default();
+
+        if let Some(ref gdb_address) = settings.gdb_address {
+            if settings.token.is_some() {
+                warn!("The GDB stub on {} does not check the debugger token, any local user can attach", gdb_address);
+            }
+            gdb_stub::listen(gdb_address, sender.clone(), subscribers.clone())?;
+        }
+
+        let clients = subscribers.clone();
+        let token = settings.token.clone();
+
+        thread::spawn(move || {
+            for (connection, stream) in (1..).zip(listener.incoming()) {
+                match stream {
+                    Ok(stream) => {
+                        // Every debugger gets a session of its own, replies go back through the session's channels
+                        let sender = sender.clone();
+                        let clients = clients.clone();
+                        let token = token.clone();
+                        thread::spawn(move || {
+                            info!("Debugger {} connected", connection);
+                            RemoteDbg::handle_dbg_client(connection, stream, &token, &sender, &clients);
+                        });
+                    },
+                    Err(e) => warn!("Debugger connection failed: {}", e)
+                }
             }
         });
+
+        Ok(RemoteDbg { subscribers })
     }
 
-    fn handle_dbg_client(mut stream: TcpStream, sender: mpsc::Sender<DbgMessage>, snapshots: &mpsc::Receiver<Chip8Snapshots>) {
-        let mut reader = BufReader::new(&stream);
-        let mut writer = BufWriter::new(&stream);
+    /// Pushes the event to every attached debugger
+    pub fn publish(&self, event: Event) {
+        self.subscribers.publish(event);
+    }
+
+    fn handle_dbg_client(connection: u32, stream: TcpStream, token: &Option<String>, sender: &mpsc::Sender<DbgMessage>, subscribers: &Subscribers) {
+        if let Err(e) = stream.set_write_timeout(Some(IO_TIMEOUT)) {
+            warn!("Could not set debugger connection timeout: {}", e);
+        }
+
+        // Replies and events are written by a thread of their own, so that events can be pushed at any time
+        let (writer, outgoing) = mpsc::channel();
+        match stream.try_clone() {
+            Ok(stream) => thread::spawn(move || RemoteDbg::write_messages(stream, outgoing)),
+            Err(e) => {
+                warn!("Could not set up the debugger connection: {}", e);
+                return;
+            }
+        };
+
+        let mut stream = stream;
+        let mut attached = false;
+        // Unsubscribes once the debugger leaves
+        let mut _subscription = None;
 
         loop {
-            let mut line = String::new();
-            let result = reader.read_line(&mut line);
-
-            match result {
-                Ok(_) => {
-                    let command: DbgCommand = from_str(&line).unwrap();
-
-                    match command {
-                        DbgCommand::Start =>  { sender.send(DbgMessage::START); }
-                        DbgCommand::Step =>  { sender.send(DbgMessage::STEP); }
-                        DbgCommand::Stop => { sender.send(DbgMessage::STOP); }
-                        DbgCommand::Cpu => {
-                            sender.send(DbgMessage::CPU);
-                            // TODO: timeouts
-                            let cpu_snapshot = snapshots.recv().unwrap();
-
-                            match cpu_snapshot {
-                                Chip8Snapshots::CPU(snapshot) => {
-                                    let encoded = serialize(&snapshot).unwrap();
-                                    writer.write_all(encoded.as_ref());
-                                    writer.write(b"\n").unwrap();
-                                }
-                                _ => {}
-                            };
-                        },
-                        DbgCommand::Mem(addr, size) => {
-                            sender.send(DbgMessage::MEM(addr, size));
-                            let mem_dump = snapshots.recv().unwrap();
-
-                            match mem_dump {
-                                Chip8Snapshots::CPU(snapshot) => {},
-                                Chip8Snapshots::MEM(mem) => {
-                                    writer.write_all(mem.as_ref());
-                                    writer.write_all(b"\n");
-                            }
-                            };
-                        }
-                    }
-                    writer.flush();
+            let request = RemoteDbg::read_request(&mut stream);
+            let was_attached = attached;
 
+            let reply = match request {
+                Ok(request) => Reply {
+                    id: request.id,
+                    result: RemoteDbg::handle_request(request.command, token, &mut attached, sender)
+                },
+                Err(FrameError::Decode(reason)) => Reply { id: 0, result: Err(DbgError::Malformed(reason)) },
+                Err(FrameError::TooLarge(size)) => {
+                    // The rest of the stream cannot be interpreted anymore, so report and hang up
+                    let reply = Reply { id: 0, result: Err(DbgError::Malformed(format!("frame of {} bytes is too large", size))) };
+                    let _ = writer.send(ServerMessage::Reply(reply));
+                    warn!("Dropping debugger connection after a frame of {} bytes", size);
+                    return;
+                },
+                Err(FrameError::Closed) => {
+                    info!("Debugger {} disconnected", connection);
+                    return;
+                },
+                Err(e) => {
+                    warn!("Could not read from the debugger: {}", e);
+                    return;
                 }
-                Err(_) => { error!("In error!"); }
+            };
+
+            if writer.send(ServerMessage::Reply(reply)).is_err() {
+                return;
+            }
+
+            // Events only go to debuggers that completed the handshake
+            if attached && !was_attached {
+                _subscription = Some(subscribers.subscribe(writer.clone()));
             }
         }
     }
+
+    fn write_messages(mut stream: TcpStream, outgoing: mpsc::Receiver<ServerMessage>) {
+        for message in outgoing {
+            if let Err(e) = write_frame(&mut stream, &message) {
+                warn!("Could not write to the debugger: {}", e);
+                break;
+            }
+        }
+
+        // Also ends the reading side if the writes failed
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+
+    fn read_request(stream: &mut TcpStream) -> Result<Request, FrameError> {
+        let mut header = [0u8; 1];
+
+        // An idle debugger is fine, but a frame has to arrive in one piece once it started
+        stream.set_read_timeout(None)?;
+        if stream.peek(&mut header)? == 0 {
+            return Err(FrameError::Closed);
+        }
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+
+        read_frame(stream)
+    }
+
+    fn handle_request(command: Command, token: &Option<String>, attached: &mut bool, sender: &mpsc::Sender<DbgMessage>)
+                      -> Result<Response, DbgError> {
+        match command {
+            Command::Hello { version, .. } if version != PROTOCOL_VERSION =>
+                Err(DbgError::VersionMismatch { server: PROTOCOL_VERSION, client: version }),
+            Command::Hello { token: ref presented, .. } if token.is_some() && presented != token => {
+                warn!("Debugger presented a wrong token");
+                Err(DbgError::Unauthorized)
+            },
+            Command::Hello { .. } => {
+                *attached = true;
+                Ok(Response::Hello { version: PROTOCOL_VERSION })
+            },
+            _ if !*attached => Err(DbgError::HandshakeRequired),
+            command => execute(command, sender)
+        }
+    }
+}
+
+/// Hands the command to the VM loop and waits for its answer
+pub fn execute(command: Command, sender: &mpsc::Sender<DbgMessage>) -> Result<Response, DbgError> {
+    let (reply, result) = mpsc::channel();
+    sender.send(DbgMessage { command, reply }).map_err(|_| DbgError::VmUnavailable)?;
+
+    match result.recv_timeout(VM_REPLY_TIMEOUT) {
+        Ok(result) => result,
+        Err(mpsc::RecvTimeoutError::Timeout) => Err(DbgError::Timeout),
+        Err(mpsc::RecvTimeoutError::Disconnected) => Err(DbgError::VmUnavailable)
+    }
 }
@@ -0,0 +1,43 @@
+use super::arch::quirks::Quirks;
+use super::fonts::Font;
+use super::mem_map::MemoryMap;
+
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 10;
+
+/// Where the remote debugger server listens and what it expects from debuggers
+#[derive(Debug, Clone)]
+pub struct DebuggerSettings {
+    /// `host:port`, port 0 picks any free one
+    pub address: String,
+    /// Shared secret debuggers have to present in their handshake
+    pub token: Option<String>,
+    /// `host:port` of the GDB remote protocol stub, which runs next to the debugger server
+    pub gdb_address: Option<String>,
+    /// Bytes the execution history may use, 0 disables stepping backwards
+    pub history_budget: usize
+}
+
+/// Everything about the VM that can be tuned before it starts running
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub quirks: Quirks,
+    pub instructions_per_frame: u32,
+    pub memory_map: MemoryMap,
+    pub font: Font,
+    /// Remote debugger server, none runs when it is disabled
+    pub debugger: Option<DebuggerSettings>,
+    pub paused: bool
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            quirks: Quirks::default(),
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            memory_map: MemoryMap::default(),
+            font: Font::default(),
+            debugger: None,
+            paused: false
+        }
+    }
+}
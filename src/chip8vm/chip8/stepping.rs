@@ -0,0 +1,44 @@
+use chip8_protocol::{DbgError, StepMode};
+
+/// Where a debugger step stops, checked while the VM runs so that it takes no round trips per instruction
+#[derive(Debug, PartialEq)]
+pub enum StepTarget {
+    /// Instructions left to execute
+    Instructions(u32),
+    /// PC reaching the address, at the given stack depth if there is one
+    Address { pc: u16, sp: Option<u8> },
+    /// Stack getting shallower than the depth
+    Return { sp: u8 },
+    /// End of the current frame
+    Frame
+}
+
+impl StepTarget {
+    /// Target of a step starting at `pc`, which holds `opcode`
+    pub fn new(mode: StepMode, pc: u16, sp: u8, opcode: u16) -> Result<StepTarget, DbgError> {
+        match mode {
+            StepMode::Instructions(0) => Err(DbgError::InvalidArgument("has to step at least one instruction".to_owned())),
+            StepMode::Instructions(count) => Ok(StepTarget::Instructions(count)),
+            StepMode::Over if opcode & 0xF000 == 0x2000 => Ok(StepTarget::Address { pc: pc.wrapping_add(2), sp: Some(sp) }),
+            StepMode::Over => Ok(StepTarget::Instructions(1)),
+            StepMode::Out if sp == 0 => Err(DbgError::InvalidState("not inside a subroutine".to_owned())),
+            StepMode::Out => Ok(StepTarget::Return { sp }),
+            StepMode::Until(addr) => Ok(StepTarget::Address { pc: addr, sp: None }),
+            StepMode::Frame => Ok(StepTarget::Frame)
+        }
+    }
+
+    /// Has to be called after every instruction, returns whether the step is complete
+    pub fn after_instruction(&mut self, pc: u16, sp: u8) -> bool {
+        match *self {
+            StepTarget::Instructions(ref mut left) => {
+                *left -= 1;
+                *left == 0
+            },
+            StepTarget::Address { pc: target, sp: None } => pc == target,
+            StepTarget::Address { pc: target, sp: Some(depth) } => pc == target && sp == depth,
+            StepTarget::Return { sp: depth } => sp < depth,
+            StepTarget::Frame => false
+        }
+    }
+}
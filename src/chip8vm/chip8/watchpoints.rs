@@ -0,0 +1,79 @@
+use chip8_protocol::{WatchAccess, WatchpointHit, WatchpointInfo};
+
+/// Access that triggered a watchpoint, waiting for the VM to attach the instruction to it
+struct PendingHit {
+    id: u32,
+    access: WatchAccess,
+    addr: u16,
+    old: u8,
+    new: u8
+}
+
+/// Memory watchpoints, checked by `Interconnect` on every access made by the CPU
+#[derive(Default)]
+pub struct Watchpoints {
+    watchpoints: Vec<WatchpointInfo>,
+    /// Only the first hit of an instruction is reported, e.g. for `FX55` storing several registers
+    pending: Option<PendingHit>
+}
+
+impl Watchpoints {
+    pub fn add(&mut self, id: u32, start: u16, end: u16, access: WatchAccess) -> WatchpointInfo {
+        let watchpoint = WatchpointInfo { id, start, end, access, enabled: true, hits: 0 };
+        self.watchpoints.push(watchpoint.clone());
+        watchpoint
+    }
+
+    /// Returns whether a watchpoint with the id existed
+    pub fn delete(&mut self, id: u32) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|w| w.id != id);
+        self.watchpoints.len() != count
+    }
+
+    pub fn set_enabled(&mut self, id: u32, enabled: bool) -> Option<WatchpointInfo> {
+        let watchpoint = self.watchpoints.iter_mut().find(|w| w.id == id)?;
+        watchpoint.enabled = enabled;
+        Some(watchpoint.clone())
+    }
+
+    pub fn list(&self) -> Vec<WatchpointInfo> {
+        self.watchpoints.clone()
+    }
+
+    pub fn on_read(&mut self, addr: u16, value: u8) {
+        self.check(WatchAccess::Read, addr, value, value);
+    }
+
+    pub fn on_write(&mut self, addr: u16, old: u8, new: u8) {
+        self.check(WatchAccess::Write, addr, old, new);
+    }
+
+    /// Completes the hit caused by the instruction at `pc`, if any
+    pub fn take_hit(&mut self, pc: u16, opcode: u16) -> Option<WatchpointHit> {
+        self.pending.take().map(|hit| WatchpointHit {
+            id: hit.id,
+            access: hit.access,
+            addr: hit.addr,
+            old: hit.old,
+            new: hit.new,
+            pc,
+            opcode
+        })
+    }
+
+    fn check(&mut self, access: WatchAccess, addr: u16, old: u8, new: u8) {
+        if self.pending.is_some() || self.watchpoints.is_empty() {
+            return;
+        }
+
+        let matching = self.watchpoints.iter_mut().find(|w| {
+            w.enabled && w.start <= addr && addr <= w.end && (w.access == access || w.access == WatchAccess::Access)
+        });
+
+        if let Some(watchpoint) = matching {
+            watchpoint.hits += 1;
+            self.pending = Some(PendingHit { id: watchpoint.id, access, addr, old, new });
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use std::env;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use serde_json;
+use serde_json::Value;
+
+use chip8::arch::quirks::Quirks;
+use chip8::display::{Palette, DEFAULT_SCALE};
+use chip8::fonts::{Font, FontSet};
+use chip8::mem_map::MemoryMap;
+use chip8::settings::{DebuggerSettings, Settings, DEFAULT_INSTRUCTIONS_PER_FRAME};
+
+/// Key of the section holding per-ROM configuration, keyed by ROM SHA-1 or file name
+const OVERRIDES_KEY: &str = "overrides";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DisplayConfig {
+    pub scale: usize,
+    pub palette: Palette
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        DisplayConfig {
+            scale: DEFAULT_SCALE,
+            palette: Palette::default()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct InputConfig {
+    pub key_map: Option<PathBuf>,
+    pub controller_map: Option<PathBuf>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TimingConfig {
+    pub instructions_per_frame: u32
+}
+
+impl Default for TimingConfig {
+    fn default() -> Self {
+        TimingConfig {
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DebuggerConfig {
+    /// The debugger server only runs when enabled
+    pub enabled: bool,
+    pub bind: String,
+    /// 0 picks any free port
+    pub port: u16,
+    /// Shared secret debuggers have to present when attaching
+    pub token: Option<String>,
+    /// Port of the GDB remote protocol stub, which always listens on 127.0.0.1 since the protocol has no authentication
+    pub gdb_port: Option<u16>,
+    /// Memory kept for stepping backwards, in KiB; 0 disables the execution history
+    pub history_kib: usize
+}
+
+impl Default for DebuggerConfig {
+    fn default() -> Self {
+        DebuggerConfig {
+            enabled: false,
+            bind: "127.0.0.1".to_owned(),
+            port: 9876,
+            token: None,
+            gdb_port: None,
+            history_kib: 16 * 1024
+        }
+    }
+}
+
+/// Whether only the local machine can reach a server bound to the address
+pub fn is_loopback(bind: &str) -> bool {
+    match bind.parse::<IpAddr>() {
+        Ok(ip) => ip.is_loopback(),
+        Err(_) => bind == "localhost"
+    }
+}
+
+impl DebuggerConfig {
+    pub fn settings(&self) -> Result<Option<DebuggerSettings>, String> {
+        if !self.enabled {
+            return Ok(None);
+        }
+
+        // Anyone reaching the stub could read and write memory, the debugger token cannot protect it
+        if self.gdb_port.is_some() && !is_loopback(&self.bind) {
+            return Err(format!("The GDB stub only listens on 127.0.0.1, it cannot be combined with the bind address {}", self.bind));
+        }
+
+        Ok(Some(DebuggerSettings {
+            address: format!("{}:{}", self.bind, self.port),
+            token: self.token.clone(),
+            gdb_address: self.gdb_port.map(|port| format!("127.0.0.1:{}", port)),
+            history_budget: self.history_kib * 1024
+        }))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct FontConfig {
+    pub set: FontSet,
+    /// Custom font replacing the built-in set
+    pub file: Option<PathBuf>
+}
+
+impl FontConfig {
+    pub fn load(&self) -> Result<Font, String> {
+        match self.file {
+            Some(ref path) => Font::load(path),
+            None => Ok(self.set.font())
+        }
+    }
+}
+
+/// Effective configuration of a run
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Config {
+    pub display: DisplayConfig,
+    pub input: InputConfig,
+    pub timing: TimingConfig,
+    pub quirks: Quirks,
+    pub debugger: DebuggerConfig,
+    pub memory: MemoryMap,
+    pub font: FontConfig
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/chip8`, falling back to `~/.config/chip8`
+    pub fn directory() -> Option<PathBuf> {
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+
+        config_home.map(|dir| dir.join("chip8"))
+    }
+
+    pub fn default_path() -> Option<PathBuf> {
+        Config::directory().map(|dir| dir.join("config.json"))
+    }
+
+    /// Loads global defaults from the file, then applies the ROM's recommended settings (e.g. from the ROM
+    /// database) and finally the overrides matching the ROM's file name and its SHA-1.
+    /// A missing file results in the default configuration.
+    pub fn load(path: Option<&Path>, rom_path: &Path, rom_hash: &str, rom_defaults: Option<Value>) -> Result<Config, String> {
+        let mut file = match path {
+            Some(path) if path.exists() => {
+                let content = fs::read_to_string(path).map_err(|e| format!("Could not read config {:?}: {}", path, e))?;
+                serde_json::from_str(&content).map_err(|e| format!("Invalid config {:?}: {}", path, e))?
+            },
+            _ => {
+                debug!("No configuration file at {:?}, using defaults", path);
+                json!({})
+            }
+        };
+
+        let mut overrides = match file.as_object_mut().and_then(|sections| sections.remove(OVERRIDES_KEY)) {
+            Some(Value::Object(overrides)) => overrides,
+            Some(_) => return Err(format!("Invalid config {:?}: \"{}\" has to be an object", path, OVERRIDES_KEY)),
+            None => Default::default()
+        };
+
+        if let Some(rom_defaults) = rom_defaults {
+            merge(&mut file, rom_defaults);
+        }
+
+        let rom_name = rom_path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+
+        for key in &[rom_name, rom_hash] {
+            if let Some(rom_config) = overrides.remove(*key) {
+                info!("Applying configuration overrides for: {}", key);
+                merge(&mut file, rom_config);
+            }
+        }
+
+        serde_json::from_value(file).map_err(|e| format!("Invalid config {:?}: {}", path, e))
+    }
+
+    pub fn vm_settings(&self, paused: bool) -> Result<Settings, String> {
+        Ok(Settings {
+            quirks: self.quirks,
+            instructions_per_frame: self.timing.instructions_per_frame,
+            memory_map: self.memory,
+            font: self.font.load()?,
+            debugger: self.debugger.settings()?,
+            paused
+        })
+    }
+
+    pub fn to_pretty_string(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+/// Recursively merges objects, any other value in `overlay` replaces the one in `base`
+fn merge(base: &mut Value, overlay: Value) {
+    match overlay {
+        Value::Object(overlay) => {
+            if let Value::Object(ref mut base) = *base {
+                for (key, value) in overlay {
+                    merge(base.entry(key).or_insert(Value::Null), value);
+                }
+                return;
+            }
+
+            *base = Value::Object(overlay);
+        },
+        overlay => *base = overlay
+    }
+}
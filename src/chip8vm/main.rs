@@ -1,32 +1,139 @@
 #[macro_use] extern crate log;
 #[macro_use] extern crate bincode;
 #[macro_use] extern crate serde_derive;
+extern crate chip8_protocol;
+extern crate chip8_trace;
+extern crate clap;
 extern crate env_logger;
 extern crate sdl2;
 extern crate rand;
 extern crate serde;
-extern crate serde_json;
+#[macro_use] extern crate serde_json;
+extern crate sha1;
 
-use std::env;
 use std::fs;
-use std::io::prelude::*;
+use std::process;
 
 mod chip8;
+mod config;
+mod options;
+mod romdb;
 
-fn main() {
-    let rom_path = env::args().nth(1).expect("Provide rom location!");
+use chip8::controller::ControllerMapping;
+use chip8::keypad::KeyMap;
+use chip8_trace::{TraceFormat, TraceRecorder, TraceWriter};
+use config::Config;
+use options::Options;
+use romdb::RomDatabase;
 
+fn main() {
     env_logger::init();
 
-    let code = fs::read(&rom_path).expect("Could not read file");
+    let options = Options::from_args();
+
+    if let Err(err) = run(options) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let code = fs::read(&options.rom_path)
+        .map_err(|e| format!("Could not read ROM {:?}: {}", options.rom_path, e))?;
+
+    let rom_hash = chip8::movie::rom_hash(&code);
+
+    let rom_database = RomDatabase::load(Config::directory().map(|dir| dir.join("roms.json")))?;
+    let rom_defaults = match rom_database.lookup(&rom_hash) {
+        Some(rom_info) => {
+            info!("Recognized ROM: {}", rom_info.describe());
+            if rom_info.platform != romdb::Platform::Chip8 {
+                warn!("ROM targets {:?}, only CHIP-8 instructions are supported", rom_info.platform);
+            }
+            Some(rom_info.config_defaults())
+        },
+        None => {
+            warn!("ROM {} not found in the ROM database, using default settings", rom_hash);
+            None
+        }
+    };
+
+    let config_path = options.config_path.clone().or_else(Config::default_path);
+    let mut config = Config::load(config_path.as_ref().map(|p| p.as_path()), &options.rom_path, &rom_hash, rom_defaults)?;
+    options.apply(&mut config);
+
+    if options.print_config {
+        println!("{}", config.to_pretty_string());
+        return Ok(());
+    }
+
+    info!("Starting Chip8 emulation for ROM at: {:?} (SHA-1: {})", options.rom_path, rom_hash);
+    debug!("Effective configuration: {:?}", config);
+
+    config.memory.validate().map_err(|e| format!("Invalid memory map: {}", e))?;
+
+    let movie = match options.replay {
+        Some(ref replay_path) => {
+            let movie = chip8::movie::Movie::load(replay_path)
+                .map_err(|e| format!("Could not read movie {:?}: {}", replay_path, e))?;
+
+            if movie.rom_hash != rom_hash {
+                return Err(format!("Movie {:?} was recorded with a different ROM", replay_path));
+            }
+            Some(movie)
+        },
+        None => None
+    };
+
+    let mut settings = config.vm_settings(options.paused)?;
+    if let Some(ref movie) = movie {
+        for setting in movie.settings.differences(&settings) {
+            info!("Replaying with the {} the movie was recorded with", setting);
+        }
+        movie.settings.apply(&mut settings);
+    }
+
+    let mut chip8 = if options.headless {
+        chip8::Chip8::new(chip8::Display::headless(), chip8::Keypad::headless(), settings)
+    } else {
+        let sdl_context = sdl2::init()?;
+
+        let display = chip8::display::Display::new(&sdl_context, config.display.scale, config.display.palette);
+
+        let key_map = match config.input.key_map {
+            Some(ref path) => KeyMap::load(path)?,
+            None => KeyMap::default()
+        };
+        let controller_mapping = match config.input.controller_map {
+            Some(ref path) => ControllerMapping::load(path)
+                .map_err(|e| format!("Could not load controller mapping {:?}: {}", path, e))?,
+            None => ControllerMapping::for_rom(&options.rom_path)
+        };
+        let keypad = chip8::keypad::Keypad::new(&sdl_context, key_map, controller_mapping);
+
+        chip8::Chip8::new(display, keypad, settings)
+    };
+
+    if let Some(seed) = options.seed {
+        chip8.reseed(seed);
+    }
+
+    info!("RNG seed: {:?}", chip8.seed());
 
-    info!("Starting Chip8 emulation for ROM at: {:#}", rom_path);
+    if let Some(movie) = movie {
+        chip8.replay_movie(movie)?;
+    } else if let Some(ref record_path) = options.record {
+        chip8.record_movie(record_path, &code);
+    }
 
-    let sdl_context = sdl2::init().unwrap();
+    if let Some(ref trace_path) = options.trace {
+        let format = options.trace_format.unwrap_or_else(|| TraceFormat::from_path(trace_path));
+        let writer = TraceWriter::create(trace_path, format)
+            .map_err(|e| format!("Could not create trace {:?}: {}", trace_path, e))?;
+        chip8.record_trace(TraceRecorder::new(writer, options.trace_filter.clone()));
+    }
 
-    let display = chip8::display::Display::new(&sdl_context);
-    let keypad = chip8::keypad::Keypad::new(&sdl_context);
-    let mut chip8 = chip8::Chip8::new(display, keypad);
-    chip8.load_rom(&code);
-    chip8.run();
+    chip8.load_rom(&code).map_err(|e| format!("Could not load ROM {:?}: {}", options.rom_path, e))?;
+    chip8.set_rom_path(&options.rom_path);
+    chip8.run()
 }
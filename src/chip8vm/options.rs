@@ -0,0 +1,362 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use clap::{App, Arg, ArgMatches, Error, ErrorKind};
+
+use chip8::arch::quirks::QuirkProfile;
+use chip8::display::Palette;
+use chip8::fonts::FontSet;
+use chip8::mem_map::{self, MemoryLayout};
+use chip8_trace::{AddressRange, TraceFilter, TraceFormat, Trigger};
+use config::{self, Config};
+
+/// Options of a single run, as given on the command line
+pub struct Options {
+    pub rom_path: PathBuf,
+    pub config_path: Option<PathBuf>,
+    pub print_config: bool,
+    pub headless: bool,
+    pub paused: bool,
+    pub seed: Option<u64>,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub trace: Option<PathBuf>,
+    pub trace_format: Option<TraceFormat>,
+    pub trace_filter: TraceFilter,
+
+    scale: Option<usize>,
+    palette: Option<Palette>,
+    instructions_per_frame: Option<u32>,
+    quirks: Option<QuirkProfile>,
+    debugger: bool,
+    dbg_bind: Option<String>,
+    dbg_port: Option<u16>,
+    dbg_token: Option<String>,
+    gdb_port: Option<u16>,
+    history_kib: Option<usize>,
+    key_map: Option<PathBuf>,
+    controller_map: Option<PathBuf>,
+    memory_layout: Option<MemoryLayout>,
+    load_address: Option<u16>,
+    font_address: Option<u16>,
+    font: Option<FontSet>,
+    font_file: Option<PathBuf>
+}
+
+impl Options {
+    pub fn from_args() -> Options {
+        let matches = App::new("chip8")
+            .version(env!("CARGO_PKG_VERSION"))
+            .about("CHIP-8 virtual machine")
+            .arg(Arg::with_name("ROM")
+                .help("ROM image to run")
+                .required(true)
+                .index(1))
+            .arg(Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .help("Configuration file to use instead of the one in the XDG config directory"))
+            .arg(Arg::with_name("print-config")
+                .long("print-config")
+                .help("Prints the effective configuration for the ROM and exits"))
+            .arg(Arg::with_name("scale")
+                .long("scale")
+                .value_name("N")
+                .help("Size of a single CHIP-8 pixel on screen"))
+            .arg(Arg::with_name("palette")
+                .long("palette")
+                .value_name("FG,BG")
+                .help("Foreground and background colors as hex RGB, e.g. ffffff,000000"))
+            .arg(Arg::with_name("ipf")
+                .long("ipf")
+                .value_name("N")
+                .help("Instructions executed per 60Hz frame"))
+            .arg(Arg::with_name("quirks")
+                .long("quirks")
+                .value_name("PROFILE")
+                .possible_values(&["chip8", "schip", "xochip"])
+                .help("Interpreter behaviour to emulate [default: chip8]"))
+            .arg(Arg::with_name("debugger")
+                .long("debugger")
+                .help("Runs the remote debugger server"))
+            .arg(Arg::with_name("dbg-bind")
+                .long("dbg-bind")
+                .value_name("ADDRESS")
+                .help("Address the remote debugger listens on (127.0.0.1 by default)"))
+            .arg(Arg::with_name("dbg-port")
+                .long("dbg-port")
+                .value_name("PORT")
+                .help("Port the remote debugger listens on, 0 picks a free one"))
+            .arg(Arg::with_name("dbg-token")
+                .long("dbg-token")
+                .value_name("TOKEN")
+                .help("Secret debuggers have to present to attach"))
+            .arg(Arg::with_name("gdb-port")
+                .long("gdb-port")
+                .value_name("PORT")
+                .help("Also serves the GDB remote protocol on 127.0.0.1:PORT (implies --debugger)"))
+            .arg(Arg::with_name("history-kib")
+                .long("history-kib")
+                .value_name("KIB")
+                .help("Memory kept for stepping backwards in the debugger, 0 disables it (16384 by default)"))
+            .arg(Arg::with_name("headless")
+                .long("headless")
+                .help("Runs without a window and without input"))
+            .arg(Arg::with_name("paused")
+                .long("paused")
+                .help("Starts the VM stopped, waiting for the debugger (implies --debugger)"))
+            .arg(Arg::with_name("seed")
+                .long("seed")
+                .value_name("N")
+                .help("Seed of the random number generator used by CXNN"))
+            .arg(Arg::with_name("key-map")
+                .long("key-map")
+                .value_name("FILE")
+                .help("JSON file mapping key names to keypad keys, e.g. {\"Q\": \"K4\"}"))
+            .arg(Arg::with_name("controller-map")
+                .long("controller-map")
+                .value_name("FILE")
+                .help("JSON file with game controller bindings (defaults to <ROM>.pad.json)"))
+            .arg(Arg::with_name("memory-layout")
+                .long("memory-layout")
+                .value_name("LAYOUT")
+                .possible_values(&["vip", "eti660", "64k"])
+                .help("Memory map of the emulated machine (RAM size, load and font addresses)"))
+            .arg(Arg::with_name("font-address")
+                .long("font-address")
+                .value_name("ADDRESS")
+                .help("Address the font is stored at, the big font follows right after it"))
+            .arg(Arg::with_name("font")
+                .long("font")
+                .value_name("NAME")
+                .possible_values(&["vip", "dream6800", "eti660", "schip", "octo"])
+                .help("Built-in font set"))
+            .arg(Arg::with_name("font-file")
+                .long("font-file")
+                .value_name("FILE")
+                .help("Raw font file: 80 bytes of 4x5 glyphs, optionally followed by 160 bytes of 8x10 ones"))
+            .arg(Arg::with_name("load-address")
+                .long("load-address")
+                .value_name("ADDRESS")
+                .help("Address the ROM is loaded at and execution starts from"))
+            .arg(Arg::with_name("record")
+                .long("record")
+                .value_name("FILE")
+                .conflicts_with("replay")
+                .help("Records keypad input to a movie file"))
+            .arg(Arg::with_name("replay")
+                .long("replay")
+                .value_name("FILE")
+                .help("Replays keypad input from a movie file"))
+            .arg(Arg::with_name("trace")
+                .long("trace")
+                .value_name("FILE")
+                .help("Records every executed instruction to a trace file"))
+            .arg(Arg::with_name("trace-format")
+                .long("trace-format")
+                .value_name("FORMAT")
+                .possible_values(&["binary", "json"])
+                .help("Trace file format, JSON lines for .jsonl and .json files and binary otherwise by default"))
+            .arg(Arg::with_name("trace-range")
+                .long("trace-range")
+                .value_name("START-END")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Only traces instructions within the address range, can be repeated"))
+            .arg(Arg::with_name("trace-start")
+                .long("trace-start")
+                .value_name("TRIGGER")
+                .help("Starts tracing once PC reaches an address (ADDR or pc=ADDR) or after cycle=N instructions"))
+            .arg(Arg::with_name("trace-stop")
+                .long("trace-stop")
+                .value_name("TRIGGER")
+                .help("Stops tracing once PC reaches an address (ADDR or pc=ADDR) or after cycle=N instructions"))
+            .get_matches();
+
+        let options = Options {
+            rom_path: PathBuf::from(matches.value_of("ROM").unwrap()),
+            config_path: matches.value_of("config").map(PathBuf::from),
+            print_config: matches.is_present("print-config"),
+            headless: matches.is_present("headless"),
+            paused: matches.is_present("paused"),
+            seed: parse_value(&matches, "seed", u64::from_str),
+            record: matches.value_of("record").map(PathBuf::from),
+            replay: matches.value_of("replay").map(PathBuf::from),
+            trace: matches.value_of("trace").map(PathBuf::from),
+            trace_format: parse_value(&matches, "trace-format", TraceFormat::from_str),
+            trace_filter: TraceFilter {
+                ranges: parse_values(&matches, "trace-range", AddressRange::from_str),
+                start: parse_value(&matches, "trace-start", Trigger::from_str),
+                stop: parse_value(&matches, "trace-stop", Trigger::from_str)
+            },
+
+            scale: parse_value(&matches, "scale", usize::from_str),
+            palette: parse_value(&matches, "palette", parse_palette),
+            instructions_per_frame: parse_value(&matches, "ipf", u32::from_str),
+            quirks: parse_value(&matches, "quirks", QuirkProfile::from_str),
+            debugger: matches.is_present("debugger"),
+            dbg_bind: matches.value_of("dbg-bind").map(String::from),
+            dbg_port: parse_value(&matches, "dbg-port", u16::from_str),
+            dbg_token: matches.value_of("dbg-token").map(String::from),
+            gdb_port: parse_value(&matches, "gdb-port", u16::from_str),
+            history_kib: parse_value(&matches, "history-kib", usize::from_str),
+            key_map: matches.value_of("key-map").map(PathBuf::from),
+            controller_map: matches.value_of("controller-map").map(PathBuf::from),
+            memory_layout: parse_value(&matches, "memory-layout", MemoryLayout::from_str),
+            load_address: parse_value(&matches, "load-address", parse_number),
+            font_address: parse_value(&matches, "font-address", parse_number),
+            font: parse_value(&matches, "font", FontSet::from_str),
+            font_file: matches.value_of("font-file").map(PathBuf::from)
+        };
+
+        if options.scale == Some(0) || options.instructions_per_frame == Some(0) {
+            Error::with_description("--scale and --ipf have to be greater than 0", ErrorKind::InvalidValue).exit();
+        }
+
+        if let (Some(_), Some(ref bind)) = (options.gdb_port, options.dbg_bind.as_ref()) {
+            if !config::is_loopback(bind) {
+                Error::with_description(&format!("--gdb-port cannot be combined with --dbg-bind {}: the GDB stub has no \
+                                                  authentication and only listens on 127.0.0.1", bind),
+                                        ErrorKind::ArgumentConflict).exit();
+            }
+        }
+
+        options
+    }
+
+    /// Overrides configuration values with the ones given on the command line
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(scale) = self.scale {
+            config.display.scale = scale;
+        }
+        if let Some(palette) = self.palette {
+            config.display.palette = palette;
+        }
+        if let Some(ipf) = self.instructions_per_frame {
+            config.timing.instructions_per_frame = ipf;
+        }
+        if let Some(profile) = self.quirks {
+            config.quirks = profile.quirks();
+        }
+        if self.debugger || self.paused || self.gdb_port.is_some() {
+            config.debugger.enabled = true;
+        }
+        if let Some(ref bind) = self.dbg_bind {
+            config.debugger.bind = bind.clone();
+        }
+        if let Some(port) = self.dbg_port {
+            config.debugger.port = port;
+        }
+        if let Some(ref token) = self.dbg_token {
+            config.debugger.token = Some(token.clone());
+        }
+        if let Some(port) = self.gdb_port {
+            config.debugger.gdb_port = Some(port);
+        }
+        if let Some(kib) = self.history_kib {
+            config.debugger.history_kib = kib;
+        }
+        if let Some(ref key_map) = self.key_map {
+            config.input.key_map = Some(key_map.clone());
+        }
+        if let Some(ref controller_map) = self.controller_map {
+            config.input.controller_map = Some(controller_map.clone());
+        }
+        if let Some(layout) = self.memory_layout {
+            config.memory = layout.memory_map();
+        }
+        if let Some(load_address) = self.load_address {
+            config.memory.rom_location = load_address;
+        }
+        if let Some(font_address) = self.font_address {
+            config.memory.fonts_location = font_address;
+            // Addresses too high for the fonts are reported by `MemoryMap::validate`
+            config.memory.big_fonts_location = font_address.saturating_add(mem_map::FONTS_SIZE as u16);
+        }
+        if let Some(font) = self.font {
+            config.font.set = font;
+            config.font.file = None;
+        }
+        if let Some(ref font_file) = self.font_file {
+            config.font.file = Some(font_file.clone());
+        }
+    }
+}
+
+/// Parses an optional argument, exiting with a usage error when it is malformed
+fn parse_value<T, E, F>(matches: &ArgMatches, name: &str, parse: F) -> Option<T>
+    where F: Fn(&str) -> Result<T, E>, E: ToString {
+    matches.value_of(name).map(|value| match parse(value) {
+        Ok(parsed) => parsed,
+        Err(err) => Error::with_description(&format!("Invalid value '{}' for --{}: {}", value, name, err.to_string()),
+                                            ErrorKind::InvalidValue).exit()
+    })
+}
+
+fn parse_values<T, E, F>(matches: &ArgMatches, name: &str, parse: F) -> Vec<T>
+    where F: Fn(&str) -> Result<T, E>, E: ToString {
+    matches.values_of(name).map_or(vec![], |values| values.map(|value| match parse(value) {
+        Ok(parsed) => parsed,
+        Err(err) => Error::with_description(&format!("Invalid value '{}' for --{}: {}", value, name, err.to_string()),
+                                            ErrorKind::InvalidValue).exit()
+    }).collect())
+}
+
+/// Accepts both decimal and `0x` prefixed hexadecimal numbers
+fn parse_number(value: &str) -> Result<u16, String> {
+    let parsed = if value.starts_with("0x") || value.starts_with("0X") {
+        u16::from_str_radix(&value[2..], 16)
+    } else {
+        u16::from_str(value)
+    };
+
+    parsed.map_err(|e| e.to_string())
+}
+
+fn parse_color(value: &str) -> Result<[u8; 3], String> {
+    let hex = value.trim_start_matches('#');
+
+    // Checked byte by byte, so that slicing below never splits a multi-byte character
+    if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("{} is not a RRGGBB color", value));
+    }
+
+    let mut color = [0; 3];
+    for i in 0..3 {
+        color[i] = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|e| e.to_string())?;
+    }
+
+    Ok(color)
+}
+
+fn parse_palette(value: &str) -> Result<Palette, String> {
+    let colors: Vec<&str> = value.split(',').collect();
+
+    if colors.len() != 2 {
+        return Err("expected two colors separated by a comma".to_owned());
+    }
+
+    Ok(Palette {
+        foreground: parse_color(colors[0])?,
+        background: parse_color(colors[1])?
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_color, parse_palette};
+
+    #[test]
+    fn parses_rrggbb_colors() {
+        assert_eq!(parse_color("#ffb000"), Ok([255, 176, 0]));
+        assert_eq!(parse_color("28140A"), Ok([40, 20, 10]));
+    }
+
+    #[test]
+    fn rejects_colors_that_are_not_six_hex_digits() {
+        for value in &["aééa", "#aééa", "+fffff", "ff00", "gg0000", "ff00000"] {
+            assert_eq!(parse_color(value), Err(format!("{} is not a RRGGBB color", value)));
+        }
+        assert!(parse_palette("aééa,000000").is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json;
+use serde_json::Value;
+
+use chip8::arch::quirks::{QuirkProfile, Quirks};
+use chip8::display::Palette;
+use chip8::keypad::Keys;
+
+/// Database shipped with the VM, keyed by ROM SHA-1
+const EMBEDDED_DATABASE: &str = include_str!("roms.json");
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Platform {
+    #[serde(rename = "CHIP-8")]
+    Chip8,
+    #[serde(rename = "SCHIP")]
+    Schip,
+    #[serde(rename = "XO-CHIP")]
+    XoChip
+}
+
+impl Platform {
+    pub fn quirk_profile(&self) -> QuirkProfile {
+        match *self {
+            Platform::Chip8 => QuirkProfile::Chip8,
+            Platform::Schip => QuirkProfile::Schip,
+            Platform::XoChip => QuirkProfile::XoChip
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RomInfo {
+    pub title: String,
+    pub author: Option<String>,
+    pub platform: Platform,
+    /// Overrides the quirks of the platform's profile when the ROM needs something else
+    pub quirks: Option<Quirks>,
+    pub instructions_per_frame: Option<u32>,
+    #[serde(default)]
+    pub keys: HashMap<Keys, String>,
+    pub palette: Option<Palette>
+}
+
+impl RomInfo {
+    /// Configuration recommended for the ROM, in the shape of the configuration file
+    pub fn config_defaults(&self) -> Value {
+        let quirks = self.quirks.unwrap_or_else(|| self.platform.quirk_profile().quirks());
+
+        let mut defaults = json!({ "quirks": quirks });
+
+        if let Some(ipf) = self.instructions_per_frame {
+            defaults["timing"] = json!({ "instructions_per_frame": ipf });
+        }
+        if let Some(palette) = self.palette {
+            defaults["display"] = json!({ "palette": palette });
+        }
+
+        defaults
+    }
+
+    pub fn describe(&self) -> String {
+        let mut description = format!("{} ({:?})", self.title, self.platform);
+
+        if let Some(ref author) = self.author {
+            description.push_str(&format!(" by {}", author));
+        }
+
+        let mut keys: Vec<(&Keys, &String)> = self.keys.iter().collect();
+        keys.sort_by_key(|&(key, _)| *key as usize);
+
+        if !keys.is_empty() {
+            let hints: Vec<String> = keys.iter().map(|&(key, hint)| format!("{:?}: {}", key, hint)).collect();
+            description.push_str(&format!(", controls: {}", hints.join(", ")));
+        }
+
+        description
+    }
+}
+
+pub struct RomDatabase {
+    roms: HashMap<String, RomInfo>
+}
+
+impl RomDatabase {
+    /// Loads the embedded database, extended (and overridden) by the local one when it exists
+    pub fn load(local_path: Option<PathBuf>) -> Result<RomDatabase, String> {
+        let mut roms: HashMap<String, RomInfo> = serde_json::from_str(EMBEDDED_DATABASE)
+            .map_err(|e| format!("Invalid embedded ROM database: {}", e))?;
+
+        if let Some(path) = local_path {
+            if path.exists() {
+                let local = RomDatabase::load_file(&path)?;
+                debug!("Loaded {} entries from local ROM database {:?}", local.len(), path);
+                roms.extend(local);
+            }
+        }
+
+        Ok(RomDatabase {
+            roms: roms.into_iter().map(|(hash, info)| (hash.to_lowercase(), info)).collect()
+        })
+    }
+
+    fn load_file(path: &Path) -> Result<HashMap<String, RomInfo>, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("Could not read ROM database {:?}: {}", path, e))?;
+        serde_json::from_str(&content).map_err(|e| format!("Invalid ROM database {:?}: {}", path, e))
+    }
+
+    pub fn lookup(&self, rom_hash: &str) -> Option<&RomInfo> {
+        self.roms.get(&rom_hash.to_lowercase())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use chip8::arch::quirks::QuirkProfile;
+    use chip8::movie::rom_hash;
+    use std::collections::HashMap;
+
+    use serde_json;
+    use serde_json::Value;
+
+    use super::{Platform, RomDatabase, EMBEDDED_DATABASE};
+
+    /// Clears the screen and loops forever
+    const ROM: &[u8] = &[0x00, 0xE0, 0x12, 0x02];
+
+    fn database(content: &str) -> RomDatabase {
+        let path = env::temp_dir().join(format!("chip8-romdb-{}-{}.json", process::id(), content.len()));
+        fs::write(&path, content).unwrap();
+        let database = RomDatabase::load(Some(path.clone()));
+        fs::remove_file(&path).unwrap();
+        database.unwrap()
+    }
+
+    #[test]
+    fn embedded_entries_are_found_by_their_hash() {
+        let embedded: HashMap<String, Value> = serde_json::from_str(EMBEDDED_DATABASE).unwrap();
+        let database = RomDatabase::load(None).unwrap();
+
+        for hash in embedded.keys() {
+            assert!(hash.len() == 40 && hash.bytes().all(|b| b.is_ascii_hexdigit()), "{} is not a SHA-1", hash);
+            assert!(!database.lookup(hash).unwrap().title.is_empty());
+        }
+    }
+
+    #[test]
+    fn looks_up_roms_by_hash_ignoring_case() {
+        let hash = rom_hash(ROM);
+        let database = database(&format!(r#"{{ "{}": {{ "title": "Loop", "platform": "SCHIP" }} }}"#, hash.to_uppercase()));
+
+        let info = database.lookup(&hash).expect("ROM not found");
+        assert_eq!(info.title, "Loop");
+        assert_eq!(info.platform, Platform::Schip);
+        assert!(database.lookup(&rom_hash(&[0x12, 0x00])).is_none());
+    }
+
+    #[test]
+    fn config_defaults_use_the_platform_quirks() {
+        let hash = rom_hash(ROM);
+        let database = database(&format!(r#"{{ "{}": {{ "title": "Loop", "platform": "SCHIP", "instructions_per_frame": 30 }} }}"#, hash));
+        let defaults = database.lookup(&hash).unwrap().config_defaults();
+
+        assert_eq!(defaults["quirks"], json!(QuirkProfile::Schip.quirks()));
+        assert_eq!(defaults["timing"]["instructions_per_frame"], json!(30));
+        assert_eq!(defaults["display"], Value::Null);
+    }
+
+    #[test]
+    fn config_defaults_prefer_the_rom_quirks() {
+        let hash = rom_hash(ROM);
+        let database = database(&format!(r#"{{ "{}": {{ "title": "Loop", "platform": "CHIP-8", "quirks": {{ "shift_vx": true }} }} }}"#, hash));
+        let defaults = database.lookup(&hash).unwrap().config_defaults();
+
+        assert_eq!(defaults["quirks"]["shift_vx"], json!(true));
+        assert_eq!(defaults["quirks"]["load_store_increment_i"], json!(true));
+        assert_eq!(defaults["quirks"]["vf_reset"], json!(true));
+    }
+}
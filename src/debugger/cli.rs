@@ -1,104 +1,283 @@
-use std::io::{stdin, stdout};
-use std::io::prelude::*;
-use std::net::{TcpStream, Shutdown};
-use std::io::{BufReader, BufWriter};
+use std::net::TcpStream;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
-use super::disasm;
+use chip8_protocol::{BreakpointInfo, Command, CpuSnapshot, DbgError, Event, FontSnapshot, HistoryInfo, MemoryMapSnapshot, Reply,
+                     Request, ResetMode, Response, RomSource, ServerMessage, StepMode, VmStatus, WatchAccess, WatchpointInfo,
+                     PROTOCOL_VERSION};
+use chip8_protocol::expr::{Expr, FormatPart, Register};
+use chip8_protocol::framing::{read_frame, write_frame, FrameError};
 
-use serde_json::{Value, Error};
-use serde_json::to_string;
+/// How long to wait for the VM to answer a request
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
 
-use bincode::{serialize, deserialize};
+/// Called from the reading thread when the VM pushed an event
+type Waker = Arc<Mutex<Option<Box<dyn Fn() + Send>>>>;
 
 pub struct Cli {
-    tcp_stream: TcpStream
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-pub struct CpuSnapshot {
-    pub v: [u8; 16],
-    pub i: u16,
-    pub pc: u16,
-    pub sp: u8,
-    pub stack: [u16; 16],
-}
-
-// TODO: move to shared lib
-#[derive(Serialize, Deserialize, Debug)]
-enum DbgCommand {
-    Cpu,
-    Start,
-    Step,
-    Stop,
-    Mem(u16, usize)
+    tcp_stream: TcpStream,
+    next_id: u32,
+    replies: mpsc::Receiver<Reply>,
+    events: mpsc::Receiver<Event>,
+    waker: Waker
 }
 
 // TODO: Refactor to make it more sane
 impl Cli {
-    pub fn new() -> Cli {
-        Cli {
-            tcp_stream: TcpStream::connect("127.0.0.1:9876").unwrap()
+    /// Connects to the VM and performs the protocol handshake, presenting the token if the VM requires one
+    pub fn new(address: &str, token: Option<String>) -> Result<Cli, DbgError> {
+        let tcp_stream = TcpStream::connect(address).map_err(|e| DbgError::Io(e.to_string()))?;
+        tcp_stream.set_write_timeout(Some(REPLY_TIMEOUT)).map_err(|e| DbgError::Io(e.to_string()))?;
+        let reader = tcp_stream.try_clone().map_err(|e| DbgError::Io(e.to_string()))?;
+
+        let (reply_sender, replies) = mpsc::channel();
+        let (event_sender, events) = mpsc::channel();
+        let waker: Waker = Arc::new(Mutex::new(None));
+        let reader_waker = waker.clone();
+        thread::spawn(move || Cli::read_messages(reader, reply_sender, event_sender, reader_waker));
+
+        let mut cli = Cli { tcp_stream, next_id: 0, replies, events, waker };
+
+        match cli.request(Command::Hello { version: PROTOCOL_VERSION, token })? {
+            Response::Hello { .. } => Ok(cli),
+            other => Err(Cli::unexpected(other))
+        }
+    }
+
+    /// Has `wake` called whenever the VM pushes an event, from another thread
+    pub fn on_event<F: Fn() + Send + 'static>(&mut self, wake: F) {
+        *self.waker.lock().unwrap() = Some(Box::new(wake));
+    }
+
+    /// Events pushed by the VM since the last call
+    pub fn events(&mut self) -> Vec<Event> {
+        self.events.try_iter().collect()
+    }
+
+    pub fn start(&mut self) -> Result<(), DbgError> {
+        self.execute(Command::Start)
+    }
+
+    /// Starts a step, the VM stops once it completes
+    pub fn step(&mut self, mode: StepMode) -> Result<(), DbgError> {
+        self.execute(Command::Step(mode))
+    }
+
+    pub fn reverse_step(&mut self, count: u32) -> Result<(), DbgError> {
+        self.execute(Command::ReverseStep(count))
+    }
+
+    pub fn reverse_continue(&mut self) -> Result<(), DbgError> {
+        self.execute(Command::ReverseContinue)
+    }
+
+    pub fn history(&mut self) -> Result<HistoryInfo, DbgError> {
+        match self.request(Command::History)? {
+            Response::History(history) => Ok(history),
+            other => Err(Cli::unexpected(other))
+        }
+    }
+
+    pub fn stop(&mut self) -> Result<(), DbgError> {
+        self.execute(Command::Stop)
+    }
+
+    pub fn reset(&mut self, mode: ResetMode) -> Result<(), DbgError> {
+        self.execute(Command::Reset(mode))
+    }
+
+    pub fn load_rom(&mut self, source: RomSource) -> Result<(), DbgError> {
+        self.execute(Command::LoadRom(source))
+    }
+
+    pub fn reload_rom(&mut self) -> Result<(), DbgError> {
+        self.execute(Command::ReloadRom)
+    }
+
+    pub fn reseed(&mut self, seed: u64) -> Result<(), DbgError> {
+        self.execute(Command::Reseed(seed))
+    }
+
+    pub fn cpu(&mut self) -> Result<CpuSnapshot, DbgError> {
+        match self.request(Command::Cpu)? {
+            Response::Cpu(snapshot) => Ok(snapshot),
+            other => Err(Cli::unexpected(other))
+        }
+    }
+
+    pub fn mem(&mut self, addr: u16, size: usize) -> Result<Vec<u8>, DbgError> {
+        match self.request(Command::Mem { addr, size })? {
+            Response::Mem(bytes) => Ok(bytes),
+            other => Err(Cli::unexpected(other))
         }
     }
 
-    pub fn start(&mut self) {
-        let mut writer = BufWriter::new(&self.tcp_stream);
+    pub fn set_register(&mut self, register: Register, value: u16) -> Result<(), DbgError> {
+        self.execute(Command::SetRegister { register, value })
+    }
 
-        let command = to_string(&DbgCommand::Start).unwrap();
+    pub fn set_stack(&mut self, slot: u8, value: u16) -> Result<(), DbgError> {
+        self.execute(Command::SetStack { slot, value })
+    }
 
-        writer.write(format!("{}\n", command).as_bytes());
-        writer.flush().unwrap();
+    pub fn poke(&mut self, addr: u16, bytes: Vec<u8>) -> Result<(), DbgError> {
+        self.execute(Command::Poke { addr, bytes })
     }
 
-    pub fn step(&mut self) {
-        let mut writer = BufWriter::new(&self.tcp_stream);
+    pub fn fill(&mut self, start: u16, end: u16, value: u8) -> Result<(), DbgError> {
+        self.execute(Command::Fill { start, end, value })
+    }
+
+    pub fn font(&mut self) -> Result<FontSnapshot, DbgError> {
+        match self.request(Command::Font)? {
+            Response::Font(snapshot) => Ok(snapshot),
+            other => Err(Cli::unexpected(other))
+        }
+    }
 
-        let command = to_string(&DbgCommand::Step).unwrap();
+    pub fn memory_map(&mut self) -> Result<MemoryMapSnapshot, DbgError> {
+        match self.request(Command::MemoryMap)? {
+            Response::MemoryMap(memory_map) => Ok(memory_map),
+            other => Err(Cli::unexpected(other))
+        }
+    }
 
-        writer.write(format!("{}\n", command).as_bytes());
-        writer.flush().unwrap();
+    pub fn keypad(&mut self) -> Result<[bool; 16], DbgError> {
+        match self.request(Command::Keypad)? {
+            Response::Keypad(keys) => Ok(keys),
+            other => Err(Cli::unexpected(other))
+        }
     }
 
-    pub fn stop(&mut self) {
-        let mut writer = BufWriter::new(&self.tcp_stream);
+    pub fn status(&mut self) -> Result<VmStatus, DbgError> {
+        match self.request(Command::Status)? {
+            Response::Status(status) => Ok(status),
+            other => Err(Cli::unexpected(other))
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16, condition: Option<Expr>) -> Result<BreakpointInfo, DbgError> {
+        match self.request(Command::AddBreakpoint { addr, condition })? {
+            Response::Breakpoint(breakpoint) => Ok(breakpoint),
+            other => Err(Cli::unexpected(other))
+        }
+    }
+
+    pub fn add_tracepoint(&mut self, addr: u16, message: Vec<FormatPart>, condition: Option<Expr>) -> Result<BreakpointInfo, DbgError> {
+        match self.request(Command::AddTracepoint { addr, condition, message })? {
+            Response::Breakpoint(tracepoint) => Ok(tracepoint),
+            other => Err(Cli::unexpected(other))
+        }
+    }
+
+    pub fn ignore_breakpoint(&mut self, id: u32, count: u32) -> Result<(), DbgError> {
+        match self.request(Command::IgnoreBreakpoint { id, count })? {
+            Response::Breakpoint(_) => Ok(()),
+            other => Err(Cli::unexpected(other))
+        }
+    }
 
-        let command = to_string(&DbgCommand::Stop).unwrap();
+    pub fn delete_breakpoint(&mut self, id: u32) -> Result<(), DbgError> {
+        self.execute(Command::DeleteBreakpoint(id))
+    }
 
-        writer.write(format!("{}\n", command).as_bytes());
-        writer.flush().unwrap();
+    /// Enables or disables a breakpoint or a watchpoint
+    pub fn enable_breakpoint(&mut self, id: u32, enabled: bool) -> Result<(), DbgError> {
+        match self.request(Command::EnableBreakpoint { id, enabled })? {
+            Response::Breakpoint(_) | Response::Watchpoint(_) => Ok(()),
+            other => Err(Cli::unexpected(other))
+        }
     }
 
-    pub fn cpu(&mut self) -> CpuSnapshot {
-        let mut writer = BufWriter::new(&self.tcp_stream);
-        let mut reader = BufReader::new(&self.tcp_stream);
+    pub fn breakpoints(&mut self) -> Result<Vec<BreakpointInfo>, DbgError> {
+        match self.request(Command::ListBreakpoints)? {
+            Response::Breakpoints(breakpoints) => Ok(breakpoints),
+            other => Err(Cli::unexpected(other))
+        }
+    }
 
-        let command = to_string(&DbgCommand::Cpu).unwrap();
+    pub fn add_watchpoint(&mut self, start: u16, end: u16, access: WatchAccess) -> Result<WatchpointInfo, DbgError> {
+        match self.request(Command::AddWatchpoint { start, end, access })? {
+            Response::Watchpoint(watchpoint) => Ok(watchpoint),
+            other => Err(Cli::unexpected(other))
+        }
+    }
 
-        writer.write(format!("{}\n", command).as_bytes());
-        writer.flush().unwrap();
-        let mut buf: Vec<u8> = vec![];
-        reader.read_until("\n".as_bytes()[0], &mut buf);
+    pub fn watchpoints(&mut self) -> Result<Vec<WatchpointInfo>, DbgError> {
+        match self.request(Command::ListWatchpoints)? {
+            Response::Watchpoints(watchpoints) => Ok(watchpoints),
+            other => Err(Cli::unexpected(other))
+        }
+    }
 
-        deserialize(buf.as_ref()).unwrap()
+    /// Sends a command that is only acknowledged
+    fn execute(&mut self, command: Command) -> Result<(), DbgError> {
+        match self.request(command)? {
+            Response::Done => Ok(()),
+            other => Err(Cli::unexpected(other))
+        }
     }
 
-    pub fn mem(&mut self, addr: u16, size: usize) -> Vec<u8> {
-        let mut writer = BufWriter::new(&self.tcp_stream);
-        let mut reader = BufReader::new(&self.tcp_stream);
+    fn request(&mut self, command: Command) -> Result<Response, DbgError> {
+        self.next_id += 1;
+        let request = Request { id: self.next_id, command };
 
-        let command = to_string(&DbgCommand::Mem(addr, size)).unwrap();
+        write_frame(&mut self.tcp_stream, &request).map_err(Cli::frame_error)?;
 
-        writer.write(format!("{}\n", command).as_bytes());
-        writer.flush().unwrap();
-        let mut buf: Vec<u8> = vec![];
-        reader.read_until("\n".as_bytes()[0], &mut buf);
+        loop {
+            let reply = match self.replies.recv_timeout(REPLY_TIMEOUT) {
+                Ok(reply) => reply,
+                Err(mpsc::RecvTimeoutError::Timeout) => return Err(DbgError::Timeout),
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Err(DbgError::Io("connection to the VM closed".to_owned()))
+            };
 
-        buf
+            // Replies to requests that timed out earlier may still arrive, skip them
+            if reply.id != 0 && reply.id < request.id {
+                continue;
+            }
+
+            if reply.id != 0 && reply.id != request.id {
+                return Err(DbgError::UnexpectedReply(format!("expected reply to request {}, got {}", request.id, reply.id)));
+            }
+
+            return reply.result;
+        }
+    }
+
+    fn read_messages(mut stream: TcpStream, replies: mpsc::Sender<Reply>, events: mpsc::Sender<Event>, waker: Waker) {
+        loop {
+            match read_frame(&mut stream) {
+                Ok(ServerMessage::Reply(reply)) => {
+                    if replies.send(reply).is_err() {
+                        return;
+                    }
+                },
+                Ok(ServerMessage::Event(event)) => {
+                    if events.send(event).is_err() {
+                        return;
+                    }
+                    if let Some(ref wake) = *waker.lock().unwrap() {
+                        wake();
+                    }
+                },
+                // Frames are self-contained, so a broken one can be skipped
+                Err(FrameError::Decode(reason)) => eprintln!("Could not decode a message from the VM: {}", reason),
+                // Pending and later requests fail once the channels are gone
+                Err(_) => return
+            }
+        }
+    }
+
+    fn frame_error(e: FrameError) -> DbgError {
+        match e {
+            ref e if e.is_timeout() => DbgError::Timeout,
+            FrameError::Decode(reason) => DbgError::UnexpectedReply(reason),
+            e => DbgError::Io(e.to_string())
+        }
     }
 
-    fn read_stdin() -> String {
-        let mut input = String::new();
-        stdin().read_line(&mut input).unwrap();
-        input.trim().into()
+    fn unexpected(response: Response) -> DbgError {
+        DbgError::UnexpectedReply(format!("{:?}", response))
     }
 }
@@ -1,15 +1,55 @@
 use std::borrow::Cow;
+use std::num::ParseIntError;
 use std::str::{self, FromStr};
-use nom::{IResult, space, eol, digit};
+use nom::{IResult, space, eol, digit, hex_digit, alphanumeric, rest};
+use chip8_protocol::{ResetMode, WatchAccess};
+use chip8_protocol::expr::{Expr, FormatPart, Register};
+use expr;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum Commands {
     Cpu,
     Disasm(u16),
     Mem(u16),
     Start,
-    Step, // TODO: step size!
-    Stop
+    /// Executes the given number of instructions
+    Step(u32),
+    /// Steps over `2NNN` calls
+    Next,
+    /// Runs until the current subroutine returns
+    Finish,
+    Until(u16),
+    /// Runs until the next vblank
+    Frame,
+    /// Undoes the given number of instructions
+    ReverseStep(u32),
+    /// Runs backwards to the previous breakpoint or watchpoint hit
+    ReverseContinue,
+    History,
+    Stop,
+    Reset(ResetMode),
+    /// ROM file read by the VM
+    LoadRom(String),
+    /// ROM file read by the debugger and sent to the VM
+    UploadRom(String),
+    Reload,
+    Seed(u64),
+    Font,
+    Status,
+    Break(u16, Option<Expr>),
+    Trace(u16, Vec<FormatPart>, Option<Expr>),
+    Ignore(u32, u32),
+    Delete(u32),
+    Enable(u32),
+    Disable(u32),
+    Breakpoints,
+    Watch(u16, u16, WatchAccess),
+    Watchpoints,
+    Set(Register, u16),
+    SetStack(u8, u16),
+    Poke(u16, Vec<u8>),
+    Fill(u16, u16, u8),
+    Load(u16, String)
 }
 
 impl FromStr for Commands {
@@ -19,7 +59,8 @@ impl FromStr for Commands {
         let command: IResult<&[u8], Commands, u32> = command(s.trim().as_bytes());
 
         match command {
-            IResult::Done(_, c) => Ok(c),
+            IResult::Done(rest, c) if rest.is_empty() => Ok(c),
+            IResult::Done(rest, _) => Err(format!("Unable to parse command: unexpected `{}`", String::from_utf8_lossy(rest)).into()),
             err => Err(format!("Unable to parse command: {:?}", err).into())
         }
     }
@@ -29,7 +70,7 @@ impl FromStr for Commands {
 // TODO: upgrade to nom 4.0.0
 named!(
     command<Commands>,
-    alt!(cpu | mem | start | step | stop | disasm)
+    alt!(cpu | mem | start | step | next | finish | until | frame | reverse_step | reverse_continue | history | stop | restart | reset | reload | disasm | seed | font | status | breakpoints | add_breakpoint | delete | enable | disable | watchpoints | watch | trace | ignore | set | poke | fill | load | load_rom | upload_rom)
 );
 
 named!(
@@ -39,11 +80,68 @@ named!(
         |_| Commands::Cpu)
 );
 
+/// `step [N]`, one instruction by default
 named!(
     step<Commands>,
+    chain!(
+        tag!("step") ~
+        count: opt!(preceded!(space, id_parser)),
+        || Commands::Step(count.unwrap_or(1))
+    )
+);
+
+/// `reverse-step [N]`, one instruction by default
+named!(
+    reverse_step<Commands>,
+    chain!(
+        tag!("reverse-step") ~
+        count: opt!(preceded!(space, id_parser)),
+        || Commands::ReverseStep(count.unwrap_or(1))
+    )
+);
+
+named!(
+    reverse_continue<Commands>,
+    map!(
+        tag!("reverse-continue"),
+        |_| Commands::ReverseContinue)
+);
+
+named!(
+    history<Commands>,
+    map!(
+        tag!("history"),
+        |_| Commands::History)
+);
+
+named!(
+    next<Commands>,
+    map!(
+        tag!("next"),
+        |_| Commands::Next)
+);
+
+named!(
+    finish<Commands>,
     map!(
-        tag!("step"),
-        |_| Commands::Step)
+        tag!("finish"),
+        |_| Commands::Finish)
+);
+
+named!(
+    until<Commands>,
+    chain!(
+        tag!("until") ~
+        addr: preceded!(space, addr_parser),
+        || Commands::Until(addr)
+    )
+);
+
+named!(
+    frame<Commands>,
+    map!(
+        tag!("frame"),
+        |_| Commands::Frame)
 );
 
 named!(
@@ -53,6 +151,45 @@ named!(
         |_| Commands::Stop)
 );
 
+/// Same as `reset soft`
+named!(
+    restart<Commands>,
+    map!(
+        tag!("restart"),
+        |_| Commands::Reset(ResetMode::Soft))
+);
+
+/// `reset [soft|hard]`, soft by default
+named!(
+    reset<Commands>,
+    chain!(
+        tag!("reset") ~
+        mode: opt!(preceded!(space, reset_mode)),
+        || Commands::Reset(mode.unwrap_or(ResetMode::Soft))
+    )
+);
+
+named!(
+    reset_mode<ResetMode>,
+    alt!(
+        map!(tag!("soft"), |_| ResetMode::Soft) |
+        map!(tag!("hard"), |_| ResetMode::Hard))
+);
+
+named!(
+    reload<Commands>,
+    map!(
+        tag!("reload"),
+        |_| Commands::Reload)
+);
+
+named!(
+    font<Commands>,
+    map!(
+        tag!("font"),
+        |_| Commands::Font)
+);
+
 named!(
     start<Commands>,
     map!(
@@ -60,7 +197,6 @@ named!(
         |_| Commands::Start)
 );
 
-// TODO: allow hexadecimal digits
 named!(
     disasm<Commands>,
     chain!(
@@ -70,7 +206,6 @@ named!(
     )
 );
 
-// TODO: allow hexadecimal digits
 named!(
     mem<Commands>,
     chain!(
@@ -80,7 +215,244 @@ named!(
     )
 );
 
+named!(
+    seed<Commands>,
+    chain!(
+        tag!("seed") ~
+        value: preceded!(space, seed_parser),
+        || Commands::Seed(value)
+    )
+);
+
+named!(
+    seed_parser<u64>,
+    map_res!(
+        map_res!(digit, str::from_utf8),FromStr::from_str));
+
+named!(
+    status<Commands>,
+    map!(
+        tag!("status"),
+        |_| Commands::Status)
+);
+
+named!(
+    breakpoints<Commands>,
+    map!(
+        tag!("breakpoints"),
+        |_| Commands::Breakpoints)
+);
+
+named!(
+    add_breakpoint<Commands>,
+    chain!(
+        tag!("break") ~
+        addr: preceded!(space, addr_parser) ~
+        condition: opt!(condition),
+        || Commands::Break(addr, condition)
+    )
+);
+
+/// `trace ADDR "MESSAGE" [if CONDITION]`, the message may contain `{expr}` and `{expr:d}`
+named!(
+    trace<Commands>,
+    chain!(
+        tag!("trace") ~
+        addr: preceded!(space, addr_parser) ~
+        message: preceded!(space, message) ~
+        condition: opt!(condition),
+        || Commands::Trace(addr, message, condition)
+    )
+);
+
+named!(
+    message<Vec<FormatPart> >,
+    map_res!(
+        map_res!(preceded!(tag!("\""), take_until_and_consume!("\"")), str::from_utf8), expr::parse_format));
+
+/// ` if EXPR`, taking the rest of the command
+named!(
+    condition<Expr>,
+    map_res!(
+        map_res!(preceded!(delimited!(space, tag!("if"), space), rest), str::from_utf8), expr::parse));
+
+/// `ignore ID COUNT` lets the next COUNT hits of a breakpoint pass
+named!(
+    ignore<Commands>,
+    chain!(
+        tag!("ignore") ~
+        id: preceded!(space, id_parser) ~
+        count: preceded!(space, id_parser),
+        || Commands::Ignore(id, count)
+    )
+);
+
+named!(
+    delete<Commands>,
+    chain!(
+        tag!("delete") ~
+        id: preceded!(space, id_parser),
+        || Commands::Delete(id)
+    )
+);
+
+named!(
+    enable<Commands>,
+    chain!(
+        tag!("enable") ~
+        id: preceded!(space, id_parser),
+        || Commands::Enable(id)
+    )
+);
+
+named!(
+    disable<Commands>,
+    chain!(
+        tag!("disable") ~
+        id: preceded!(space, id_parser),
+        || Commands::Disable(id)
+    )
+);
+
+named!(
+    watchpoints<Commands>,
+    map!(
+        tag!("watchpoints"),
+        |_| Commands::Watchpoints)
+);
+
+/// `watch`, `rwatch` or `awatch` (reads and writes) followed by the first and optionally the last watched address
+named!(
+    watch<Commands>,
+    chain!(
+        access: watch_access ~
+        start: preceded!(space, addr_parser) ~
+        end: opt!(preceded!(space, addr_parser)),
+        || Commands::Watch(start, end.unwrap_or(start), access)
+    )
+);
+
+named!(
+    watch_access<WatchAccess>,
+    alt!(
+        map!(tag!("watch"), |_| WatchAccess::Write) |
+        map!(tag!("rwatch"), |_| WatchAccess::Read) |
+        map!(tag!("awatch"), |_| WatchAccess::Access))
+);
+
+/// `set REGISTER VALUE` or `set stack SLOT VALUE`
+named!(
+    set<Commands>,
+    chain!(
+        tag!("set") ~
+        command: preceded!(space, alt!(set_stack | set_register)),
+        || command
+    )
+);
+
+named!(
+    set_stack<Commands>,
+    chain!(
+        tag!("stack") ~
+        slot: preceded!(space, map_res!(map_res!(digit, str::from_utf8), FromStr::from_str)) ~
+        value: preceded!(space, addr_parser),
+        || Commands::SetStack(slot, value)
+    )
+);
+
+named!(
+    set_register<Commands>,
+    chain!(
+        register: register_parser ~
+        value: preceded!(space, addr_parser),
+        || Commands::Set(register, value)
+    )
+);
+
+named!(
+    register_parser<Register>,
+    map_opt!(
+        map_res!(alphanumeric, str::from_utf8), |name: &str| expr::parse_register(&name.to_lowercase())));
+
+/// `poke ADDR BYTE...` with the bytes in hex, e.g. `poke 0x300 de ad`
+named!(
+    poke<Commands>,
+    chain!(
+        tag!("poke") ~
+        addr: preceded!(space, addr_parser) ~
+        bytes: many1!(preceded!(space, byte_parser)),
+        || Commands::Poke(addr, bytes)
+    )
+);
+
+/// `fill START END BYTE` sets the whole range to the hex byte
+named!(
+    fill<Commands>,
+    chain!(
+        tag!("fill") ~
+        start: preceded!(space, addr_parser) ~
+        end: preceded!(space, addr_parser) ~
+        value: preceded!(space, byte_parser),
+        || Commands::Fill(start, end, value)
+    )
+);
+
+/// `load ADDR FILE` writes the contents of the file to memory
+named!(
+    load<Commands>,
+    chain!(
+        tag!("load") ~
+        addr: preceded!(space, addr_parser) ~
+        path: map_res!(preceded!(space, rest), str::from_utf8),
+        || Commands::Load(addr, path.to_owned())
+    )
+);
+
+/// `load-rom FILE` replaces the ROM with a file on the VM's side
+named!(
+    load_rom<Commands>,
+    chain!(
+        tag!("load-rom") ~
+        path: map_res!(preceded!(space, rest), str::from_utf8),
+        || Commands::LoadRom(path.to_owned())
+    )
+);
+
+/// `upload-rom FILE` replaces the ROM with a file on the debugger's side
+named!(
+    upload_rom<Commands>,
+    chain!(
+        tag!("upload-rom") ~
+        path: map_res!(preceded!(space, rest), str::from_utf8),
+        || Commands::UploadRom(path.to_owned())
+    )
+);
+
+named!(
+    byte_parser<u8>,
+    map_res!(
+        map_res!(hex_digit, str::from_utf8), |s| u8::from_str_radix(s, 16)));
+
+named!(
+    id_parser<u32>,
+    map_res!(
+        map_res!(digit, str::from_utf8),FromStr::from_str));
+
+/// Addresses are either `0x` prefixed hexadecimal or decimal numbers
 named!(
     addr_parser<u16>,
+    alt!(hex_addr_parser | dec_addr_parser));
+
+named!(
+    hex_addr_parser<u16>,
+    map_res!(
+        map_res!(preceded!(tag!("0x"), hex_digit), str::from_utf8), parse_hex));
+
+named!(
+    dec_addr_parser<u16>,
     map_res!(
         map_res!(digit, str::from_utf8),FromStr::from_str));
+
+fn parse_hex(s: &str) -> Result<u16, ParseIntError> {
+    u16::from_str_radix(s, 16)
+}
@@ -0,0 +1,813 @@
+//! Debug Adapter Protocol server, so that DAP-capable editors can debug CHIP-8 programs.
+//!
+//! The adapter speaks DAP over stdio or TCP and controls the VM through the regular debugger protocol, either
+//! launching the VM itself or attaching to a running one. ROMs have no sources, so breakpoints and stack frames refer
+//! to a disassembly of the ROM, where line N holds the instruction at `rom_location + 2 * (N - 1)`.
+
+use std::env;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+
+use serde_json::Value;
+
+use chip8_protocol::{CpuSnapshot, DbgError, Event, MemoryMapSnapshot, StepMode, StopReason};
+use chip8_protocol::disasm::Disasm;
+use chip8_protocol::expr::{Expr, ExprContext, Register};
+
+use cli::Cli;
+use expr;
+
+const THREAD_ID: u64 = 1;
+/// `sourceReference` of the ROM disassembly
+const ROM_SOURCE: u64 = 1;
+/// Line printed by the VM once its debugger server is up
+const LISTENING_PREFIX: &str = "Debugger listening on ";
+const DEFAULT_ADDRESS: &str = "127.0.0.1:9876";
+
+/// `variablesReference`s of the scopes
+const REGISTERS: u64 = 1;
+const TIMERS: u64 = 2;
+const KEYPAD: u64 = 3;
+
+/// Everything the session waits for
+enum Input {
+    Message(Value),
+    /// The VM pushed events, to be fetched with `Cli::events`
+    VmEvents,
+    /// Line printed by a launched VM, e.g. a tracepoint message
+    VmOutput(String),
+    Closed
+}
+
+/// Serves a single session over stdin and stdout
+pub fn run_stdio() {
+    run(io::stdin(), io::stdout());
+}
+
+/// Serves sessions over TCP, one at a time
+pub fn serve(port: u16) -> Result<(), String> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| format!("Could not start the DAP server: {}", e))?;
+    let address = listener.local_addr().map_err(|e| format!("Could not start the DAP server: {}", e))?;
+    println!("DAP server listening on {}", address);
+
+    for stream in listener.incoming() {
+        let stream = stream.map_err(|e| format!("DAP connection failed: {}", e))?;
+        let reader = stream.try_clone().map_err(|e| format!("DAP connection failed: {}", e))?;
+
+        run(reader, stream.try_clone().map_err(|e| format!("DAP connection failed: {}", e))?);
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+
+    Ok(())
+}
+
+fn run<R: Read + Send + 'static, W: Write>(reader: R, writer: W) {
+    let (sender, input) = mpsc::channel();
+    let messages = sender.clone();
+    thread::spawn(move || read_messages(reader, messages));
+
+    let mut session = Session {
+        writer,
+        seq: 0,
+        sender,
+        cli: None,
+        vm: None,
+        source_name: "ROM".to_owned(),
+        memory_map: None,
+        stop_on_entry: false,
+        source_breakpoints: vec![],
+        instruction_breakpoints: vec![],
+        pending_events: vec![],
+        closing: false
+    };
+
+    while !session.closing {
+        match input.recv() {
+            Ok(Input::Message(message)) => session.handle(&message),
+            Ok(Input::VmEvents) => session.forward_vm_events(),
+            Ok(Input::VmOutput(line)) => session.send_event("output", json!({ "category": "stdout", "output": line + "\n" })),
+            Ok(Input::Closed) | Err(_) => break
+        }
+    }
+
+    session.terminate_vm();
+}
+
+fn read_messages<R: Read>(reader: R, input: mpsc::Sender<Input>) {
+    let mut reader = BufReader::new(reader);
+
+    loop {
+        match read_message(&mut reader) {
+            Ok(Some(message)) => if input.send(Input::Message(message)).is_err() {
+                return;
+            },
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("Could not read a DAP message: {}", e);
+                break;
+            }
+        }
+    }
+
+    let _ = input.send(Input::Closed);
+}
+
+/// Reads a `Content-Length` framed message, `None` at the end of the stream
+fn read_message<R: BufRead>(reader: &mut R) -> Result<Option<Value>, String> {
+    let mut length = None;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+
+        let mut header = line.splitn(2, ':');
+        if header.next().map_or(false, |name| name.trim().eq_ignore_ascii_case("Content-Length")) {
+            let value = header.next().unwrap_or_default().trim();
+            length = Some(value.parse::<usize>().map_err(|_| format!("Invalid Content-Length `{}`", value))?);
+        }
+    }
+
+    let mut body = vec![0; length.ok_or("Message without Content-Length")?];
+    reader.read_exact(&mut body).map_err(|e| e.to_string())?;
+
+    serde_json::from_slice(&body).map(Some).map_err(|e| format!("Invalid message: {}", e))
+}
+
+struct Session<W: Write> {
+    writer: W,
+    seq: u64,
+    /// Handed to the VM connection and the launched VM's output reader
+    sender: mpsc::Sender<Input>,
+    cli: Option<Cli>,
+    /// VM started by `launch`
+    vm: Option<Child>,
+    source_name: String,
+    memory_map: Option<MemoryMapSnapshot>,
+    stop_on_entry: bool,
+    /// Breakpoint ids of the last `setBreakpoints` and `setInstructionBreakpoints`, which replace them as a whole
+    source_breakpoints: Vec<u32>,
+    instruction_breakpoints: Vec<u32>,
+    /// Events that have to follow the response being sent
+    pending_events: Vec<(&'static str, Value)>,
+    closing: bool
+}
+
+impl<W: Write> Session<W> {
+    fn handle(&mut self, message: &Value) {
+        let command = message["command"].as_str().unwrap_or_default().to_owned();
+        let null = Value::Null;
+        let arguments = message.get("arguments").unwrap_or(&null);
+
+        let mut response = json!({
+            "type": "response",
+            "request_seq": message["seq"],
+            "command": command
+        });
+
+        match self.execute(&command, arguments) {
+            Ok(body) => {
+                response["success"] = json!(true);
+                response["body"] = body;
+            },
+            Err(e) => {
+                response["success"] = json!(false);
+                response["message"] = json!(e);
+            }
+        }
+
+        self.send(response);
+
+        for (event, body) in self.pending_events.split_off(0) {
+            self.send_event(event, body);
+        }
+    }
+
+    fn execute(&mut self, command: &str, arguments: &Value) -> Result<Value, String> {
+        match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsHitConditionalBreakpoints": true,
+                "supportsLogPoints": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsSetVariable": true,
+                "supportsReadMemoryRequest": true,
+                "supportsDisassembleRequest": true,
+                "supportsStepBack": true
+            })),
+            "launch" => self.launch(arguments),
+            "attach" => {
+                let address = arguments["address"].as_str().unwrap_or(DEFAULT_ADDRESS);
+                self.connect(address, arguments["token"].as_str())
+            },
+            "configurationDone" => {
+                if self.vm.is_some() && !self.stop_on_entry {
+                    vm(self.cli()?.start())?;
+                } else if !vm(self.cli()?.status())?.running {
+                    let reason = if self.vm.is_some() { "entry" } else { "pause" };
+                    self.pending_events.push(("stopped", json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true })));
+                }
+                Ok(json!({}))
+            },
+            "setBreakpoints" => self.set_source_breakpoints(arguments),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(arguments),
+            "setExceptionBreakpoints" => Ok(json!({})),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            "stackTrace" => self.stack_trace(arguments),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "presentationHint": "registers", "variablesReference": REGISTERS, "expensive": false },
+                { "name": "Timers", "variablesReference": TIMERS, "expensive": false },
+                { "name": "Keypad", "variablesReference": KEYPAD, "expensive": false }
+            ]})),
+            "variables" => self.variables(arguments["variablesReference"].as_u64().unwrap_or_default()),
+            "setVariable" => self.set_variable(arguments),
+            "source" => self.source(),
+            "readMemory" => self.read_memory(arguments),
+            "disassemble" => self.disassemble(arguments),
+            "evaluate" => self.evaluate(arguments),
+            "continue" => {
+                vm(self.cli()?.start())?;
+                Ok(json!({ "allThreadsContinued": true }))
+            },
+            "next" => self.step(StepMode::Over),
+            "stepIn" => self.step(StepMode::Instructions(1)),
+            "stepOut" => self.step(StepMode::Out),
+            "stepBack" => {
+                vm(self.cli()?.reverse_step(1))?;
+                Ok(json!({}))
+            },
+            "reverseContinue" => {
+                vm(self.cli()?.reverse_continue())?;
+                Ok(json!({}))
+            },
+            "pause" => {
+                vm(self.cli()?.stop())?;
+                Ok(json!({}))
+            },
+            "disconnect" => {
+                let terminate = arguments["terminateDebuggee"].as_bool().unwrap_or(self.vm.is_some());
+
+                if terminate {
+                    self.terminate_vm();
+                } else if let Some(ref mut cli) = self.cli {
+                    // A VM left behind keeps running, unless asked otherwise
+                    if !arguments["suspendDebuggee"].as_bool().unwrap_or(false) {
+                        vm(cli.start())?;
+                    }
+                }
+
+                self.closing = true;
+                Ok(json!({}))
+            },
+            _ => Err(format!("Unsupported request `{}`", command))
+        }
+    }
+
+    /// Starts the VM paused on a free port and attaches to it, breakpoints get set before it runs
+    fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+        let program = arguments["program"].as_str().ok_or("`program` has to name the ROM to run")?;
+        let vm_path = match arguments["vm"].as_str() {
+            Some(path) => PathBuf::from(path),
+            None => default_vm_path()?
+        };
+        let token = arguments["token"].as_str();
+
+        let mut command = Command::new(&vm_path);
+        command.arg(program)
+            .args(arguments["args"].as_array().into_iter().flat_map(|args| args.iter().filter_map(|arg| arg.as_str())))
+            .args(&["--paused", "--dbg-port", "0"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped());
+        if let Some(token) = token {
+            command.args(&["--dbg-token", token]);
+        }
+
+        let mut child = command.spawn().map_err(|e| format!("Could not start the VM {:?}: {}", vm_path, e))?;
+        let mut output = BufReader::new(child.stdout.take().ok_or("VM output is not available")?);
+
+        let address = loop {
+            let mut line = String::new();
+            if output.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+                let _ = child.wait();
+                return Err("The VM exited before its debugger server started".to_owned());
+            }
+
+            if line.starts_with(LISTENING_PREFIX) {
+                break line[LISTENING_PREFIX.len()..].trim().to_owned();
+            }
+            self.send_event("output", json!({ "category": "stdout", "output": line }));
+        };
+
+        // Keeps forwarding what the VM prints, e.g. tracepoint messages
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            for line in output.lines() {
+                match line {
+                    Ok(line) => if sender.send(Input::VmOutput(line)).is_err() {
+                        return;
+                    },
+                    Err(_) => return
+                }
+            }
+        });
+
+        self.vm = Some(child);
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        if let Some(name) = Path::new(program).file_name() {
+            self.source_name = name.to_string_lossy().into_owned();
+        }
+
+        self.connect(&address, token)
+    }
+
+    fn connect(&mut self, address: &str, token: Option<&str>) -> Result<Value, String> {
+        let mut cli = Cli::new(address, token.map(String::from))
+            .map_err(|e| format!("Could not attach to the VM at {}: {}", address, e))?;
+
+        let sender = self.sender.clone();
+        cli.on_event(move || {
+            let _ = sender.send(Input::VmEvents);
+        });
+
+        self.memory_map = Some(vm(cli.memory_map())?);
+        self.cli = Some(cli);
+
+        self.pending_events.push(("initialized", json!({})));
+        Ok(json!({}))
+    }
+
+    fn terminate_vm(&mut self) {
+        if let Some(mut child) = self.vm.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+
+    fn forward_vm_events(&mut self) {
+        let events = match self.cli {
+            Some(ref mut cli) => cli.events(),
+            None => return
+        };
+
+        for event in events {
+            match event {
+                Event::Stopped { reason, .. } => {
+                    let mut body = json!({ "threadId": THREAD_ID, "allThreadsStopped": true });
+
+                    body["reason"] = json!(match reason {
+                        StopReason::Requested => "pause",
+                        StopReason::Step => "step",
+                        StopReason::Breakpoint { id, .. } => {
+                            body["hitBreakpointIds"] = json!([id]);
+                            "breakpoint"
+                        },
+                        StopReason::Watchpoint(ref hit) => {
+                            body["description"] = json!(format!("{:?} of {:#x} by {:#x}", hit.access, hit.addr, hit.pc));
+                            "data breakpoint"
+                        },
+                        StopReason::Fault(ref fault) => {
+                            body["description"] = json!("CPU fault");
+                            body["text"] = json!(fault);
+                            "exception"
+                        },
+                        StopReason::HistoryStart => {
+                            body["description"] = json!("Start of the execution history");
+                            "step"
+                        }
+                    });
+
+                    self.send_event("stopped", body);
+                },
+                Event::Exited => {
+                    self.send_event("exited", json!({ "exitCode": 0 }));
+                    self.send_event("terminated", json!({}));
+                }
+            }
+        }
+    }
+
+    fn set_source_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        // The ROM disassembly is the only source, breakpoints elsewhere are left unverified
+        let in_rom = arguments["source"]["sourceReference"].as_u64() == Some(ROM_SOURCE);
+        if in_rom {
+            let old = self.source_breakpoints.split_off(0);
+            self.delete_breakpoints(old)?;
+        }
+
+        let memory_map = self.memory_map()?;
+        let mut breakpoints = vec![];
+
+        for breakpoint in arguments["breakpoints"].as_array().into_iter().flat_map(|breakpoints| breakpoints.iter()) {
+            let line = breakpoint["line"].as_u64().unwrap_or_default();
+            let addr = line_address(&memory_map, line);
+
+            let result = match addr {
+                _ if !in_rom => Err("Breakpoints can only be set in the ROM disassembly".to_owned()),
+                None => Err("Line is outside of the ROM".to_owned()),
+                Some(addr) => self.add_breakpoint(addr, breakpoint)
+            };
+
+            breakpoints.push(match result {
+                Ok(id) => {
+                    self.source_breakpoints.push(id);
+                    json!({ "id": id, "verified": true, "line": line })
+                },
+                Err(e) => json!({ "verified": false, "line": line, "message": e })
+            });
+        }
+
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn set_instruction_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let old = self.instruction_breakpoints.split_off(0);
+        self.delete_breakpoints(old)?;
+
+        let mut breakpoints = vec![];
+
+        for breakpoint in arguments["breakpoints"].as_array().into_iter().flat_map(|breakpoints| breakpoints.iter()) {
+            let addr = breakpoint["instructionReference"].as_str().and_then(parse_reference)
+                .map(|addr| addr + breakpoint["offset"].as_i64().unwrap_or_default());
+
+            let result = match addr {
+                Some(addr) if addr >= 0 && addr <= 0xFFFF => self.add_breakpoint(addr as u16, breakpoint),
+                _ => Err("Invalid instruction reference".to_owned())
+            };
+
+            breakpoints.push(match result {
+                Ok(id) => {
+                    self.instruction_breakpoints.push(id);
+                    json!({ "id": id, "verified": true, "instructionReference": format!("{:#x}", addr.unwrap_or_default()) })
+                },
+                Err(e) => json!({ "verified": false, "message": e })
+            });
+        }
+
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    /// Adds a breakpoint, or a tracepoint for log points, with the condition and hit count of a DAP breakpoint
+    fn add_breakpoint(&mut self, addr: u16, breakpoint: &Value) -> Result<u32, String> {
+        let condition = match breakpoint["condition"].as_str() {
+            Some(condition) if !condition.trim().is_empty() => Some(expr::parse(condition)?),
+            _ => None
+        };
+        let hits = match breakpoint["hitCondition"].as_str() {
+            Some(hits) => Some(hits.trim().parse::<u32>().map_err(|_| format!("Hit count `{}` is not a number", hits))?),
+            None => None
+        };
+
+        let cli = self.cli()?;
+        let info = match breakpoint["logMessage"].as_str() {
+            Some(message) => vm(cli.add_tracepoint(addr, expr::parse_format(message)?, condition))?,
+            None => vm(cli.add_breakpoint(addr, condition))?
+        };
+
+        // Stops on the given hit, so the ones before it pass
+        if let Some(hits) = hits {
+            if hits > 1 {
+                vm(cli.ignore_breakpoint(info.id, hits - 1))?;
+            }
+        }
+
+        Ok(info.id)
+    }
+
+    fn delete_breakpoints(&mut self, ids: Vec<u32>) -> Result<(), String> {
+        let cli = self.cli()?;
+
+        for id in ids {
+            match cli.delete_breakpoint(id) {
+                // Deleted by another debugger
+                Ok(()) | Err(DbgError::NoSuchBreakpoint(_)) => {},
+                Err(e) => return Err(e.to_string())
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Frames from the innermost one, named after the subroutine the call stack says they are in
+    fn stack_trace(&mut self, arguments: &Value) -> Result<Value, String> {
+        let memory_map = self.memory_map()?;
+        let source = self.rom_source();
+        let cli = self.cli()?;
+        let cpu = vm(cli.cpu())?;
+
+        // Calls push the address of the `2NNN` instruction
+        let calls = &cpu.stack[..cpu.sp as usize];
+        let mut subroutines = vec![];
+        for &call in calls {
+            let opcode = vm(cli.mem(call, 2))?;
+            subroutines.push(((opcode[0] as u16) << 8 | opcode[1] as u16) & 0x0FFF);
+        }
+
+        let frames: Vec<Value> = (0..=calls.len()).map(|depth| {
+            let pc = if depth == 0 { cpu.pc } else { calls[calls.len() - depth] };
+            let name = match calls.len().checked_sub(depth + 1) {
+                Some(call) => format!("sub_{:03x}", subroutines[call]),
+                None => "main".to_owned()
+            };
+
+            let mut frame = json!({
+                "id": depth,
+                "name": name,
+                "line": 0,
+                "column": 0,
+                "instructionPointerReference": format!("{:#x}", pc)
+            });
+            if let Some(line) = address_line(&memory_map, pc) {
+                frame["line"] = json!(line);
+                frame["column"] = json!(1);
+                frame["source"] = source.clone();
+            }
+            frame
+        }).collect();
+
+        let total = frames.len();
+        let start = arguments["startFrame"].as_u64().unwrap_or_default() as usize;
+        let levels = match arguments["levels"].as_u64() {
+            Some(levels) if levels > 0 => levels as usize,
+            _ => total
+        };
+
+        Ok(json!({
+            "stackFrames": frames.into_iter().skip(start).take(levels).collect::<Vec<_>>(),
+            "totalFrames": total
+        }))
+    }
+
+    fn variables(&mut self, reference: u64) -> Result<Value, String> {
+        let cli = self.cli()?;
+
+        let variables = match reference {
+            REGISTERS => {
+                let cpu = vm(cli.cpu())?;
+                let mut variables: Vec<Value> = cpu.v.iter().enumerate()
+                    .map(|(x, value)| variable(&format!("v{:x}", x), format!("{:#04x}", value)))
+                    .collect();
+
+                let mut i = variable("i", format!("{:#05x}", cpu.i));
+                i["memoryReference"] = json!(format!("{:#x}", cpu.i));
+                let mut pc = variable("pc", format!("{:#05x}", cpu.pc));
+                pc["memoryReference"] = json!(format!("{:#x}", cpu.pc));
+
+                variables.push(i);
+                variables.push(pc);
+                variables.push(variable("sp", format!("{}", cpu.sp)));
+                variables
+            },
+            TIMERS => {
+                let cpu = vm(cli.cpu())?;
+                vec![variable("dt", format!("{}", cpu.dt)), variable("st", format!("{}", cpu.st))]
+            },
+            KEYPAD => vm(cli.keypad())?.iter().enumerate()
+                .map(|(key, &pressed)| variable(&format!("K{:X}", key), (if pressed { "down" } else { "up" }).to_owned()))
+                .collect(),
+            _ => return Err(format!("Unknown variables reference {}", reference))
+        };
+
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn set_variable(&mut self, arguments: &Value) -> Result<Value, String> {
+        let reference = arguments["variablesReference"].as_u64().unwrap_or_default();
+        let name = arguments["name"].as_str().unwrap_or_default();
+
+        let register = match expr::parse_register(&name.to_lowercase()) {
+            Some(register) if reference == REGISTERS || reference == TIMERS => register,
+            _ => return Err(format!("`{}` cannot be changed", name))
+        };
+        let value = expr::parse_number(arguments["value"].as_str().unwrap_or_default().trim())?;
+
+        vm(self.cli()?.set_register(register, value))?;
+        Ok(json!({ "value": format!("{:#x}", value) }))
+    }
+
+    fn source(&mut self) -> Result<Value, String> {
+        let memory_map = self.memory_map()?;
+        let rom = vm(self.cli()?.mem(memory_map.rom_location, memory_map.rom_size))?;
+
+        let lines: Vec<String> = disassemble(&rom).iter().enumerate()
+            .map(|(i, &(ref repr, opcode))| format!("0x{:03x} {:04X}  {}", memory_map.rom_location as usize + i * 2, opcode, repr))
+            .collect();
+
+        Ok(json!({ "content": lines.join("\n") }))
+    }
+
+    fn read_memory(&mut self, arguments: &Value) -> Result<Value, String> {
+        let memory_map = self.memory_map()?;
+        let start = arguments["memoryReference"].as_str().and_then(parse_reference).ok_or("Invalid memory reference")?
+            + arguments["offset"].as_i64().unwrap_or_default();
+        let count = arguments["count"].as_u64().unwrap_or_default() as usize;
+
+        if start < 0 || start as usize >= memory_map.ram_size {
+            return Ok(json!({ "address": format!("{:#x}", start.max(0)), "unreadableBytes": count }));
+        }
+
+        let readable = count.min(memory_map.ram_size - start as usize);
+        let bytes = vm(self.cli()?.mem(start as u16, readable))?;
+
+        Ok(json!({
+            "address": format!("{:#x}", start),
+            "data": base64(&bytes),
+            "unreadableBytes": count - readable
+        }))
+    }
+
+    /// Exactly the requested number of instructions, the ones outside of memory are marked invalid
+    fn disassemble(&mut self, arguments: &Value) -> Result<Value, String> {
+        let memory_map = self.memory_map()?;
+        let source = self.rom_source();
+        let start = arguments["memoryReference"].as_str().and_then(parse_reference).ok_or("Invalid memory reference")?
+            + arguments["offset"].as_i64().unwrap_or_default()
+            + arguments["instructionOffset"].as_i64().unwrap_or_default() * 2;
+        let count = arguments["instructionCount"].as_u64().unwrap_or_default() as i64;
+
+        let ram_size = memory_map.ram_size as i64;
+        let first = start.max(0).min(ram_size);
+        let last = (start + count * 2).max(0).min(ram_size);
+        let bytes = vm(self.cli()?.mem(first as u16, (last - first) as usize))?;
+        let opcodes = disassemble(&bytes);
+
+        let instructions: Vec<Value> = (0..count).map(|i| {
+            let addr = start + i * 2;
+            let index = ((addr - first) / 2) as usize;
+
+            if addr < first || addr + 1 >= last || index >= opcodes.len() {
+                return json!({ "address": format!("{:#x}", addr.max(0)), "instruction": "??", "presentationHint": "invalid" });
+            }
+
+            let (ref repr, opcode) = opcodes[index];
+            let mut instruction = json!({
+                "address": format!("{:#x}", addr),
+                "instructionBytes": format!("{:02x} {:02x}", opcode >> 8, opcode & 0xFF),
+                "instruction": repr
+            });
+            if let Some(line) = address_line(&memory_map, addr as u16) {
+                instruction["location"] = source.clone();
+                instruction["line"] = json!(line);
+            }
+            instruction
+        }).collect();
+
+        Ok(json!({ "instructions": instructions }))
+    }
+
+    /// Evaluates a condition expression against the current state, e.g. for watches and hovers
+    fn evaluate(&mut self, arguments: &Value) -> Result<Value, String> {
+        let expression = expr::parse(arguments["expression"].as_str().unwrap_or_default())?;
+        let memory_map = self.memory_map()?;
+        let cli = self.cli()?;
+
+        let state = VmState { cpu: vm(cli.cpu())?, memory: vm(cli.mem(0, memory_map.ram_size))? };
+        let value = expression.eval(&state);
+
+        let mut result = json!({ "result": format!("{:#x} ({})", value, value), "variablesReference": 0 });
+        if let Expr::Reg(Register::I) = expression {
+            result["memoryReference"] = json!(format!("{:#x}", value));
+        }
+        Ok(result)
+    }
+
+    fn step(&mut self, mode: StepMode) -> Result<Value, String> {
+        vm(self.cli()?.step(mode))?;
+        Ok(json!({}))
+    }
+
+    fn rom_source(&self) -> Value {
+        json!({ "name": self.source_name, "sourceReference": ROM_SOURCE })
+    }
+
+    fn cli(&mut self) -> Result<&mut Cli, String> {
+        self.cli.as_mut().ok_or_else(|| "Not attached to a VM".to_owned())
+    }
+
+    fn memory_map(&self) -> Result<MemoryMapSnapshot, String> {
+        self.memory_map.clone().ok_or_else(|| "Not attached to a VM".to_owned())
+    }
+
+    fn send_event(&mut self, event: &str, body: Value) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+
+    fn send(&mut self, mut message: Value) {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+
+        let body = message.to_string();
+        let result = write!(self.writer, "Content-Length: {}\r\n\r\n{}", body.len(), body).and_then(|_| self.writer.flush());
+        if let Err(e) = result {
+            eprintln!("Could not send a DAP message: {}", e);
+            self.closing = true;
+        }
+    }
+}
+
+/// CPU and memory of the VM, for evaluating expressions on the adapter's side
+struct VmState {
+    cpu: CpuSnapshot,
+    memory: Vec<u8>
+}
+
+impl ExprContext for VmState {
+    fn register(&self, register: Register) -> u16 {
+        match register {
+            Register::V(x) => self.cpu.v[x as usize & 0xF] as u16,
+            Register::I => self.cpu.i,
+            Register::Pc => self.cpu.pc,
+            Register::Sp => self.cpu.sp as u16,
+            Register::Dt => self.cpu.dt as u16,
+            Register::St => self.cpu.st as u16
+        }
+    }
+
+    fn memory(&self, addr: u16) -> u8 {
+        self.memory.get(addr as usize).cloned().unwrap_or_default()
+    }
+}
+
+fn variable(name: &str, value: String) -> Value {
+    json!({ "name": name, "value": value, "variablesReference": 0 })
+}
+
+/// Mnemonics and opcodes of the 2 byte words
+fn disassemble(bytes: &[u8]) -> Vec<(String, u16)> {
+    if bytes.len() < 2 {
+        return vec![];
+    }
+
+    Disasm::disasm(&bytes.to_vec()).iter().map(|&(ref op, opcode)| (op.repr(), opcode)).collect()
+}
+
+/// Line of the ROM disassembly holding the address
+fn address_line(memory_map: &MemoryMapSnapshot, addr: u16) -> Option<u64> {
+    let offset = (addr as usize).checked_sub(memory_map.rom_location as usize)?;
+
+    if offset % 2 == 0 && offset < memory_map.rom_size {
+        Some(offset as u64 / 2 + 1)
+    } else {
+        None
+    }
+}
+
+fn line_address(memory_map: &MemoryMapSnapshot, line: u64) -> Option<u16> {
+    let offset = line.checked_sub(1)? as usize * 2;
+
+    if offset < memory_map.rom_size {
+        Some((memory_map.rom_location as usize + offset) as u16)
+    } else {
+        None
+    }
+}
+
+/// Memory and instruction references are `0x` prefixed hex addresses
+fn parse_reference(reference: &str) -> Option<i64> {
+    let reference = reference.trim();
+
+    if reference.starts_with("0x") {
+        i64::from_str_radix(&reference[2..], 16).ok()
+    } else {
+        reference.parse().ok()
+    }
+}
+
+/// The VM running next to the debugger, the one `cargo build` puts into the same directory
+fn default_vm_path() -> Result<PathBuf, String> {
+    let debugger = env::current_exe().map_err(|e| format!("Could not locate the VM: {}", e))?;
+    Ok(debugger.with_file_name(format!("chip8{}", env::consts::EXE_SUFFIX)))
+}
+
+fn vm<T>(result: Result<T, DbgError>) -> Result<T, String> {
+    result.map_err(|e| e.to_string())
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| group | (byte as u32) << (16 - i * 8));
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - i * 6)) as usize & 0x3F] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
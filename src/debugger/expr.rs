@@ -0,0 +1,359 @@
+//! Parser for breakpoint conditions and tracepoint messages, which the VM evaluates.
+//!
+//! Conditions use Rust-like operators and precedence over the registers `v0`-`vf`, `i`, `pc`, `sp`, `dt`, `st`,
+//! memory bytes `mem[addr]` and numbers (`0x` prefixed hexadecimal or decimal), e.g. `v3 == 0x10 && i > 0x400`.
+
+use std::fmt;
+use chip8_protocol::expr::{BinaryOp, Expr, FormatPart, Register, UnaryOp};
+
+/// Operators are tried in order, so longer ones have to come first
+const SYMBOLS: &[&str] = &["<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+                           "<", ">", "!", "~", "-", "+", "*", "&", "^", "|", "(", ")", "[", "]"];
+
+/// Binary operators from the lowest to the highest precedence
+const PRECEDENCE: &[&[(&str, BinaryOp)]] = &[
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne), ("<", BinaryOp::Lt),
+      ("<=", BinaryOp::Le), (">", BinaryOp::Gt), (">=", BinaryOp::Ge)],
+    &[("|", BinaryOp::BitOr)],
+    &[("^", BinaryOp::BitXor)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[("*", BinaryOp::Mul)]
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(u16),
+    Name(String),
+    Symbol(&'static str)
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Number(value) => write!(f, "{:#x}", value),
+            Token::Name(ref name) => write!(f, "{}", name),
+            Token::Symbol(symbol) => write!(f, "{}", symbol)
+        }
+    }
+}
+
+pub fn parse(s: &str) -> Result<Expr, String> {
+    let mut parser = Parser { tokens: tokenize(s)?, pos: 0 };
+    let expr = parser.binary(0)?;
+
+    match parser.next() {
+        Some(token) => Err(format!("Unexpected `{}` in `{}`", token, s)),
+        None => Ok(expr)
+    }
+}
+
+/// Parses a tracepoint message, where `{expr}` prints a value in hex, `{expr:d}` in decimal and `{{`, `}}` are
+/// literal braces
+pub fn parse_format(s: &str) -> Result<Vec<FormatPart>, String> {
+    let mut parts = vec![];
+    let mut text = String::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            },
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            },
+            '{' => {
+                let mut inner = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => inner.push(c),
+                        None => return Err(format!("Unmatched `{{` in `{}`", s))
+                    }
+                }
+
+                let inner = inner.trim();
+                let (inner, decimal) = if inner.ends_with(":d") {
+                    (&inner[..inner.len() - 2], true)
+                } else {
+                    (inner, false)
+                };
+
+                if !text.is_empty() {
+                    parts.push(FormatPart::Text(text.split_off(0)));
+                }
+                parts.push(FormatPart::Value { expr: parse(inner)?, decimal });
+            },
+            '}' => return Err(format!("Unmatched `}}` in `{}`", s)),
+            c => text.push(c)
+        }
+    }
+
+    if !text.is_empty() {
+        parts.push(FormatPart::Text(text));
+    }
+
+    Ok(parts)
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = s.trim_start();
+
+    while !rest.is_empty() {
+        let word_len = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+        let (word, tail) = rest.split_at(word_len);
+
+        if word.starts_with(|c: char| c.is_ascii_digit()) {
+            tokens.push(Token::Number(parse_number(word)?));
+            rest = tail;
+        } else if !word.is_empty() {
+            tokens.push(Token::Name(word.to_lowercase()));
+            rest = tail;
+        } else {
+            let symbol = SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol))
+                .ok_or_else(|| format!("Unexpected character `{}` in `{}`", rest.chars().next().unwrap(), s))?;
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        }
+
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+/// Parses a `0x` prefixed hexadecimal or a decimal number
+pub fn parse_number(word: &str) -> Result<u16, String> {
+    let result = if word.starts_with("0x") {
+        u16::from_str_radix(&word[2..], 16)
+    } else {
+        word.parse()
+    };
+
+    result.map_err(|_| format!("Invalid number `{}`", word))
+}
+
+/// Parses a lowercase register name
+pub fn parse_register(name: &str) -> Option<Register> {
+    match name {
+        "i" => Some(Register::I),
+        "pc" => Some(Register::Pc),
+        "sp" => Some(Register::Sp),
+        "dt" => Some(Register::Dt),
+        "st" => Some(Register::St),
+        _ if name.len() == 2 && name.starts_with('v') => u8::from_str_radix(&name[1..], 16).ok().map(Register::V),
+        _ => None
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek_symbol(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(&Token::Symbol(symbol)) => Some(symbol),
+            _ => None
+        }
+    }
+
+    fn expect(&mut self, expected: &'static str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Symbol(symbol)) if symbol == expected => Ok(()),
+            Some(token) => Err(format!("Expected `{}`, found `{}`", expected, token)),
+            None => Err(format!("Expected `{}` at the end", expected))
+        }
+    }
+
+    /// Parses operators of the given precedence level and above, left associative
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+
+        let mut left = self.binary(level + 1)?;
+
+        while let Some(&(_, op)) = self.peek_symbol().and_then(|s| PRECEDENCE[level].iter().find(|&&(symbol, _)| symbol == s)) {
+            self.pos += 1;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let op = match self.peek_symbol() {
+            Some("!") => UnaryOp::Not,
+            Some("-") => UnaryOp::Neg,
+            Some("~") => UnaryOp::BitNot,
+            _ => return self.primary()
+        };
+
+        self.pos += 1;
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Expr::Const(value)),
+            Some(Token::Symbol("(")) => {
+                let expr = self.binary(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            },
+            Some(Token::Name(ref name)) if name == "mem" => {
+                self.expect("[")?;
+                let addr = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Expr::Mem(Box::new(addr)))
+            },
+            Some(Token::Name(name)) => parse_register(&name).map(Expr::Reg).ok_or_else(|| format!("Unknown register `{}`", name)),
+            Some(token) => Err(format!("Unexpected `{}`", token)),
+            None => Err("Unexpected end of expression".to_owned())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chip8_protocol::expr::{BinaryOp, Expr, ExprContext, FormatPart, Register, UnaryOp};
+
+    use super::{parse, parse_format, parse_number, parse_register};
+
+    /// Registers hold 0x10 times their index plus one, memory bytes the low byte of their address
+    struct Context;
+
+    impl ExprContext for Context {
+        fn register(&self, register: Register) -> u16 {
+            match register {
+                Register::V(x) => (x as u16 + 1) * 0x10,
+                Register::I => 0x400,
+                Register::Pc => 0x200,
+                Register::Sp => 1,
+                Register::Dt => 0,
+                Register::St => 0
+            }
+        }
+
+        fn memory(&self, addr: u16) -> u8 {
+            addr as u8
+        }
+    }
+
+    fn eval(s: &str) -> u16 {
+        parse(s).unwrap().eval(&Context)
+    }
+
+    fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+        Expr::Binary(op, Box::new(left), Box::new(right))
+    }
+
+    #[test]
+    fn follows_rust_precedence() {
+        assert_eq!(parse("1 + 2 * 3").unwrap(),
+                   binary(BinaryOp::Add, Expr::Const(1), binary(BinaryOp::Mul, Expr::Const(2), Expr::Const(3))));
+        assert_eq!(parse("v3 == 0x10 && i > 0x400").unwrap(),
+                   binary(BinaryOp::And,
+                          binary(BinaryOp::Eq, Expr::Reg(Register::V(3)), Expr::Const(0x10)),
+                          binary(BinaryOp::Gt, Expr::Reg(Register::I), Expr::Const(0x400))));
+
+        assert_eq!(eval("1 + 2 * 3"), 7);
+        assert_eq!(eval("(1 + 2) * 3"), 9);
+        assert_eq!(eval("1 << 2 + 1"), 8);
+        assert_eq!(eval("6 & 3 == 3"), 0);
+        assert_eq!(eval("1 | 6 ^ 3 & 1"), 7);
+        assert_eq!(eval("0 || 1 && 0"), 0);
+        assert_eq!(eval("-1 + 2"), 1);
+        assert_eq!(eval("!0 + 1"), 2);
+    }
+
+    #[test]
+    fn binary_operators_are_left_associative() {
+        assert_eq!(eval("10 - 4 - 3"), 3);
+        assert_eq!(eval("0x100 >> 4 >> 4"), 1);
+    }
+
+    #[test]
+    fn reads_hex_and_decimal_literals() {
+        assert_eq!(parse_number("0x2a4"), Ok(0x2a4));
+        assert_eq!(parse_number("0xFFFF"), Ok(0xFFFF));
+        assert_eq!(parse_number("42"), Ok(42));
+        assert_eq!(parse_number("010"), Ok(10));
+        assert_eq!(eval("0x10 + 10"), 26);
+
+        assert!(parse_number("0x").is_err());
+        assert!(parse_number("0xg1").is_err());
+        assert!(parse_number("65536").is_err());
+        assert!(parse("12ab").is_err());
+    }
+
+    #[test]
+    fn reads_register_names_in_any_case() {
+        assert_eq!(parse_register("v0"), Some(Register::V(0)));
+        assert_eq!(parse_register("vf"), Some(Register::V(0xF)));
+        assert_eq!(parse_register("i"), Some(Register::I));
+        assert_eq!(parse_register("pc"), Some(Register::Pc));
+        assert_eq!(parse_register("sp"), Some(Register::Sp));
+        assert_eq!(parse_register("dt"), Some(Register::Dt));
+        assert_eq!(parse_register("st"), Some(Register::St));
+        assert_eq!(parse_register("vg"), None);
+        assert_eq!(parse_register("v10"), None);
+
+        assert_eq!(parse("VA").unwrap(), Expr::Reg(Register::V(0xA)));
+        assert_eq!(eval("v1 + PC"), 0x220);
+        assert_eq!(eval("mem[i + 0x12]"), 0x12);
+        assert_eq!(parse("foo"), Err("Unknown register `foo`".to_owned()));
+    }
+
+    #[test]
+    fn reports_parse_errors() {
+        assert_eq!(parse(""), Err("Unexpected end of expression".to_owned()));
+        assert_eq!(parse("1 +"), Err("Unexpected end of expression".to_owned()));
+        assert_eq!(parse("(1 + 2"), Err("Expected `)` at the end".to_owned()));
+        assert_eq!(parse("mem 1"), Err("Expected `[`, found `0x1`".to_owned()));
+        assert_eq!(parse("1 2"), Err("Unexpected `0x2` in `1 2`".to_owned()));
+        assert_eq!(parse("v1 $ 2"), Err("Unexpected character `$` in `v1 $ 2`".to_owned()));
+        assert_eq!(parse("* 2"), Err("Unexpected `*`".to_owned()));
+    }
+
+    #[test]
+    fn round_trips_through_display() {
+        for s in &["v3 == 0x10 && i > 0x400", "-(v1 + 2) * ~mem[i]", "!(pc < 0x300) || sp >= 2 << 1"] {
+            let expr = parse(s).unwrap();
+            assert_eq!(parse(&expr.to_string()).unwrap(), expr);
+        }
+    }
+
+    #[test]
+    fn parses_tracepoint_messages() {
+        assert_eq!(parse_format("v3={v3:d} at { i } {{}}").unwrap(), vec![
+            FormatPart::Text("v3=".to_owned()),
+            FormatPart::Value { expr: Expr::Reg(Register::V(3)), decimal: true },
+            FormatPart::Text(" at ".to_owned()),
+            FormatPart::Value { expr: Expr::Reg(Register::I), decimal: false },
+            FormatPart::Text(" {}".to_owned())
+        ]);
+        assert_eq!(parse_format("{~v0}").unwrap(),
+                   vec![FormatPart::Value { expr: Expr::Unary(UnaryOp::BitNot, Box::new(Expr::Reg(Register::V(0)))), decimal: false }]);
+
+        assert_eq!(parse_format("{v3"), Err("Unmatched `{` in `{v3`".to_owned()));
+        assert_eq!(parse_format("v3}"), Err("Unmatched `}` in `v3}`".to_owned()));
+        assert!(parse_format("{v3 +}").is_err());
+    }
+}
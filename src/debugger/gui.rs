@@ -2,9 +2,11 @@ use conrod::{self, widget, Colorable, Positionable, Widget};
 use conrod::backend::glium::glium;
 use conrod::backend::glium::glium::{Surface};
 use std;
-use cli::{Cli, CpuSnapshot};
-use disasm::Disasm;
-use disasm::Opcode;
+use std::fs;
+use cli::Cli;
+use chip8_protocol::{BreakpointInfo, CpuSnapshot, DbgError, Event, RomSource, StepMode, StopReason, WatchAccess};
+use chip8_protocol::disasm::Disasm;
+use chip8_protocol::disasm::Opcode;
 use commands::Commands;
 use std::borrow::Cow;
 use std::str::FromStr;
@@ -67,7 +69,15 @@ pub fn run(mut cli: Cli) {
     let mut ui_state = UIState::default();
 
     let mut events = Vec::new();
-    synchronize_vm_state(&mut cli, &mut ui_state);
+    if let Err(e) = synchronize_vm_state(&mut cli, &mut ui_state) {
+        println!("{}", e);
+    }
+
+    // Wakes up the loop below when the VM pushes an event
+    let events_loop_proxy = events_loop.create_proxy();
+    cli.on_event(move || {
+        let _ = events_loop_proxy.wakeup();
+    });
 
     'render: loop {
         events.clear();
@@ -95,13 +105,22 @@ pub fn run(mut cli: Cli) {
                         } => break 'render,
                         glium::glutin::WindowEvent::KeyboardInput {
                             input: glium::glutin::KeyboardInput {
-                                virtual_keycode: Some(glium::glutin::VirtualKeyCode::F8),
+                                state: glium::glutin::ElementState::Pressed,
+                                virtual_keycode: Some(key),
                                 ..
                             },
                             ..
                         } => {
-                            cli.step();
-                            synchronize_vm_state(&mut cli, &mut ui_state);
+                            let result = match key {
+                                glium::glutin::VirtualKeyCode::F8 => cli.step(StepMode::Instructions(1)),
+                                glium::glutin::VirtualKeyCode::F7 => cli.reverse_step(1),
+                                glium::glutin::VirtualKeyCode::F6 => cli.reverse_continue(),
+                                _ => Ok(())
+                            };
+
+                            if let Err(e) = result {
+                                println!("{}", e);
+                            }
                         },
                         _ => (),
                     }
@@ -119,6 +138,16 @@ pub fn run(mut cli: Cli) {
             set_widgets(ui.set_widgets(), ids, &mut command_text, &mut ui_state, &mut cli);
         }
 
+        let vm_events = cli.events();
+        if !vm_events.is_empty() {
+            for event in vm_events {
+                if let Err(e) = handle_vm_event(event, &mut cli, &mut ui_state) {
+                    println!("{}", e);
+                }
+            }
+            set_widgets(ui.set_widgets(), ids, &mut command_text, &mut ui_state, &mut cli);
+        }
+
         if let Some(primitives) = ui.draw_if_changed() {
             renderer.fill(&display, primitives, &image_map);
             let mut target = display.draw();
@@ -129,16 +158,34 @@ pub fn run(mut cli: Cli) {
     }
 }
 
-fn synchronize_vm_state(cli: &mut Cli, chip8_state: &mut UIState) {
-    let cpu_state = cli.cpu();
-    let code_at_pc = cli.mem(cpu_state.pc, 64);
+fn handle_vm_event(event: Event, cli: &mut Cli, chip8_state: &mut UIState) -> Result<(), DbgError> {
+    match event {
+        Event::Stopped { reason, cpu } => {
+            println!("{}", describe_stop_reason(&reason));
+            show_vm_state(cpu, cli, chip8_state)
+        },
+        Event::Exited => {
+            println!("VM exited");
+            Ok(())
+        }
+    }
+}
+
+fn synchronize_vm_state(cli: &mut Cli, chip8_state: &mut UIState) -> Result<(), DbgError> {
+    let cpu_state = cli.cpu()?;
+    show_vm_state(cpu_state, cli, chip8_state)
+}
+
+fn show_vm_state(cpu_state: CpuSnapshot, cli: &mut Cli, chip8_state: &mut UIState) -> Result<(), DbgError> {
+    let code_at_pc = cli.mem(cpu_state.pc, 64)?;
 
     update_cpu_state_view(&cpu_state, chip8_state);
     update_stack_state_view(&cpu_state, chip8_state);
     update_disasm_view(cpu_state.pc, code_at_pc, chip8_state);
 
     // TEMP
-    update_mem_dump_view(cpu_state.pc, cli.mem(cpu_state.pc, 100), chip8_state)
+    update_mem_dump_view(cpu_state.pc, cli.mem(cpu_state.pc, 100)?, chip8_state);
+    Ok(())
 }
 
 fn set_widgets(ref mut ui: conrod::UiCell, ids: &mut Ids, command_text: &mut String, chip8_state: &mut UIState, cli: &mut Cli) {
@@ -216,30 +263,12 @@ fn set_widgets(ref mut ui: conrod::UiCell, ids: &mut Ids, command_text: &mut Str
         .set(ids.command_textbox, ui) {
         match event {
             widget::text_box::Event::Enter => {
-                match Commands::from_str(command_text) {
-                    Ok(Commands::Cpu) => {
-                        // We'll ignore this command for now, as debugger should always be synchronized
-                        // with VM's state therefore there should be no need for manual CPU state
-                        // retrieval
-                    },
-                    Ok(Commands::Disasm(addr)) => {
-                        update_disasm_view(addr, cli.mem(addr, 100), chip8_state);
-                    },
-                    Ok(Commands::Mem(addr)) => {
-                        update_mem_dump_view(addr, cli.mem(addr, 100), chip8_state);
-                    },
-                    Ok(Commands::Start) => {
-                        cli.start()
-                    },
-                    Ok(Commands::Step) => {
-                        cli.step();
-                        synchronize_vm_state(cli, chip8_state);
+                let result = Commands::from_str(command_text)
+                    .map_err(|e| e.into_owned())
+                    .and_then(|command| execute_command(command, cli, chip8_state).map_err(|e| e.to_string()));
 
-                    }
-                    Ok(Commands::Stop) => {
-                        cli.stop()
-                    },
-                    Err(ref e) => println!("{}", e)
+                if let Err(e) = result {
+                    println!("{}", e);
                 }
             },
             widget::text_box::Event::Update(s) => {
@@ -249,6 +278,198 @@ fn set_widgets(ref mut ui: conrod::UiCell, ids: &mut Ids, command_text: &mut Str
     }
 }
 
+fn execute_command(command: Commands, cli: &mut Cli, chip8_state: &mut UIState) -> Result<(), DbgError> {
+    match command {
+        Commands::Cpu => {
+            // We'll ignore this command for now, as debugger should always be synchronized
+            // with VM's state therefore there should be no need for manual CPU state
+            // retrieval
+        },
+        Commands::Disasm(addr) => {
+            update_disasm_view(addr, cli.mem(addr, 100)?, chip8_state);
+        },
+        Commands::Mem(addr) => {
+            update_mem_dump_view(addr, cli.mem(addr, 100)?, chip8_state);
+        },
+        Commands::Start => {
+            cli.start()?
+        },
+        Commands::Step(count) => {
+            cli.step(StepMode::Instructions(count))?;
+        },
+        Commands::Next => {
+            cli.step(StepMode::Over)?;
+        },
+        Commands::Finish => {
+            cli.step(StepMode::Out)?;
+        },
+        Commands::Until(addr) => {
+            cli.step(StepMode::Until(addr))?;
+        },
+        Commands::Frame => {
+            cli.step(StepMode::Frame)?;
+        },
+        Commands::ReverseStep(count) => {
+            cli.reverse_step(count)?;
+        },
+        Commands::ReverseContinue => {
+            cli.reverse_continue()?;
+        },
+        Commands::History => {
+            let history = cli.history()?;
+
+            if history.budget == 0 {
+                println!("Execution history is disabled");
+            } else {
+                println!("{} instructions can be stepped back ({} of {} KiB used)",
+                         history.instructions, history.size / 1024, history.budget / 1024);
+            }
+        },
+        Commands::Stop => {
+            cli.stop()?
+        },
+        Commands::Reset(mode) => {
+            cli.reset(mode)?;
+            synchronize_vm_state(cli, chip8_state)?;
+        },
+        Commands::LoadRom(path) => {
+            cli.load_rom(RomSource::Path(path.clone()))?;
+            println!("Loaded ROM {}", path);
+            synchronize_vm_state(cli, chip8_state)?;
+        },
+        Commands::UploadRom(path) => {
+            let data = fs::read(&path).map_err(|e| DbgError::Io(format!("Could not read {}: {}", path, e)))?;
+            let size = data.len();
+            cli.load_rom(RomSource::Bytes { name: path.clone(), data })?;
+            println!("Uploaded ROM {} ({} bytes)", path, size);
+            synchronize_vm_state(cli, chip8_state)?;
+        },
+        Commands::Reload => {
+            cli.reload_rom()?;
+            println!("Reloaded ROM");
+            synchronize_vm_state(cli, chip8_state)?;
+        },
+        Commands::Seed(seed) => {
+            cli.reseed(seed)?;
+            synchronize_vm_state(cli, chip8_state)?;
+        },
+        Commands::Font => {
+            let font = cli.font()?;
+
+            match font.big_location {
+                Some(big_location) => println!("Font {}: 0x{:x} ({} bytes), big font: 0x{:x} ({} bytes)",
+                                               font.name, font.location, font.size, big_location, font.big_size),
+                None => println!("Font {}: 0x{:x} ({} bytes), no big font", font.name, font.location, font.size)
+            }
+            update_mem_dump_view(font.location, cli.mem(font.location, font.size)?, chip8_state);
+        },
+        Commands::Status => {
+            let status = cli.status()?;
+
+            match status.stop_reason {
+                _ if status.running => println!("Running"),
+                Some(reason) => println!("{}", describe_stop_reason(&reason)),
+                None => println!("Stopped")
+            }
+            synchronize_vm_state(cli, chip8_state)?;
+        },
+        Commands::Break(addr, condition) => {
+            let breakpoint = cli.add_breakpoint(addr, condition)?;
+            println!("Breakpoint {}", describe_breakpoint(&breakpoint));
+        },
+        Commands::Trace(addr, message, condition) => {
+            let tracepoint = cli.add_tracepoint(addr, message, condition)?;
+            println!("Tracepoint {}", describe_breakpoint(&tracepoint));
+        },
+        Commands::Ignore(id, count) => {
+            cli.ignore_breakpoint(id, count)?;
+            println!("Will ignore next {} hits of breakpoint {}", count, id);
+        },
+        Commands::Delete(id) => {
+            cli.delete_breakpoint(id)?
+        },
+        Commands::Enable(id) => {
+            cli.enable_breakpoint(id, true)?;
+        },
+        Commands::Disable(id) => {
+            cli.enable_breakpoint(id, false)?;
+        },
+        Commands::Watch(start, end, access) => {
+            let watchpoint = cli.add_watchpoint(start, end, access)?;
+            println!("Watchpoint {} on 0x{:x}-0x{:x} ({:?})", watchpoint.id, watchpoint.start, watchpoint.end, watchpoint.access);
+        },
+        Commands::Watchpoints => {
+            for watchpoint in cli.watchpoints()? {
+                println!("{}: 0x{:x}-0x{:x} {:?}{} (hit {} times)", watchpoint.id, watchpoint.start, watchpoint.end,
+                         watchpoint.access, if watchpoint.enabled { "" } else { " [disabled]" }, watchpoint.hits);
+            }
+        },
+        Commands::Set(register, value) => {
+            cli.set_register(register, value)?;
+            synchronize_vm_state(cli, chip8_state)?;
+        },
+        Commands::SetStack(slot, value) => {
+            cli.set_stack(slot, value)?;
+            synchronize_vm_state(cli, chip8_state)?;
+        },
+        Commands::Poke(addr, bytes) => {
+            cli.poke(addr, bytes)?;
+            update_mem_dump_view(addr, cli.mem(addr, 100)?, chip8_state);
+        },
+        Commands::Fill(start, end, value) => {
+            cli.fill(start, end, value)?;
+            update_mem_dump_view(start, cli.mem(start, 100)?, chip8_state);
+        },
+        Commands::Load(addr, path) => {
+            let bytes = fs::read(&path).map_err(|e| DbgError::Io(format!("Could not read {}: {}", path, e)))?;
+            let size = bytes.len();
+            cli.poke(addr, bytes)?;
+            println!("Loaded {} bytes at 0x{:x}", size, addr);
+            update_mem_dump_view(addr, cli.mem(addr, 100)?, chip8_state);
+        },
+        Commands::Breakpoints => {
+            for breakpoint in cli.breakpoints()? {
+                println!("{}{} (hit {} times)", describe_breakpoint(&breakpoint),
+                         if breakpoint.enabled { "" } else { " [disabled]" }, breakpoint.hits);
+            }
+        }
+    };
+
+    Ok(())
+}
+
+fn describe_stop_reason(reason: &StopReason) -> String {
+    match *reason {
+        StopReason::Breakpoint { id, addr } => format!("Stopped at breakpoint {} (0x{:x})", id, addr),
+        StopReason::Watchpoint(ref hit) => format!("Stopped at watchpoint {}: {:04X} at 0x{:x} {} 0x{:x} ({:02X} -> {:02X})",
+                                                  hit.id, hit.opcode, hit.pc,
+                                                  if hit.access == WatchAccess::Read { "read" } else { "wrote" },
+                                                  hit.addr, hit.old, hit.new),
+        StopReason::Fault(ref fault) => format!("Stopped by a fault: {}", fault),
+        StopReason::Step => "Stopped after a step".to_owned(),
+        StopReason::HistoryStart => "Stopped at the start of the execution history".to_owned(),
+        StopReason::Requested => "Stopped".to_owned()
+    }
+}
+
+/// `ID at ADDR`, followed by the tracepoint message, the condition and the ignore count if there are any
+fn describe_breakpoint(breakpoint: &BreakpointInfo) -> String {
+    let mut description = format!("{} at 0x{:x}", breakpoint.id, breakpoint.addr);
+
+    if let Some(ref message) = breakpoint.message {
+        let message: String = message.iter().map(|part| part.to_string()).collect();
+        description.push_str(&format!(" \"{}\"", message));
+    }
+    if let Some(ref condition) = breakpoint.condition {
+        description.push_str(&format!(" if {}", condition));
+    }
+    if breakpoint.ignore_count > 0 {
+        description.push_str(&format!(", ignoring next {} hits", breakpoint.ignore_count));
+    }
+
+    description
+}
+
 fn update_cpu_state_view(cpu_state: &CpuSnapshot, chip8_state: &mut UIState) {
     chip8_state.cpu_status_textbox.clear();
     chip8_state.cpu_status_textbox.push_str(&(format!("PC: 0x{:x}  SP: 0x{:x}  I: 0x{:x}\n\n\
@@ -265,6 +486,13 @@ fn update_cpu_state_view(cpu_state: &CpuSnapshot, chip8_state: &mut UIState) {
                                                       cpu_state.v[3], cpu_state.v[9], cpu_state.v[15],
                                                       cpu_state.v[4], cpu_state.v[10],
                                                       cpu_state.v[5], cpu_state.v[11]).to_owned())[..]);
+
+    chip8_state.cpu_status_textbox.push_str(&format!("\n\nDT: 0x{:x}  ST: 0x{:x}", cpu_state.dt, cpu_state.st));
+
+    match cpu_state.seed {
+        Some(seed) => chip8_state.cpu_status_textbox.push_str(&format!("\n\nRNG seed: {}", seed)),
+        None => chip8_state.cpu_status_textbox.push_str("\n\nRNG: scripted")
+    };
 }
 
 fn update_stack_state_view(cpu_state: &CpuSnapshot, chip8_state: &mut UIState) {
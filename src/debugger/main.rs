@@ -2,18 +2,73 @@
 #[macro_use] extern crate bincode;
 #[macro_use] extern crate serde_derive;
 #[macro_use] extern crate nom;
+extern crate clap;
 extern crate serde;
-extern crate serde_json;
+#[macro_use] extern crate serde_json;
+extern crate chip8_protocol;
+
+use std::process;
+
+use clap::{App, Arg};
 
-mod disasm;
 mod cli;
 mod commands;
+mod dap;
+mod expr;
 mod gui;
 
+const DEFAULT_ADDRESS: &str = "127.0.0.1:9876";
+
 fn main() {
-    let mut cli = cli::Cli::new();
+    let matches = App::new("debugger")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Remote debugger for the CHIP-8 virtual machine")
+        .arg(Arg::with_name("ADDRESS")
+            .help("Address of the VM's debugger server, 127.0.0.1:9876 by default")
+            .index(1))
+        .arg(Arg::with_name("token")
+            .long("token")
+            .value_name("TOKEN")
+            .help("Secret the VM was started with"))
+        .arg(Arg::with_name("dap")
+            .long("dap")
+            .conflicts_with_all(&["ADDRESS", "dap-port"])
+            .help("Runs as a Debug Adapter Protocol server on stdin and stdout instead of the GUI"))
+        .arg(Arg::with_name("dap-port")
+            .long("dap-port")
+            .value_name("PORT")
+            .conflicts_with("ADDRESS")
+            .help("Runs as a Debug Adapter Protocol server on the local port instead of the GUI"))
+        .get_matches();
+
+    if matches.is_present("dap") {
+        dap::run_stdio();
+        return;
+    }
+
+    if let Some(port) = matches.value_of("dap-port") {
+        let result = port.parse::<u16>().map_err(|e| format!("Invalid DAP port {}: {}", port, e)).and_then(dap::serve);
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let address = matches.value_of("ADDRESS").unwrap_or(DEFAULT_ADDRESS);
+    let token = matches.value_of("token").map(String::from);
+
+    let mut cli = match cli::Cli::new(address, token) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("Could not attach to the VM at {}: {}", address, e);
+            process::exit(1);
+        }
+    };
 
     // TODO: for now, lets make debugger stop VM that it is attaching to
-    cli.stop();
+    if let Err(e) = cli.stop() {
+        eprintln!("Could not stop the VM: {}", e);
+    }
     gui::run(cli);
 }
@@ -0,0 +1,193 @@
+//! Finds where two traces of the same ROM stop agreeing, e.g. runs with different quirks.
+//!
+//! Records are compared by address, opcode and effects; cycles are not compared, so traces started with different
+//! triggers still line up. Everything before the first differing record is identical, so the divergence is the
+//! instruction whose effects differ, not merely one that runs on already different state.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::path::Path;
+
+use chip8_protocol::expr::Register;
+
+use format::TraceReader;
+use record::TraceRecord;
+
+/// Way two records at the same position differ
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    Pc(u16, u16),
+    Opcode(u16, u16),
+    /// Value each instruction left in the register, `None` when it did not change it
+    Register(Register, Option<u16>, Option<u16>),
+    /// Value each instruction wrote to the address, `None` when it did not write it
+    Memory(u16, Option<u8>, Option<u8>)
+}
+
+/// First records two traces disagree on
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    /// Position of the records in their traces, counting from 0
+    pub index: usize,
+    /// `None` when the trace ended before the other one
+    pub left: Option<TraceRecord>,
+    pub right: Option<TraceRecord>,
+    /// Empty when one of the traces ended
+    pub differences: Vec<Difference>,
+    /// Records both traces agree on right before the divergence, oldest first
+    pub before: Vec<TraceRecord>,
+    /// Records following the divergence in each trace
+    pub left_after: Vec<TraceRecord>,
+    pub right_after: Vec<TraceRecord>
+}
+
+/// Compares two records, empty when they match
+pub fn compare(left: &TraceRecord, right: &TraceRecord) -> Vec<Difference> {
+    let mut differences = vec![];
+
+    if left.pc != right.pc {
+        differences.push(Difference::Pc(left.pc, right.pc));
+    }
+    if left.opcode != right.opcode {
+        differences.push(Difference::Opcode(left.opcode, right.opcode));
+    }
+
+    let mut registers: Vec<Register> = vec![];
+    for change in left.registers.iter().chain(right.registers.iter()) {
+        if !registers.contains(&change.register) {
+            registers.push(change.register);
+        }
+    }
+    for register in registers {
+        let (old, new) = (left.register(register), right.register(register));
+        if old != new {
+            differences.push(Difference::Register(register, old, new));
+        }
+    }
+
+    let mut addresses: Vec<u16> = left.memory.iter().chain(right.memory.iter()).map(|write| write.addr).collect();
+    addresses.sort();
+    addresses.dedup();
+    for addr in addresses {
+        let written = |record: &TraceRecord| record.memory.iter().rev().find(|write| write.addr == addr).map(|write| write.new);
+        let (old, new) = (written(left), written(right));
+        if old != new {
+            differences.push(Difference::Memory(addr, old, new));
+        }
+    }
+
+    differences
+}
+
+/// Finds the first divergence of two traces, keeping `context` records around it
+pub fn diff<L, R>(left: L, right: R, context: usize) -> Option<Divergence>
+    where L: IntoIterator<Item = TraceRecord>, R: IntoIterator<Item = TraceRecord> {
+    diff_results(left.into_iter().map(Ok), right.into_iter().map(Ok), context).expect("records in memory can not fail to read")
+}
+
+/// Like `diff`, for trace files of either format
+pub fn diff_files(left: &Path, right: &Path, context: usize) -> Result<Option<Divergence>, String> {
+    let open = |path: &Path| TraceReader::open(path).map_err(|e| format!("Could not read trace {:?}: {}", path, e));
+    let (left_reader, right_reader) = (open(left)?, open(right)?);
+
+    diff_results(left_reader.map(|r| r.map_err(|e| format!("{:?}: {}", left, e))),
+                 right_reader.map(|r| r.map_err(|e| format!("{:?}: {}", right, e))),
+                 context)
+}
+
+fn diff_results<L, R>(mut left: L, mut right: R, context: usize) -> Result<Option<Divergence>, String>
+    where L: Iterator<Item = Result<TraceRecord, String>>, R: Iterator<Item = Result<TraceRecord, String>> {
+    let mut before = VecDeque::with_capacity(context + 1);
+    let mut index = 0;
+
+    loop {
+        let (left_record, right_record) = match (left.next().transpose()?, right.next().transpose()?) {
+            (None, None) => return Ok(None),
+            records => records
+        };
+
+        let (left_record, right_record, differences) = match (left_record, right_record) {
+            (Some(l), Some(r)) => {
+                let differences = compare(&l, &r);
+                if differences.is_empty() {
+                    if context > 0 {
+                        if before.len() == context {
+                            before.pop_front();
+                        }
+                        before.push_back(l);
+                    }
+                    index += 1;
+                    continue;
+                }
+                (Some(l), Some(r), differences)
+            },
+            (l, r) => (l, r, vec![])
+        };
+
+        let left_after = left.by_ref().take(context).collect::<Result<Vec<_>, _>>()?;
+        let right_after = right.by_ref().take(context).collect::<Result<Vec<_>, _>>()?;
+
+        return Ok(Some(Divergence {
+            index,
+            left: left_record,
+            right: right_record,
+            differences,
+            before: before.into_iter().collect(),
+            left_after,
+            right_after
+        }));
+    }
+}
+
+fn register_value(register: Register, value: Option<u16>) -> String {
+    match (register, value) {
+        (_, None) => "unchanged".to_owned(),
+        (Register::I, Some(value)) | (Register::Pc, Some(value)) => format!("{:03x}", value),
+        (_, Some(value)) => format!("{:02x}", value)
+    }
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Difference::Pc(left, right) => write!(f, "pc: {:03x} vs {:03x}", left, right),
+            Difference::Opcode(left, right) => write!(f, "opcode: {:04X} vs {:04X}", left, right),
+            Difference::Register(register, left, right) => {
+                write!(f, "{}: {} vs {}", register, register_value(register, left), register_value(register, right))
+            },
+            Difference::Memory(addr, left, right) => {
+                let value = |value: Option<u8>| value.map_or("not written".to_owned(), |value| format!("{:02x}", value));
+                write!(f, "[{:03x}]: {} vs {}", addr, value(left), value(right))
+            }
+        }
+    }
+}
+
+/// Report of the divergence with its context, left trace first
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.left, &self.right) {
+            (Some(left), Some(right)) => {
+                writeln!(f, "Traces diverge at record {} (cycles {} / {})", self.index, left.cycle, right.cycle)?;
+                for difference in &self.differences {
+                    writeln!(f, "  {}", difference)?;
+                }
+            },
+            (Some(_), None) => writeln!(f, "Right trace ends after {} records, left trace goes on", self.index)?,
+            (None, _) => writeln!(f, "Left trace ends after {} records, right trace goes on", self.index)?
+        }
+
+        writeln!(f)?;
+        for record in &self.before {
+            writeln!(f, "  {}", record)?;
+        }
+
+        for &(side, record, after) in &[("<", &self.left, &self.left_after), (">", &self.right, &self.right_after)] {
+            for record in record.iter().chain(after.iter()) {
+                writeln!(f, "{} {}", side, record)?;
+            }
+        }
+
+        Ok(())
+    }
+}
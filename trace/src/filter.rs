@@ -0,0 +1,104 @@
+use std::str::FromStr;
+
+/// Accepts both decimal and `0x` prefixed hexadecimal numbers
+pub fn parse_number(value: &str) -> Result<u64, String> {
+    let parsed = if value.starts_with("0x") || value.starts_with("0X") {
+        u64::from_str_radix(&value[2..], 16)
+    } else {
+        u64::from_str(value)
+    };
+
+    parsed.map_err(|e| format!("'{}' is not a number: {}", value, e))
+}
+
+fn parse_address(value: &str) -> Result<u16, String> {
+    let addr = parse_number(value)?;
+
+    if addr > 0xFFFF {
+        return Err(format!("{:#x} is not an address", addr));
+    }
+    Ok(addr as u16)
+}
+
+/// Event starting or stopping a recording
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    /// PC reaching the address
+    Pc(u16),
+    /// The VM having executed that many instructions
+    Cycle(u64)
+}
+
+impl Trigger {
+    /// Whether the trigger fires before executing the instruction at `pc`, `cycle` instructions into the run
+    pub fn fires(&self, cycle: u64, pc: u16) -> bool {
+        match *self {
+            Trigger::Pc(addr) => pc == addr,
+            Trigger::Cycle(at) => cycle >= at
+        }
+    }
+}
+
+/// `cycle=N`, `pc=ADDR` or just `ADDR`
+impl FromStr for Trigger {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("cycle=") {
+            parse_number(&s["cycle=".len()..]).map(Trigger::Cycle)
+        } else if s.starts_with("pc=") {
+            parse_address(&s["pc=".len()..]).map(Trigger::Pc)
+        } else {
+            parse_address(s).map(Trigger::Pc)
+        }
+    }
+}
+
+/// `start..=end`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AddressRange {
+    pub start: u16,
+    pub end: u16
+}
+
+impl AddressRange {
+    pub fn contains(&self, addr: u16) -> bool {
+        self.start <= addr && addr <= self.end
+    }
+}
+
+/// `START-END`, or a single address
+impl FromStr for AddressRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '-');
+        let start = parse_address(parts.next().unwrap_or_default())?;
+        let end = match parts.next() {
+            Some(end) => parse_address(end)?,
+            None => start
+        };
+
+        if start > end {
+            return Err(format!("range {:#x}-{:#x} is empty", start, end));
+        }
+        Ok(AddressRange { start, end })
+    }
+}
+
+/// Which instructions get recorded
+#[derive(Debug, Clone, Default)]
+pub struct TraceFilter {
+    /// Instructions outside of all ranges are skipped, none means everywhere
+    pub ranges: Vec<AddressRange>,
+    /// Recording starts once it fires, right away without one
+    pub start: Option<Trigger>,
+    /// Recording ends for good once it fires
+    pub stop: Option<Trigger>
+}
+
+impl TraceFilter {
+    pub fn matches_pc(&self, pc: u16) -> bool {
+        self.ranges.is_empty() || self.ranges.iter().any(|range| range.contains(pc))
+    }
+}
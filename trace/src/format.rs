@@ -0,0 +1,160 @@
+//! Trace files. Binary traces start with `MAGIC` and a version byte followed by bincode-encoded records,
+//! JSON traces hold one record per line.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use bincode;
+use serde_json;
+
+use record::TraceRecord;
+
+pub const MAGIC: &[u8] = b"CH8TRACE";
+/// Has to be bumped on every incompatible change of `TraceRecord`
+pub const VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    /// Compact, for long runs
+    Binary,
+    /// Readable by other tools
+    JsonLines
+}
+
+impl TraceFormat {
+    /// `.jsonl` and `.json` files hold JSON lines, anything else binary records
+    pub fn from_path(path: &Path) -> TraceFormat {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("jsonl") | Some("json") => TraceFormat::JsonLines,
+            _ => TraceFormat::Binary
+        }
+    }
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "binary" => Ok(TraceFormat::Binary),
+            "json" => Ok(TraceFormat::JsonLines),
+            _ => Err(format!("unknown trace format '{}', expected binary or json", s))
+        }
+    }
+}
+
+fn invalid_data<E: ToString>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+pub struct TraceWriter {
+    output: BufWriter<File>,
+    format: TraceFormat
+}
+
+impl TraceWriter {
+    pub fn create(path: &Path, format: TraceFormat) -> io::Result<TraceWriter> {
+        let mut output = BufWriter::new(File::create(path)?);
+
+        if format == TraceFormat::Binary {
+            output.write_all(MAGIC)?;
+            output.write_all(&[VERSION])?;
+        }
+
+        Ok(TraceWriter { output, format })
+    }
+
+    pub fn write(&mut self, record: &TraceRecord) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Binary => bincode::serialize_into(&mut self.output, record).map_err(invalid_data),
+            TraceFormat::JsonLines => {
+                serde_json::to_writer(&mut self.output, record)?;
+                self.output.write_all(b"\n")
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Reads the records of a trace in either format
+pub struct TraceReader {
+    input: BufReader<File>,
+    format: TraceFormat,
+    /// Records read so far, for error messages
+    position: usize
+}
+
+impl TraceReader {
+    pub fn open(path: &Path) -> io::Result<TraceReader> {
+        let mut input = BufReader::new(File::open(path)?);
+
+        let binary = input.fill_buf()?.starts_with(MAGIC);
+        let format = if binary {
+            input.consume(MAGIC.len());
+
+            let mut version = [0u8; 1];
+            input.read_exact(&mut version)?;
+            if version[0] != VERSION {
+                return Err(invalid_data(format!("trace format version {} is not supported (expected {})", version[0], VERSION)));
+            }
+            TraceFormat::Binary
+        } else {
+            TraceFormat::JsonLines
+        };
+
+        Ok(TraceReader { input, format, position: 0 })
+    }
+
+    pub fn format(&self) -> TraceFormat {
+        self.format
+    }
+
+    /// Reads the whole trace, failing on the first malformed record
+    pub fn read_all(path: &Path) -> Result<Vec<TraceRecord>, String> {
+        let reader = TraceReader::open(path).map_err(|e| format!("Could not read trace {:?}: {}", path, e))?;
+        reader.collect::<Result<Vec<_>, _>>().map_err(|e| format!("Could not read trace {:?}: {}", path, e))
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<TraceRecord>> {
+        match self.format {
+            TraceFormat::Binary => {
+                if self.input.fill_buf()?.is_empty() {
+                    return Ok(None);
+                }
+                bincode::deserialize_from(&mut self.input).map(Some).map_err(invalid_data)
+            },
+            TraceFormat::JsonLines => {
+                let mut line = String::new();
+                loop {
+                    line.clear();
+                    if self.input.read_line(&mut line)? == 0 {
+                        return Ok(None);
+                    }
+                    if !line.trim().is_empty() {
+                        return serde_json::from_str(&line).map(Some).map_err(invalid_data);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for TraceReader {
+    type Item = Result<TraceRecord, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_record() {
+            Ok(Some(record)) => {
+                self.position += 1;
+                Some(Ok(record))
+            },
+            Ok(None) => None,
+            Err(e) => Some(Err(format!("record {}: {}", self.position + 1, e)))
+        }
+    }
+}
@@ -0,0 +1,33 @@
+//! Execution traces of the CHIP-8 VM.
+//!
+//! A trace holds one `TraceRecord` per executed instruction: its cycle, address, opcode and mnemonic along with the
+//! registers and memory it changed. Traces are written either as compact bincode records behind a short header or
+//! as JSON lines (see `format`); `TraceReader` tells them apart by the header. A `TraceFilter` limits recording to
+//! address ranges and to the instructions between a start and a stop trigger.
+//!
+//! `diff` finds the first instruction two traces disagree on, e.g. to check in a test that a change to the VM
+//! keeps a ROM's behavior:
+//!
+//! ```ignore
+//! if let Some(divergence) = chip8_trace::diff_files(Path::new("before.trace"), Path::new("after.trace"), 5)? {
+//!     panic!("{}", divergence);
+//! }
+//! ```
+
+#[macro_use] extern crate serde_derive;
+extern crate serde;
+extern crate serde_json;
+extern crate bincode;
+extern crate chip8_protocol;
+
+pub mod diff;
+mod filter;
+pub mod format;
+mod record;
+mod recorder;
+
+pub use diff::{diff, diff_files, Difference, Divergence};
+pub use filter::{parse_number, AddressRange, TraceFilter, Trigger};
+pub use format::{TraceFormat, TraceReader, TraceWriter};
+pub use record::{MemoryWrite, RegisterChange, TraceRecord};
+pub use recorder::TraceRecorder;
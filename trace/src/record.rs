@@ -0,0 +1,105 @@
+use std::fmt;
+
+use chip8_protocol::CpuSnapshot;
+use chip8_protocol::disasm::Disasm;
+use chip8_protocol::expr::Register;
+
+/// Register an instruction changed; PC is left out since nearly every instruction moves it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct RegisterChange {
+    pub register: Register,
+    pub old: u16,
+    pub new: u16
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct MemoryWrite {
+    pub addr: u16,
+    pub old: u8,
+    pub new: u8
+}
+
+/// One executed instruction
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TraceRecord {
+    /// Instructions the VM executed before this one
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub mnemonic: String,
+    pub registers: Vec<RegisterChange>,
+    /// Bytes the instruction wrote, in write order
+    pub memory: Vec<MemoryWrite>
+}
+
+impl TraceRecord {
+    /// Record of `opcode` executed at `pc`, given the CPU state before and after it
+    pub fn new(cycle: u64, pc: u16, opcode: u16, before: &CpuSnapshot, after: &CpuSnapshot, memory: Vec<MemoryWrite>) -> TraceRecord {
+        TraceRecord {
+            cycle,
+            pc,
+            opcode,
+            mnemonic: Disasm::opcode(opcode).repr(),
+            registers: register_changes(before, after),
+            memory
+        }
+    }
+
+    /// Whether the instruction wrote to `start..=end`
+    pub fn writes(&self, start: u16, end: u16) -> bool {
+        self.memory.iter().any(|write| start <= write.addr && write.addr <= end)
+    }
+
+    /// Value the instruction left in the register, if it changed it
+    pub fn register(&self, register: Register) -> Option<u16> {
+        self.registers.iter().find(|change| change.register == register).map(|change| change.new)
+    }
+}
+
+fn register_changes(before: &CpuSnapshot, after: &CpuSnapshot) -> Vec<RegisterChange> {
+    let mut registers = vec![];
+
+    for x in 0..16 {
+        registers.push((Register::V(x as u8), before.v[x] as u16, after.v[x] as u16));
+    }
+    registers.push((Register::I, before.i, after.i));
+    registers.push((Register::Sp, before.sp as u16, after.sp as u16));
+    registers.push((Register::Dt, before.dt as u16, after.dt as u16));
+    registers.push((Register::St, before.st as u16, after.st as u16));
+
+    registers.into_iter()
+        .filter(|&(_, old, new)| old != new)
+        .map(|(register, old, new)| RegisterChange { register, old, new })
+        .collect()
+}
+
+impl fmt::Display for RegisterChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.register {
+            Register::I | Register::Pc => write!(f, "{}: {:03x} -> {:03x}", self.register, self.old, self.new),
+            _ => write!(f, "{}: {:02x} -> {:02x}", self.register, self.old, self.new)
+        }
+    }
+}
+
+impl fmt::Display for MemoryWrite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{:03x}]: {:02x} -> {:02x}", self.addr, self.old, self.new)
+    }
+}
+
+/// `CYCLE PC OPCODE MNEMONIC` followed by the changes
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut line = format!("{:>8}  {:03x}  {:04X}  {:<20}", self.cycle, self.pc, self.opcode, self.mnemonic);
+
+        for change in &self.registers {
+            line.push_str(&format!("  {}", change));
+        }
+        for write in &self.memory {
+            line.push_str(&format!("  {}", write));
+        }
+
+        write!(f, "{}", line.trim_end())
+    }
+}
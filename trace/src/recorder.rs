@@ -0,0 +1,53 @@
+use std::io;
+
+use filter::TraceFilter;
+use format::TraceWriter;
+use record::TraceRecord;
+
+#[derive(Debug, PartialEq)]
+enum State {
+    /// Waiting for the start trigger
+    Armed,
+    Recording,
+    /// The stop trigger fired
+    Done
+}
+
+/// Writes the records of the instructions selected by a filter
+pub struct TraceRecorder {
+    writer: TraceWriter,
+    filter: TraceFilter,
+    state: State
+}
+
+impl TraceRecorder {
+    pub fn new(writer: TraceWriter, filter: TraceFilter) -> TraceRecorder {
+        let state = if filter.start.is_some() { State::Armed } else { State::Recording };
+        TraceRecorder { writer, filter, state }
+    }
+
+    /// Has to be called before executing each instruction, returns whether it gets recorded
+    pub fn wants(&mut self, cycle: u64, pc: u16) -> bool {
+        if self.state == State::Armed && self.filter.start.map_or(true, |start| start.fires(cycle, pc)) {
+            self.state = State::Recording;
+        }
+
+        if self.state == State::Recording && self.filter.stop.map_or(false, |stop| stop.fires(cycle, pc)) {
+            self.state = State::Done;
+        }
+
+        self.state == State::Recording && self.filter.matches_pc(pc)
+    }
+
+    pub fn record(&mut self, record: &TraceRecord) -> io::Result<()> {
+        self.writer.write(record)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
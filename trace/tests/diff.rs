@@ -0,0 +1,124 @@
+extern crate chip8_protocol;
+extern crate chip8_trace;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use chip8_protocol::expr::Register;
+use chip8_trace::{diff, diff_files, Difference, MemoryWrite, RegisterChange, TraceFormat, TraceRecord, TraceWriter};
+
+fn record(cycle: u64, pc: u16, opcode: u16) -> TraceRecord {
+    TraceRecord { cycle, pc, opcode, mnemonic: format!("{:04X}", opcode), registers: vec![], memory: vec![] }
+}
+
+fn set(mut record: TraceRecord, register: Register, old: u16, new: u16) -> TraceRecord {
+    record.registers.push(RegisterChange { register, old, new });
+    record
+}
+
+fn write(mut record: TraceRecord, addr: u16, old: u8, new: u8) -> TraceRecord {
+    record.memory.push(MemoryWrite { addr, old, new });
+    record
+}
+
+/// Ten instructions counting V0 up, storing it at 0x300 every other one
+fn run() -> Vec<TraceRecord> {
+    (0..10u16).map(|n| {
+        let pc = 0x200 + 2 * n;
+        if n % 2 == 0 {
+            set(record(n as u64, pc, 0x7001), Register::V(0), n, n + 1)
+        } else {
+            write(record(n as u64, pc, 0xF055), 0x300, n as u8 - 1, n as u8)
+        }
+    }).collect()
+}
+
+fn pcs(records: &[TraceRecord]) -> Vec<u16> {
+    records.iter().map(|record| record.pc).collect()
+}
+
+#[test]
+fn identical_traces_do_not_diverge() {
+    assert!(diff(run(), run(), 3).is_none());
+}
+
+#[test]
+fn reports_the_first_differing_register() {
+    let mut right = run();
+    right[4] = set(record(4, 0x208, 0x7001), Register::V(0), 4, 6);
+    right[8] = set(record(8, 0x210, 0x7001), Register::V(0), 8, 10);
+
+    let divergence = diff(run(), right, 3).unwrap();
+
+    assert_eq!(divergence.index, 4);
+    assert_eq!(divergence.differences, vec![Difference::Register(Register::V(0), Some(5), Some(6))]);
+    assert_eq!(divergence.left.unwrap().pc, 0x208);
+    assert_eq!(divergence.right.unwrap().pc, 0x208);
+    assert_eq!(pcs(&divergence.before), vec![0x202, 0x204, 0x206]);
+    assert_eq!(pcs(&divergence.left_after), vec![0x20a, 0x20c, 0x20e]);
+    assert_eq!(pcs(&divergence.right_after), vec![0x20a, 0x20c, 0x20e]);
+}
+
+#[test]
+fn reports_memory_writes_only_one_side_did() {
+    let mut right = run();
+    right[1].memory.clear();
+
+    let divergence = diff(run(), right, 2).unwrap();
+
+    assert_eq!(divergence.index, 1);
+    assert_eq!(divergence.differences, vec![Difference::Memory(0x300, Some(1), None)]);
+    assert_eq!(pcs(&divergence.before), vec![0x200]);
+    assert_eq!(pcs(&divergence.left_after), vec![0x204, 0x206]);
+}
+
+#[test]
+fn cycles_are_not_compared() {
+    let shifted: Vec<TraceRecord> = run().into_iter().map(|mut record| { record.cycle += 1000; record }).collect();
+
+    assert!(diff(run(), shifted, 0).is_none());
+}
+
+#[test]
+fn reports_a_trace_ending_early() {
+    let short: Vec<TraceRecord> = run().into_iter().take(6).collect();
+
+    let divergence = diff(short, run(), 2).unwrap();
+
+    assert_eq!(divergence.index, 6);
+    assert!(divergence.left.is_none());
+    assert_eq!(divergence.right.unwrap().pc, 0x20c);
+    assert!(divergence.differences.is_empty());
+    assert_eq!(pcs(&divergence.before), vec![0x208, 0x20a]);
+    assert!(divergence.left_after.is_empty());
+    assert_eq!(pcs(&divergence.right_after), vec![0x20e, 0x210]);
+}
+
+#[test]
+fn compares_trace_files_of_different_formats() {
+    let dir = env::temp_dir().join(format!("chip8_trace_diff_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let save = |name: &str, format: TraceFormat, records: &[TraceRecord]| -> PathBuf {
+        let path = dir.join(name);
+        let mut writer = TraceWriter::create(&path, format).unwrap();
+        for record in records {
+            writer.write(record).unwrap();
+        }
+        writer.flush().unwrap();
+        path
+    };
+
+    let mut right = run();
+    right[7].opcode = 0xF155;
+    let left_path = save("left.bin", TraceFormat::Binary, &run());
+    let right_path = save("right.jsonl", TraceFormat::JsonLines, &right);
+
+    let divergence = diff_files(&left_path, &right_path, 0).unwrap().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(divergence.index, 7);
+    assert_eq!(divergence.differences, vec![Difference::Opcode(0xF055, 0xF155)]);
+    assert!(divergence.before.is_empty());
+}